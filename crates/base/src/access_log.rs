@@ -0,0 +1,325 @@
+// Writes a line for every request the server receives, in either JSON lines or
+// the combined log format.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Instant;
+
+use anyhow::{bail, Context};
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use deno_core::serde_json::json;
+use futures_util::{Stream, StreamExt};
+use http_v02::{header, HeaderMap, Version};
+use hyper_v014::body::HttpBody;
+use hyper_v014::{Body, Request, Response};
+use log::error;
+use sb_core::SharedMetricSource;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+// NOTE: Lines that don't fit in the queue are dropped (and counted), so a slow
+// target can't make the memory grow without bound.
+const QUEUE_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessLogFormat {
+    /// One JSON object per line.
+    Json,
+    /// The combined log format, followed by the request body size, the
+    /// latency in milliseconds, the scheme and the request ID.
+    Combined,
+}
+
+impl FromStr for AccessLogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "combined" => Ok(Self::Combined),
+            _ => bail!("expected `json` or `combined`, got `{}`", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    pub format: AccessLogFormat,
+    pub target: AccessLogTarget,
+    /// Whether the query string is logged along with the path. It is left out
+    /// by default, as it often carries tokens and other secrets.
+    pub include_query: bool,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLogger {
+    format: AccessLogFormat,
+    include_query: bool,
+    tx: mpsc::Sender<String>,
+    metric_src: SharedMetricSource,
+}
+
+impl AccessLogger {
+    /// Opens the target and spawns the task that writes to it. The task ends
+    /// once every logger has been dropped and the pending lines are written.
+    pub async fn spawn(
+        config: &AccessLogConfig,
+        metric_src: SharedMetricSource,
+    ) -> Result<Self, anyhow::Error> {
+        let writer: Pin<Box<dyn AsyncWrite + Send>> = match &config.target {
+            AccessLogTarget::Stdout => Box::pin(tokio::io::stdout()),
+            AccessLogTarget::File(path) => Box::pin(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .with_context(|| {
+                        format!("can't open the access log file: {}", path.display())
+                    })?,
+            ),
+        };
+
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+
+        tokio::spawn(write_lines(writer, rx));
+
+        Ok(Self {
+            format: config.format,
+            include_query: config.include_query,
+            tx,
+            metric_src,
+        })
+    }
+
+    /// Starts a record for the request. The record is written when it is
+    /// dropped, so it covers requests that never got a response as well.
+    pub fn record(
+        &self,
+        req: &mut Request<Body>,
+        maybe_client_addr: Option<SocketAddr>,
+        tls: bool,
+        request_id: &str,
+    ) -> AccessLogRecord {
+        let bytes_in = Arc::new(AtomicU64::new(0));
+
+        if !req.body().is_end_stream() {
+            let body = std::mem::take(req.body_mut());
+
+            *req.body_mut() = Body::wrap_stream(CountingBody {
+                inner: body,
+                count: bytes_in.clone(),
+            });
+        }
+
+        AccessLogRecord {
+            logger: self.clone(),
+            received_at: Utc::now(),
+            started_at: Instant::now(),
+            method: req.method().to_string(),
+            path: if self.include_query {
+                req.uri()
+                    .path_and_query()
+                    .map_or_else(|| req.uri().path(), |it| it.as_str())
+                    .to_string()
+            } else {
+                req.uri().path().to_string()
+            },
+            version: req.version(),
+            referer: get_header(req.headers(), header::REFERER),
+            user_agent: get_header(req.headers(), header::USER_AGENT),
+            maybe_client_addr,
+            tls,
+            request_id: request_id.to_string(),
+            status: None,
+            bytes_in,
+            bytes_out: 0,
+        }
+    }
+}
+
+pub(crate) struct AccessLogRecord {
+    logger: AccessLogger,
+    received_at: DateTime<Utc>,
+    started_at: Instant,
+    method: String,
+    path: String,
+    version: Version,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    maybe_client_addr: Option<SocketAddr>,
+    tls: bool,
+    request_id: String,
+    status: Option<u16>,
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: u64,
+}
+
+impl AccessLogRecord {
+    /// Ties the record to the response, so that it is written once the
+    /// response body has been sent or dropped.
+    pub fn attach(mut self, res: Response<Body>) -> Response<Body> {
+        self.status = Some(res.status().as_u16());
+
+        let (parts, body) = res.into_parts();
+
+        Response::from_parts(
+            parts,
+            Body::wrap_stream(RecordingBody {
+                inner: body,
+                record: Some(self),
+            }),
+        )
+    }
+
+    fn to_line(&self) -> String {
+        let latency_ms = self.started_at.elapsed().as_millis();
+        let bytes_in = self.bytes_in.load(Ordering::Relaxed);
+
+        match self.logger.format {
+            AccessLogFormat::Json => json!({
+                "time": self.received_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+                "method": self.method,
+                "path": self.path,
+                "protocol": format!("{:?}", self.version),
+                "status": self.status,
+                "bytesIn": bytes_in,
+                "bytesOut": self.bytes_out,
+                "latencyMs": latency_ms,
+                "clientAddr": self.maybe_client_addr.map(|it| it.to_string()),
+                "tls": self.tls,
+                "requestId": self.request_id,
+                "referer": self.referer,
+                "userAgent": self.user_agent,
+            })
+            .to_string(),
+
+            AccessLogFormat::Combined => format!(
+                "{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\" {} {} {} {}",
+                self.maybe_client_addr
+                    .map(|it| it.ip().to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                self.received_at.format("%d/%b/%Y:%H:%M:%S %z"),
+                escape(&self.method),
+                escape(&self.path),
+                self.version,
+                self.status
+                    .map(|it| it.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                self.bytes_out,
+                escape(self.referer.as_deref().unwrap_or("-")),
+                escape(self.user_agent.as_deref().unwrap_or("-")),
+                bytes_in,
+                latency_ms,
+                if self.tls { "https" } else { "http" },
+                self.request_id,
+            ),
+        }
+    }
+}
+
+impl Drop for AccessLogRecord {
+    fn drop(&mut self) {
+        if let Err(mpsc::error::TrySendError::Full(_)) = self.logger.tx.try_send(self.to_line()) {
+            self.logger.metric_src.incl_dropped_access_log_records();
+        }
+    }
+}
+
+struct CountingBody {
+    inner: Body,
+    count: Arc<AtomicU64>,
+}
+
+impl Stream for CountingBody {
+    type Item = Result<Bytes, hyper_v014::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.count.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+
+        poll
+    }
+}
+
+struct RecordingBody {
+    inner: Body,
+    record: Option<AccessLogRecord>,
+}
+
+impl Stream for RecordingBody {
+    type Item = Result<Bytes, hyper_v014::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(record) = self.record.as_mut() {
+                    record.bytes_out += chunk.len() as u64;
+                }
+            }
+
+            // the record is written as soon as the body ends, rather than when
+            // the connection lets go of it.
+            Poll::Ready(_) => drop(self.record.take()),
+            Poll::Pending => {}
+        }
+
+        poll
+    }
+}
+
+async fn write_lines(mut writer: Pin<Box<dyn AsyncWrite + Send>>, mut rx: mpsc::Receiver<String>) {
+    while let Some(line) = rx.recv().await {
+        let mut buf = line.into_bytes();
+
+        buf.push(b'\n');
+
+        // write whatever has piled up in one go.
+        while let Ok(line) = rx.try_recv() {
+            buf.extend_from_slice(line.as_bytes());
+            buf.push(b'\n');
+        }
+
+        if let Err(err) = async {
+            writer.write_all(&buf).await?;
+            writer.flush().await
+        }
+        .await
+        {
+            error!("failed to write the access log: {}", err);
+        }
+    }
+}
+
+fn get_header(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .map(|it| String::from_utf8_lossy(it.as_bytes()).into_owned())
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
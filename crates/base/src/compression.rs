@@ -0,0 +1,315 @@
+// Compresses response bodies on the fly, with whichever content coding the
+// client prefers among the ones the server is configured with.
+
+use std::io;
+use std::str::FromStr;
+
+use anyhow::bail;
+use async_compression::tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder};
+use futures_util::TryStreamExt;
+use http_v02::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use hyper_v014::body::HttpBody;
+use hyper_v014::{Body, Response};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Content types that are compressed unless others are configured. A type
+/// ending with `/*` matches any subtype.
+const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+];
+
+/// Responses smaller than this are not compressed unless another minimum is
+/// configured, as the result wouldn't be much smaller.
+pub const DEFAULT_MIN_SIZE: u64 = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentCoding {
+    Brotli,
+    Zstd,
+    Gzip,
+}
+
+impl ContentCoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Brotli => "br",
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+}
+
+impl FromStr for ContentCoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "br" => Ok(Self::Brotli),
+            "zstd" => Ok(Self::Zstd),
+            "gzip" => Ok(Self::Gzip),
+            _ => bail!("expected `br`, `zstd` or `gzip`, got `{}`", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Codings the server may use, the preferred one first. It only matters
+    /// when the client has no preference among them.
+    pub codings: Vec<ContentCoding>,
+    /// Responses that are known to be smaller than this are sent as-is.
+    pub min_size: u64,
+    /// Types of the responses that are compressed. A type ending with `/*`
+    /// matches any subtype.
+    pub content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codings: vec![
+                ContentCoding::Brotli,
+                ContentCoding::Zstd,
+                ContentCoding::Gzip,
+            ],
+            min_size: DEFAULT_MIN_SIZE,
+            content_types: DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|it| it.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Compressor {
+    config: CompressionConfig,
+}
+
+impl Compressor {
+    pub fn new(mut config: CompressionConfig) -> Self {
+        for it in config.content_types.iter_mut() {
+            it.make_ascii_lowercase();
+        }
+
+        Self { config }
+    }
+
+    /// Picks the coding for the response to a request, based on its
+    /// `Accept-Encoding` header. Returns `None` if the client accepts none of
+    /// the configured codings.
+    pub fn negotiate(&self, method: &Method, headers: &HeaderMap) -> Option<ContentCoding> {
+        if method == Method::HEAD {
+            return None;
+        }
+
+        let accepted = headers
+            .get_all(header::ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|it| it.to_str().ok())
+            .flat_map(|it| it.split(','))
+            .filter_map(parse_accept_encoding)
+            .collect::<Vec<_>>();
+
+        let quality_of = |coding: ContentCoding| {
+            accepted
+                .iter()
+                .find(|(name, _)| name == coding.as_str())
+                .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+                .map(|(_, quality)| *quality)
+                .unwrap_or_default()
+        };
+
+        let mut maybe_best = None::<(ContentCoding, f32)>;
+
+        for coding in self.config.codings.iter().copied() {
+            let quality = quality_of(coding);
+
+            if quality <= 0.0 {
+                continue;
+            }
+
+            // ties go to the coding configured first.
+            if let Some((_, best)) = maybe_best {
+                if quality <= best {
+                    continue;
+                }
+            }
+
+            maybe_best = Some((coding, quality));
+        }
+
+        maybe_best.map(|(coding, _)| coding)
+    }
+
+    /// Compresses the body of the response with `maybe_coding`, if the
+    /// response is worth compressing at all. The body is encoded as it is
+    /// streamed, never buffered as a whole.
+    pub fn compress(
+        &self,
+        maybe_coding: Option<ContentCoding>,
+        mut res: Response<Body>,
+    ) -> Response<Body> {
+        if !self.is_compressible(&res) {
+            return res;
+        }
+
+        // NOTE: Whether the response gets compressed depends on the request
+        // from here on, so caches must know about it even when it doesn't.
+        add_vary_accept_encoding(res.headers_mut());
+
+        let Some(coding) = maybe_coding else {
+            return res;
+        };
+
+        let (mut parts, body) = res.into_parts();
+
+        parts.headers.remove(header::CONTENT_LENGTH);
+        parts.headers.remove(header::ACCEPT_RANGES);
+        parts.headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(coding.as_str()),
+        );
+
+        // the compressed representation is no longer byte-for-byte the one
+        // a strong validator was computed for.
+        if let Some(etag) = parts.headers.get(header::ETAG) {
+            if !etag.as_bytes().starts_with(b"W/") {
+                let mut weak = b"W/".to_vec();
+
+                weak.extend_from_slice(etag.as_bytes());
+
+                if let Ok(value) = HeaderValue::from_bytes(&weak) {
+                    parts.headers.insert(header::ETAG, value);
+                }
+            }
+        }
+
+        Response::from_parts(parts, encode(coding, body))
+    }
+
+    fn is_compressible(&self, res: &Response<Body>) -> bool {
+        let status = res.status();
+        let headers = res.headers();
+
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::PARTIAL_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+        {
+            return false;
+        }
+
+        if headers.contains_key(header::CONTENT_ENCODING)
+            || headers.contains_key(header::CONTENT_RANGE)
+            || header_has_token(headers, header::CACHE_CONTROL, "no-transform")
+            || header_has_token(headers, header::CONNECTION, "upgrade")
+        {
+            return false;
+        }
+
+        let Some(content_type) = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.split(';').next())
+            .map(|it| it.trim().to_ascii_lowercase())
+        else {
+            return false;
+        };
+
+        // NOTE: The encoders only emit output once enough input has piled up,
+        // which would hold events back indefinitely.
+        if content_type == "text/event-stream" {
+            return false;
+        }
+
+        let allowed = self
+            .config
+            .content_types
+            .iter()
+            .any(|it| match it.strip_suffix("/*") {
+                Some(ty) => content_type.split_once('/').is_some_and(|(it, _)| it == ty),
+                None => *it == content_type,
+            });
+
+        if !allowed {
+            return false;
+        }
+
+        let maybe_len = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.parse::<u64>().ok())
+            .or_else(|| res.body().size_hint().exact());
+
+        match maybe_len {
+            Some(len) => len >= self.config.min_size,
+            // a body of unknown length is streamed, and is likely worth it.
+            None => true,
+        }
+    }
+}
+
+fn encode(coding: ContentCoding, body: Body) -> Body {
+    let reader = StreamReader::new(TryStreamExt::map_err(body, io::Error::other));
+
+    match coding {
+        ContentCoding::Brotli => Body::wrap_stream(ReaderStream::new(BrotliEncoder::new(reader))),
+        ContentCoding::Zstd => Body::wrap_stream(ReaderStream::new(ZstdEncoder::new(reader))),
+        ContentCoding::Gzip => Body::wrap_stream(ReaderStream::new(GzipEncoder::new(reader))),
+    }
+}
+
+/// Parses an element of `Accept-Encoding` into the coding and its quality.
+fn parse_accept_encoding(s: &str) -> Option<(String, f32)> {
+    let mut params = s.split(';');
+    let coding = params.next()?.trim().to_ascii_lowercase();
+
+    if coding.is_empty() {
+        return None;
+    }
+
+    let mut quality = 1.0;
+
+    for param in params {
+        if let Some((key, value)) = param.split_once('=') {
+            if key.trim().eq_ignore_ascii_case("q") {
+                quality = value.trim().parse::<f32>().ok()?;
+            }
+        }
+    }
+
+    // `x-gzip` is an alias that older clients still send.
+    if coding == "x-gzip" {
+        return Some(("gzip".to_string(), quality));
+    }
+
+    Some((coding, quality))
+}
+
+fn header_has_token(headers: &HeaderMap, name: header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|it| it.to_str().ok())
+        .flat_map(|it| it.split(','))
+        .any(|it| it.trim().eq_ignore_ascii_case(token))
+}
+
+fn add_vary_accept_encoding(headers: &mut HeaderMap) {
+    if header_has_token(headers, header::VARY, "accept-encoding")
+        || header_has_token(headers, header::VARY, "*")
+    {
+        return;
+    }
+
+    headers.append(
+        header::VARY,
+        HeaderValue::from_static(header::ACCEPT_ENCODING.as_str()),
+    );
+}
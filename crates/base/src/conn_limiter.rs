@@ -0,0 +1,174 @@
+// Caps the number of connections the server holds at the same time, both in
+// total and per client address.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use log::debug;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+type ConnCounts = Arc<Mutex<HashMap<IpAddr, usize>>>;
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnLimiter {
+    maybe_global: Option<Arc<Semaphore>>,
+    maybe_per_ip: Option<PerIpLimit>,
+}
+
+#[derive(Debug, Clone)]
+struct PerIpLimit {
+    max: usize,
+    conns: ConnCounts,
+}
+
+/// A slot reserved for a connection that is yet to be accepted.
+#[derive(Default)]
+pub(crate) struct ConnPermit {
+    _inner: Option<OwnedSemaphorePermit>,
+}
+
+/// Keeps a connection counted against the limits until dropped.
+pub(crate) struct ConnGuard {
+    _permit: ConnPermit,
+    maybe_ip: Option<(IpAddr, ConnCounts)>,
+}
+
+impl Drop for ConnGuard {
+    fn drop(&mut self) {
+        let Some((ip, conns)) = self.maybe_ip.take() else {
+            return;
+        };
+
+        let mut conns = conns.lock().unwrap();
+
+        if let Some(count) = conns.get_mut(&ip) {
+            *count -= 1;
+
+            if *count == 0 {
+                conns.remove(&ip);
+            }
+        }
+    }
+}
+
+impl ConnLimiter {
+    pub fn new(maybe_max_conns: Option<usize>, maybe_max_conns_per_ip: Option<usize>) -> Self {
+        Self {
+            maybe_global: maybe_max_conns.map(|it| Arc::new(Semaphore::new(it))),
+            maybe_per_ip: maybe_max_conns_per_ip.map(|max| PerIpLimit {
+                max,
+                conns: Arc::default(),
+            }),
+        }
+    }
+
+    /// Waits until there is room for another connection.
+    ///
+    /// This must be awaited before accepting, so that connections beyond the
+    /// global limit stay in the backlog of the listening socket instead of
+    /// being accepted by us.
+    pub async fn reserve(&self) -> ConnPermit {
+        let inner = match self.maybe_global.as_ref() {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+
+        ConnPermit { _inner: inner }
+    }
+
+    /// Counts an accepted connection against the limits. Returns `None` if the
+    /// client already holds as many connections as it is allowed to, in which
+    /// case the connection should be dropped.
+    pub fn admit(&self, permit: ConnPermit, maybe_ip: Option<IpAddr>) -> Option<ConnGuard> {
+        let (Some(limit), Some(ip)) = (self.maybe_per_ip.as_ref(), maybe_ip) else {
+            return Some(ConnGuard {
+                _permit: permit,
+                maybe_ip: None,
+            });
+        };
+
+        // peers connecting through IPv4 on a dual-stack listener show up as
+        // IPv4-mapped IPv6 addresses.
+        let ip = ip.to_canonical();
+        let mut conns = limit.conns.lock().unwrap();
+
+        if conns.get(&ip).copied().unwrap_or_default() >= limit.max {
+            debug!("refused a connection from {}: too many connections", ip);
+            return None;
+        }
+
+        *conns.entry(ip).or_default() += 1;
+
+        Some(ConnGuard {
+            _permit: permit,
+            maybe_ip: Some((ip, limit.conns.clone())),
+        })
+    }
+}
+
+/// A connection that holds the slot it was accepted with, so that it is
+/// counted against the global limit while it is still reading its PROXY header
+/// or doing its TLS handshake.
+pub(crate) struct Reserved<S> {
+    inner: S,
+    permit: ConnPermit,
+}
+
+impl<S> Reserved<S> {
+    pub fn new(inner: S, permit: ConnPermit) -> Self {
+        Self { inner, permit }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Takes the slot out, so that it can be handed to [`ConnLimiter::admit`].
+    pub fn take_permit(&mut self) -> ConnPermit {
+        std::mem::take(&mut self.permit)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Reserved<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Reserved<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
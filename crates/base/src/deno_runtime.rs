@@ -867,6 +867,7 @@ where
                     op_state.put::<EventMetadata>(EventMetadata {
                         service_path: conf.service_path.clone(),
                         execution_id: conf.key,
+                        request_id: None,
                     });
                 }
             }
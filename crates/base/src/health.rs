@@ -0,0 +1,118 @@
+// Answers liveness and readiness probes from the runtime itself, so that the
+// answer doesn't depend on the main worker being able to route the request.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use deno_core::serde_json::json;
+use http_v02::{header, Method, StatusCode};
+use hyper_v014::{Body, Response};
+use sb_core::RuntimeMetricSource;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use crate::http1::status;
+
+const LIVEZ_PATH: &str = "/livez";
+const READYZ_PATH: &str = "/readyz";
+
+/// A worker that doesn't answer the probe within this duration is considered
+/// unresponsive.
+static PROBE_TIMEOUT_DUR: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Probe {
+    Liveness,
+    Readiness,
+}
+
+impl Probe {
+    pub fn from_path(path: &str) -> Option<Self> {
+        match path {
+            LIVEZ_PATH => Some(Self::Liveness),
+            READYZ_PATH => Some(Self::Readiness),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct HealthCheck {
+    maybe_runtime: Option<Arc<ArcSwap<RuntimeMetricSource>>>,
+    draining: CancellationToken,
+}
+
+impl HealthCheck {
+    pub fn new(
+        maybe_runtime: Option<Arc<ArcSwap<RuntimeMetricSource>>>,
+        draining: CancellationToken,
+    ) -> Self {
+        Self {
+            maybe_runtime,
+            draining,
+        }
+    }
+
+    pub async fn respond(&self, probe: Probe, method: &Method) -> Response<Body> {
+        if method != Method::GET && method != Method::HEAD {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let (ready, body) = match probe {
+            // being able to answer at all is all that liveness is about.
+            Probe::Liveness => (true, json!({ "live": true })),
+            Probe::Readiness => self.readiness().await,
+        };
+
+        Response::builder()
+            .status(if ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            })
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    async fn readiness(&self) -> (bool, deno_core::serde_json::Value) {
+        let draining = self.draining.is_cancelled();
+        let maybe_runtime = self.maybe_runtime.as_ref().map(|it| it.load_full());
+
+        // NOTE: The isolates are probed the same way heap statistics are
+        // collected, by interrupting them. An isolate that has been terminated
+        // or whose thread is stuck outside of JavaScript never answers.
+        let (main_worker, event_worker) = tokio::join!(
+            async {
+                match maybe_runtime.as_ref() {
+                    Some(runtime) => probe(runtime.main.heap_statistics()).await,
+                    None => true,
+                }
+            },
+            async {
+                match maybe_runtime.as_ref().and_then(|it| it.event.as_ref()) {
+                    Some(source) => Some(probe(source.heap_statistics()).await),
+                    None => None,
+                }
+            }
+        );
+
+        let ready = !draining && main_worker && event_worker.unwrap_or(true);
+
+        (
+            ready,
+            json!({
+                "ready": ready,
+                "mainWorker": main_worker,
+                "eventWorker": event_worker,
+                "draining": draining,
+            }),
+        )
+    }
+}
+
+async fn probe<T>(fut: impl std::future::Future<Output = Option<T>>) -> bool {
+    matches!(timeout(PROBE_TIMEOUT_DUR, fut).await, Ok(Some(_)))
+}
@@ -0,0 +1,65 @@
+// Serves the endpoints of the runtime itself (metrics, health checks) over
+// HTTP/1, on listeners apart from the main one.
+
+use std::convert::Infallible;
+use std::future::Future;
+
+use http_v02::StatusCode;
+use hyper_v014::server::conn::Http;
+use hyper_v014::service::service_fn;
+use hyper_v014::{Body, Request, Response};
+use log::{debug, error};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+/// Accepts connections on `listener` until `token` is cancelled, answering
+/// every request with `handler`. `name` tells the endpoint apart in the logs.
+pub(crate) async fn serve_http1<F, R>(
+    listener: TcpListener,
+    token: CancellationToken,
+    name: &'static str,
+    handler: F,
+) where
+    F: Fn(Request<Body>) -> R + Clone + Send + 'static,
+    R: Future<Output = Response<Body>> + Send + 'static,
+{
+    loop {
+        tokio::select! {
+            msg = listener.accept() => {
+                let stream = match msg {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        error!("{} socket error: {}", name, err);
+                        continue;
+                    }
+                };
+
+                let handler = handler.clone();
+                let service = service_fn(move |req| {
+                    let fut = handler(req);
+                    async move { Ok::<_, Infallible>(fut.await) }
+                });
+
+                tokio::spawn(async move {
+                    if let Err(err) = Http::new()
+                        .http1_only(true)
+                        .serve_connection(stream, service)
+                        .await
+                    {
+                        debug!("{} connection error: {}", name, err);
+                    }
+                });
+            }
+
+            _ = token.cancelled() => break,
+        }
+    }
+}
+
+/// A response with an empty body.
+pub(crate) fn status(code: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(code)
+        .body(Body::empty())
+        .unwrap()
+}
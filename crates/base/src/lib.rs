@@ -8,8 +8,18 @@ pub mod server;
 pub mod snapshot;
 pub mod utils;
 
+mod access_log;
+mod compression;
+mod conn_limiter;
+mod health;
+mod http1;
 mod inspector_server;
+mod metrics_server;
+mod proxy_protocol;
+mod rate_limiter;
+mod socket_activation;
 mod timeout;
+mod tls;
 
 pub use inspector_server::InspectorOption;
 pub use sb_core::cache::CacheSetting;
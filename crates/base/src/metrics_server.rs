@@ -0,0 +1,348 @@
+// Serves the runtime metrics in the Prometheus text exposition format.
+// https://prometheus.io/docs/instrumenting/exposition_formats/#text-based-format
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use arc_swap::ArcSwap;
+use base_mem_check::WorkerHeapStatistics;
+use http_v02::{header, Method, StatusCode};
+use hyper_v014::{Body, Request, Response};
+use sb_core::RuntimeMetricSource;
+use sb_workers::context::{UserWorkerMsgs, UserWorkerServiceStats};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+
+use crate::http1::{serve_http1, status};
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+// NOTE: Heap statistics are collected by interrupting the isolate, and the
+// worker pool answers through its message loop. Neither must be able to stall
+// a scrape, so each of them is given up after this duration and the
+// corresponding metrics are left out of the response.
+static COLLECT_TIMEOUT_DUR: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub(crate) struct MetricsExporter {
+    runtime: Arc<ArcSwap<RuntimeMetricSource>>,
+    worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+}
+
+impl MetricsExporter {
+    /// `runtime` is replaced whenever the main worker is reloaded.
+    pub fn new(
+        runtime: Arc<ArcSwap<RuntimeMetricSource>>,
+        worker_pool_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    ) -> Self {
+        Self {
+            runtime,
+            worker_pool_tx,
+        }
+    }
+
+    /// Accepts connections on `listener` until `token` is cancelled.
+    ///
+    /// Everything is served from the runtime that calls this, so the endpoint
+    /// keeps responding even if the main worker is unresponsive.
+    pub async fn serve(self, listener: TcpListener, token: CancellationToken) {
+        serve_http1(listener, token, "metrics", move |req| {
+            let exporter = self.clone();
+            async move { exporter.handle(req).await }
+        })
+        .await
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Response<Body> {
+        if req.uri().path() != METRICS_PATH {
+            return status(StatusCode::NOT_FOUND);
+        }
+
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        Response::builder()
+            .header(header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(Body::from(self.render().await))
+            .unwrap()
+    }
+
+    async fn render(&self) -> String {
+        let runtime = self.runtime.load_full();
+        let shared = &runtime.shared;
+        let (main_heap_stats, event_heap_stats, service_stats) = tokio::join!(
+            collect(runtime.main.heap_statistics()),
+            async {
+                match runtime.event.as_ref() {
+                    Some(source) => collect(source.heap_statistics()).await,
+                    None => None,
+                }
+            },
+            self.service_stats(),
+        );
+
+        let mut buf = String::new();
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_active_user_workers",
+            "gauge",
+            "Number of user workers that are currently alive.",
+            [(String::new(), shared.active_user_workers())],
+        );
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_retired_user_workers_total",
+            "counter",
+            "Number of user workers that have been retired.",
+            [(String::new(), shared.retired_user_workers())],
+        );
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_received_requests_total",
+            "counter",
+            "Number of requests received by the main worker.",
+            [(String::new(), shared.received_requests())],
+        );
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_handled_requests_total",
+            "counter",
+            "Number of requests the main worker has finished handling.",
+            [(String::new(), shared.handled_requests())],
+        );
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_rate_limited_requests_total",
+            "counter",
+            "Number of requests rejected by the rate limiter.",
+            [(String::new(), shared.rate_limited_requests())],
+        );
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_dropped_access_log_records_total",
+            "counter",
+            "Number of access log lines dropped because the writer fell behind.",
+            [(String::new(), shared.dropped_access_log_records())],
+        );
+
+        write_metric(
+            &mut buf,
+            "edge_runtime_active_io",
+            "gauge",
+            "Number of connections that are currently open.",
+            [(String::new(), shared.active_io())],
+        );
+
+        let heap_stats = [("main", main_heap_stats), ("event", event_heap_stats)]
+            .into_iter()
+            .filter_map(|(worker, stats)| Some((format!("worker=\"{}\"", worker), stats?)))
+            .collect::<Vec<_>>();
+
+        for (name, help, value_fn) in HEAP_STATISTICS {
+            write_metric(
+                &mut buf,
+                name,
+                "gauge",
+                help,
+                heap_stats
+                    .iter()
+                    .map(|(labels, stats)| (labels.clone(), value_fn(stats))),
+            );
+        }
+
+        if let Some(service_stats) = service_stats {
+            let mut service_stats = service_stats.into_iter().collect::<Vec<_>>();
+
+            service_stats.sort_by(|a, b| a.0.cmp(&b.0));
+            write_metric(
+                &mut buf,
+                "edge_runtime_service_user_workers",
+                "gauge",
+                "Number of live user workers per service.",
+                service_stats.iter().flat_map(|(service, stats)| {
+                    let service = escape_label_value(service);
+
+                    [
+                        (
+                            format!("service=\"{}\",state=\"active\"", service),
+                            stats.active,
+                        ),
+                        (
+                            format!("service=\"{}\",state=\"retired\"", service),
+                            stats.retired,
+                        ),
+                    ]
+                }),
+            );
+
+            for (name, kind, help, value_fn) in SERVICE_QUEUE_STATISTICS {
+                write_metric(
+                    &mut buf,
+                    name,
+                    kind,
+                    help,
+                    service_stats.iter().map(|(service, stats)| {
+                        (
+                            format!("service=\"{}\"", escape_label_value(service)),
+                            value_fn(stats),
+                        )
+                    }),
+                );
+            }
+        }
+
+        buf
+    }
+
+    async fn service_stats(&self) -> Option<HashMap<String, UserWorkerServiceStats>> {
+        let (tx, rx) = oneshot::channel();
+
+        self.worker_pool_tx
+            .send(UserWorkerMsgs::ServiceStats(tx))
+            .ok()?;
+
+        collect(async move { rx.await.ok() }).await
+    }
+}
+
+type HeapStatisticsField = (
+    &'static str,
+    &'static str,
+    fn(&WorkerHeapStatistics) -> usize,
+);
+
+const HEAP_STATISTICS: &[HeapStatisticsField] = &[
+    (
+        "edge_runtime_heap_total_bytes",
+        "Total size of the V8 heap.",
+        |it| it.total_heap_size,
+    ),
+    (
+        "edge_runtime_heap_total_executable_bytes",
+        "Size of the executable part of the V8 heap.",
+        |it| it.total_heap_size_executable,
+    ),
+    (
+        "edge_runtime_heap_total_physical_bytes",
+        "Physical memory committed for the V8 heap.",
+        |it| it.total_physical_size,
+    ),
+    (
+        "edge_runtime_heap_total_available_bytes",
+        "Size of the memory still available to the V8 heap.",
+        |it| it.total_available_size,
+    ),
+    (
+        "edge_runtime_heap_used_bytes",
+        "Size of the V8 heap in use.",
+        |it| it.used_heap_size,
+    ),
+    (
+        "edge_runtime_heap_total_global_handles_bytes",
+        "Size of the memory reserved for global handles.",
+        |it| it.total_global_handles_size,
+    ),
+    (
+        "edge_runtime_heap_used_global_handles_bytes",
+        "Size of the memory used by global handles.",
+        |it| it.used_global_handles_size,
+    ),
+    (
+        "edge_runtime_heap_malloced_bytes",
+        "Memory allocated by V8 through malloc.",
+        |it| it.malloced_memory,
+    ),
+    (
+        "edge_runtime_heap_peak_malloced_bytes",
+        "Peak of the memory allocated by V8 through malloc.",
+        |it| it.peak_malloced_memory,
+    ),
+    (
+        "edge_runtime_heap_external_bytes",
+        "Size of the external memory held by JavaScript objects.",
+        |it| it.external_memory,
+    ),
+];
+
+type ServiceQueueField = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&UserWorkerServiceStats) -> usize,
+);
+
+const SERVICE_QUEUE_STATISTICS: &[ServiceQueueField] = &[
+    (
+        "edge_runtime_service_queued_requests",
+        "gauge",
+        "Number of requests waiting for a worker per service.",
+        |it| it.queued,
+    ),
+    (
+        "edge_runtime_service_dequeued_requests_total",
+        "counter",
+        "Number of requests that have left the queue per service.",
+        |it| it.dequeued,
+    ),
+    (
+        "edge_runtime_service_queue_wait_milliseconds_total",
+        "counter",
+        "Time spent in the queue by the requests that have left it per service.",
+        |it| it.queue_wait_ms as usize,
+    ),
+    (
+        "edge_runtime_service_shed_requests_total",
+        "counter",
+        "Number of requests turned away because the queue was full per service.",
+        |it| it.shed,
+    ),
+];
+
+async fn collect<T>(fut: impl std::future::Future<Output = Option<T>>) -> Option<T> {
+    timeout(COLLECT_TIMEOUT_DUR, fut).await.ok().flatten()
+}
+
+fn write_metric(
+    buf: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, usize)>,
+) {
+    let mut samples = samples.into_iter().peekable();
+
+    // a metric family without any sample is not worth exposing.
+    if samples.peek().is_none() {
+        return;
+    }
+
+    let _ = writeln!(buf, "# HELP {} {}", name, help);
+    let _ = writeln!(buf, "# TYPE {} {}", name, kind);
+
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(buf, "{} {}", name, value);
+        } else {
+            let _ = writeln!(buf, "{}{{{}}} {}", name, labels, value);
+        }
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
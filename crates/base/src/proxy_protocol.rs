@@ -0,0 +1,286 @@
+// Implements the receiving side of the PROXY protocol (v1 and v2).
+// https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use futures_util::stream::FuturesUnordered;
+use futures_util::{FutureExt, StreamExt};
+use ipnetwork::IpNetwork;
+use log::{debug, warn};
+use tls_listener::AsyncAccept;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::conn_limiter::{ConnLimiter, ConnPermit, Reserved};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+static HEADER_READ_TIMEOUT_DUR: Duration = Duration::from_secs(10);
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid PROXY header: {}", msg),
+    )
+}
+
+/// Reads a PROXY protocol header from the stream, and returns the address of
+/// the original client.
+///
+/// The address is `None` if the proxy didn't relay it. (e.g. health checks of
+/// the proxy itself, which are sent with the `LOCAL` command)
+///
+/// It never reads past the end of the header, so the rest of the stream can be
+/// handed over to hyper (or a TLS acceptor) as it is.
+pub(crate) async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut buf = [0u8; 16];
+
+    // headers of both versions are at least 12 bytes long.
+    stream.read_exact(&mut buf[..V2_SIGNATURE.len()]).await?;
+
+    if buf.starts_with(V2_SIGNATURE) {
+        stream.read_exact(&mut buf[12..16]).await?;
+
+        let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+        let mut payload = vec![0u8; len];
+
+        stream.read_exact(&mut payload).await?;
+        parse_v2(buf[12], buf[13], &payload)
+    } else if buf.starts_with(V1_PREFIX) {
+        let mut line = buf[..V2_SIGNATURE.len()].to_vec();
+
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LEN {
+                return Err(invalid_data("v1 header is too long"));
+            }
+
+            line.push(stream.read_u8().await?);
+        }
+
+        parse_v1(&line[..line.len() - 2])
+    } else {
+        Err(invalid_data("missing signature"))
+    }
+}
+
+fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = str::from_utf8(line).map_err(|_| invalid_data("v1 header is not ascii"))?;
+    let mut parts = line.split(' ').skip(1);
+
+    match parts.next() {
+        Some("TCP4") | Some("TCP6") => {}
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err(invalid_data("unknown v1 protocol")),
+    }
+
+    let mut next = || {
+        parts
+            .next()
+            .ok_or_else(|| invalid_data("truncated v1 header"))
+    };
+    let (src_ip, _dst_ip, src_port, _dst_port) = (next()?, next()?, next()?, next()?);
+
+    Ok(Some(SocketAddr::new(
+        src_ip
+            .parse()
+            .map_err(|_| invalid_data("invalid v1 address"))?,
+        src_port
+            .parse()
+            .map_err(|_| invalid_data("invalid v1 port"))?,
+    )))
+}
+
+fn parse_v2(ver_cmd: u8, family: u8, payload: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid_data("unsupported version"));
+    }
+
+    match ver_cmd & 0x0f {
+        // LOCAL
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => {}
+        _ => return Err(invalid_data("unknown v2 command")),
+    }
+
+    let port = |bytes: &[u8]| u16::from_be_bytes([bytes[0], bytes[1]]);
+
+    // the address block is laid out as: source address, destination address,
+    // source port, destination port.
+    match family >> 4 {
+        // AF_INET
+        0x1 if payload.len() >= 12 => {
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&payload[0..4]).unwrap());
+            Ok(Some(SocketAddr::new(
+                IpAddr::V4(src),
+                port(&payload[8..10]),
+            )))
+        }
+
+        // AF_INET6
+        0x2 if payload.len() >= 36 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&payload[0..16]).unwrap());
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(src),
+                port(&payload[32..34]),
+            )))
+        }
+
+        0x1 | 0x2 => Err(invalid_data("truncated v2 address block")),
+
+        // AF_UNSPEC and AF_UNIX don't carry anything we could report.
+        _ => Ok(None),
+    }
+}
+
+type PendingConnection = BoxFuture<'static, Option<(Reserved<TcpStream>, SocketAddr)>>;
+
+async fn read_header_or_drop(
+    mut stream: Reserved<TcpStream>,
+    peer_addr: SocketAddr,
+) -> Option<(Reserved<TcpStream>, SocketAddr)> {
+    match timeout(HEADER_READ_TIMEOUT_DUR, read_header(&mut stream)).await {
+        Ok(Ok(source)) => Some((stream, source.unwrap_or(peer_addr))),
+
+        Ok(Err(err)) => {
+            debug!("dropping connection from {}: {}", peer_addr, err);
+            None
+        }
+
+        Err(_) => {
+            debug!(
+                "dropping connection from {}: PROXY header timed out",
+                peer_addr
+            );
+            None
+        }
+    }
+}
+
+/// A TCP listener that optionally expects every connection to start with a
+/// PROXY protocol header.
+///
+/// When enabled, the address it yields for a connection is the client address
+/// decoded from the header. Connections from peers outside of the trusted
+/// ranges, or without a valid header, are dropped.
+///
+/// Each connection takes a slot of the connection limiter before it is
+/// accepted. While no slot is left, the inner listener is not polled, so the
+/// rest stay in the backlog of the socket.
+pub(crate) struct ProxyProtocolListener {
+    inner: TcpListener,
+    trusted_ranges: Option<Arc<[IpNetwork]>>,
+    pending: FuturesUnordered<PendingConnection>,
+    conn_limiter: ConnLimiter,
+    maybe_reserve: Option<BoxFuture<'static, ConnPermit>>,
+    maybe_permit: Option<ConnPermit>,
+}
+
+impl ProxyProtocolListener {
+    /// `trusted_ranges` being `None` disables the PROXY protocol. If it is
+    /// empty, every peer is trusted.
+    pub fn new(
+        inner: TcpListener,
+        trusted_ranges: Option<Vec<IpNetwork>>,
+        conn_limiter: ConnLimiter,
+    ) -> Self {
+        Self {
+            inner,
+            trusted_ranges: trusted_ranges.map(Arc::from),
+            pending: FuturesUnordered::new(),
+            conn_limiter,
+            maybe_reserve: None,
+            maybe_permit: None,
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    pub async fn accept(&mut self) -> io::Result<(Reserved<TcpStream>, SocketAddr)> {
+        futures_util::future::poll_fn(|cx| Pin::new(&mut *self).poll_accept(cx)).await
+    }
+
+    fn is_trusted(ranges: &[IpNetwork], peer_addr: SocketAddr) -> bool {
+        // peers connecting through IPv4 on a dual-stack listener show up as
+        // IPv4-mapped IPv6 addresses.
+        let ip = peer_addr.ip().to_canonical();
+
+        ranges.is_empty() || ranges.iter().any(|range| range.contains(ip))
+    }
+}
+
+impl AsyncAccept for ProxyProtocolListener {
+    type Connection = Reserved<TcpStream>;
+    type Address = SocketAddr;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(Self::Connection, Self::Address), Self::Error>> {
+        let this = self.get_mut();
+
+        loop {
+            loop {
+                if this.maybe_permit.is_none() {
+                    let reserve = this.maybe_reserve.get_or_insert_with(|| {
+                        let conn_limiter = this.conn_limiter.clone();
+                        async move { conn_limiter.reserve().await }.boxed()
+                    });
+
+                    match reserve.poll_unpin(cx) {
+                        Poll::Ready(permit) => {
+                            this.maybe_reserve = None;
+                            this.maybe_permit = Some(permit);
+                        }
+
+                        Poll::Pending => break,
+                    }
+                }
+
+                match this.inner.poll_accept(cx) {
+                    Poll::Ready(Ok((stream, peer_addr))) => {
+                        let stream = Reserved::new(stream, this.maybe_permit.take().unwrap());
+                        let Some(trusted_ranges) = this.trusted_ranges.as_ref() else {
+                            return Poll::Ready(Ok((stream, peer_addr)));
+                        };
+
+                        if !Self::is_trusted(trusted_ranges, peer_addr) {
+                            warn!("rejected a connection from untrusted source: {}", peer_addr);
+                            continue;
+                        }
+
+                        this.pending
+                            .push(read_header_or_drop(stream, peer_addr).boxed());
+                    }
+
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => break,
+                }
+            }
+
+            // a dropped connection frees a slot, so the inner listener is
+            // polled again before giving up.
+            match this.pending.poll_next_unpin(cx) {
+                Poll::Ready(Some(Some(conn))) => return Poll::Ready(Ok(conn)),
+                Poll::Ready(Some(None)) => continue,
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
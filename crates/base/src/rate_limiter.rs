@@ -0,0 +1,149 @@
+// Rejects requests that exceed a configured rate before they reach the main
+// worker, using a token bucket per key.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context};
+use http_v02::{HeaderMap, HeaderName, Uri};
+
+/// Buckets are swept once there are at least this many of them.
+const MIN_SWEEP_LEN: usize = 1024;
+
+/// What requests are grouped by when counting them against the limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// The address of the client.
+    ClientIp,
+    /// The value of a request header.
+    Header(HeaderName),
+    /// The first segment of the request path, which is how the main worker
+    /// usually picks the service that handles a request.
+    PathPrefix,
+}
+
+impl FromStr for RateLimitKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ip" => Ok(Self::ClientIp),
+            "path" => Ok(Self::PathPrefix),
+            _ => {
+                let Some(name) = s.strip_prefix("header:") else {
+                    bail!("expected `ip`, `path` or `header:<NAME>`, got `{}`", s);
+                };
+
+                Ok(Self::Header(HeaderName::from_str(name).with_context(
+                    || format!("invalid header name: {}", name),
+                )?))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub key: RateLimitKey,
+    /// Requests per second that are let through on average.
+    pub rate: u32,
+    /// Requests that can be let through at once after a quiet period.
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    buckets: HashMap<String, Bucket>,
+    next_sweep_len: usize,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State {
+                buckets: HashMap::new(),
+                next_sweep_len: MIN_SWEEP_LEN,
+            }),
+        }
+    }
+
+    /// Takes a token for the request. If there is none left, returns how long
+    /// the client should wait before trying again.
+    ///
+    /// Requests that have no value for the key (e.g. the header is missing, or
+    /// the connection came in through a Unix domain socket) share a bucket.
+    pub fn check(
+        &self,
+        uri: &Uri,
+        headers: &HeaderMap,
+        maybe_client_ip: Option<IpAddr>,
+    ) -> Result<(), Duration> {
+        let key = match &self.config.key {
+            RateLimitKey::ClientIp => maybe_client_ip
+                .map(|it| it.to_canonical().to_string())
+                .unwrap_or_default(),
+
+            RateLimitKey::Header(name) => headers
+                .get(name)
+                .map(|it| String::from_utf8_lossy(it.as_bytes()).into_owned())
+                .unwrap_or_default(),
+
+            RateLimitKey::PathPrefix => {
+                let path = uri.path().trim_start_matches('/');
+                let segment = path.split('/').next().unwrap_or_default();
+
+                format!("/{}", segment)
+            }
+        };
+
+        let rate = self.config.rate.max(1) as f64;
+        let burst = self.config.burst.max(1) as f64;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        // NOTE: A bucket that has refilled completely is no different from one
+        // that doesn't exist, so those are dropped from time to time to keep
+        // keys with a high cardinality from growing the map forever.
+        if state.buckets.len() >= state.next_sweep_len {
+            state.buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated_at).as_secs_f64() * rate < burst
+            });
+
+            state.next_sweep_len = (state.buckets.len() * 2).max(MIN_SWEEP_LEN);
+        }
+
+        let bucket = state.buckets.entry(key).or_insert(Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate));
+        }
+
+        bucket.tokens -= 1.0;
+
+        Ok(())
+    }
+}
@@ -38,7 +38,12 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
     } = args;
 
     let Timing {
-        status: TimingStatus { demand, is_retired },
+        status:
+            TimingStatus {
+                demand,
+                served,
+                is_retired,
+            },
         req: (mut req_start_rx, mut req_end_rx),
         ..
     } = timing.unwrap_or_default();
@@ -176,6 +181,7 @@ pub async fn supervise(args: Arguments, oneshot: bool) -> (ShutdownReason, i64)
                 assert!(req_start_ack, "supervisor observed the request end signal but did not see request start signal");
 
                 req_ack_count += 1;
+                served.fetch_add(1, Ordering::Release);
                 complete_reason = Some(ShutdownReason::EarlyDrop);
             }
 
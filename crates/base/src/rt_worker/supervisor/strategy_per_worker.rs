@@ -39,7 +39,12 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     } = args;
 
     let Timing {
-        status: TimingStatus { demand, is_retired },
+        status:
+            TimingStatus {
+                demand,
+                served,
+                is_retired,
+            },
         req: (_, mut req_end_rx),
     } = timing.unwrap_or_default();
 
@@ -54,6 +59,8 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     let mut current_thread_id = Option::<ThreadId>::None;
 
     let wall_clock_limit_ms = runtime_opts.worker_timeout_ms;
+    let maybe_max_requests = runtime_opts.max_requests_per_worker;
+    let maybe_max_age = runtime_opts.max_worker_age_ms.map(Duration::from_millis);
 
     let is_wall_clock_limit_disabled = wall_clock_limit_ms == 0;
     let mut is_worker_entered = false;
@@ -61,6 +68,7 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
     let mut is_cpu_time_soft_limit_reached = false;
     let mut is_waiting_for_termination = false;
     let mut have_all_reqs_been_acknowledged = false;
+    let mut is_recycled = false;
 
     let mut cpu_usage_metrics_rx = cpu_usage_metrics_rx.unwrap();
     let mut cpu_usage_ms = 0i64;
@@ -135,9 +143,17 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
         }
     };
 
+    let max_age_alert = async {
+        match maybe_max_age {
+            Some(dur) => tokio::time::sleep(dur).await,
+            None => pending().await,
+        }
+    };
+
     tokio::pin!(wall_clock_duration_alert);
     tokio::pin!(wall_clock_beforeunload_alert);
     tokio::pin!(early_drop_fut);
+    tokio::pin!(max_age_alert);
 
     loop {
         tokio::select! {
@@ -214,6 +230,15 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                                 }
                             }
                         }
+
+                        if is_recycled
+                            && have_all_reqs_been_acknowledged
+                            && promise_metrics.have_all_promises_been_resolved()
+                        {
+                            if let Some(func) = dispatch_early_drop_beforeunload_fn.take() {
+                                func();
+                            }
+                        }
                     }
                 }
             }
@@ -243,9 +268,16 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
 
             Some(_) = req_end_rx.recv() => {
                 req_ack_count += 1;
+                served.fetch_add(1, Ordering::Release);
                 have_all_reqs_been_acknowledged = req_ack_count == demand.load(Ordering::Acquire);
 
-                if !is_cpu_time_soft_limit_reached {
+                if !is_recycled && maybe_max_requests.is_some_and(|it| req_ack_count as u64 >= it) {
+                    early_retire_fn();
+                    info!("maximum requests per worker reached: isolate: {:?}", key);
+                    is_recycled = true;
+                }
+
+                if !is_cpu_time_soft_limit_reached && !is_recycled {
                     if let Some(tx) = pool_msg_tx.clone() {
                         if tx.send(UserWorkerMsgs::Idle(key)).is_err() {
                             error!("failed to send idle msg to pool: {:?}", key);
@@ -253,7 +285,7 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                     }
                 }
 
-                if !is_cpu_time_soft_limit_reached
+                if !(is_cpu_time_soft_limit_reached || is_recycled)
                     || !have_all_reqs_been_acknowledged
                     || !promise_metrics.have_all_promises_been_resolved()
                 {
@@ -294,6 +326,22 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                 is_wall_clock_beforeunload_armed = true;
             }
 
+            _ = &mut max_age_alert, if !is_recycled && maybe_max_age.is_some() => {
+                early_retire_fn();
+                info!("maximum worker age reached: isolate: {:?}", key);
+
+                is_recycled = true;
+                have_all_reqs_been_acknowledged = req_ack_count == demand.load(Ordering::Acquire);
+
+                if have_all_reqs_been_acknowledged
+                    && promise_metrics.have_all_promises_been_resolved()
+                {
+                    if let Some(func) = dispatch_early_drop_beforeunload_fn.take() {
+                        func();
+                    }
+                }
+            }
+
             Some(_) = memory_limit_rx.recv() => {
                 error!("memory limit reached for the worker: isolate: {:?}", key);
                 complete_reason = Some(ShutdownReason::Memory);
@@ -311,6 +359,8 @@ pub async fn supervise(args: Arguments) -> (ShutdownReason, i64) {
                 return (
                     if is_waiting_for_termination {
                         ShutdownReason::TerminationRequested
+                    } else if is_recycled {
+                        ShutdownReason::Recycled
                     } else {
                         ShutdownReason::EarlyDrop
                     },
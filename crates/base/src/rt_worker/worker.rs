@@ -23,7 +23,6 @@ use std::future::{pending, Future};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::io;
 use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::sync::oneshot::{self, Receiver, Sender};
 use tokio::time::Instant;
@@ -48,7 +47,7 @@ pub struct Worker {
 }
 
 pub type HandleCreationType<'r> = Pin<Box<dyn Future<Output = Result<WorkerEvents, Error>> + 'r>>;
-pub type DuplexStreamEntry = (io::DuplexStream, Option<CancellationToken>);
+pub use sb_core::net::DuplexStreamEntry;
 
 pub trait WorkerHandler: Send {
     fn handle_error(&self, error: Error) -> Result<WorkerEvents, Error>;
@@ -1,7 +1,7 @@
 use super::utils::send_event_if_event_worker_available;
 use crate::deno_runtime::DenoRuntime;
 use crate::inspector_server::Inspector;
-use crate::server::ServerFlags;
+use crate::server::{ServerFlags, REQUEST_ID_HEADER};
 use crate::timeout::{self, CancelOnWriteTimeout, ReadTimeoutStream};
 
 use crate::rt_worker::worker::Worker;
@@ -17,13 +17,14 @@ use event_worker::events::{
 };
 use futures_util::pin_mut;
 use http_utils::io::Upgraded2;
-use http_utils::utils::{emit_status_code, get_upgrade_type};
+use http_utils::utils::{emit_error_response, get_upgrade_type};
 use http_v02::StatusCode;
 use hyper_v014::client::conn::http1;
 use hyper_v014::upgrade::OnUpgrade;
 use hyper_v014::{Body, Request, Response};
 use log::{debug, error};
 use once_cell::sync::Lazy;
+use sb_core::net::ConnAddrs;
 use sb_core::{MetricSource, SharedMetricSource};
 use sb_graph::{DecoratorType, EszipPayloadKind};
 use sb_workers::context::{
@@ -34,6 +35,7 @@ use sb_workers::errors::WorkerError;
 use std::future::pending;
 use std::io::ErrorKind;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::io::{self, copy_bidirectional};
@@ -101,6 +103,7 @@ async fn handle_request(
     flags: Arc<ServerFlags>,
     worker_kind: WorkerKind,
     duplex_stream_tx: mpsc::UnboundedSender<DuplexStreamEntry>,
+    in_flight: Arc<AtomicUsize>,
     msg: WorkerRequestMsg,
 ) -> Result<(), Error> {
     let request_idle_timeout_ms = flags.request_idle_timeout_ms;
@@ -121,9 +124,29 @@ async fn handle_request(
         mut req,
         res_tx,
         conn_token,
+        conn_addrs,
     } = msg;
 
-    let _ = duplex_stream_tx.send((theirs, conn_token.clone()));
+    let request_id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|it| it.to_str().ok())
+        .map(str::to_owned);
+
+    // NOTE: The worker can't tell which of the requests it is serving at once
+    // the logs it emits are about. It is only told the request ID while it is
+    // serving a single request, so that no log is put down to the wrong one.
+    let is_alone = in_flight.fetch_add(1, Ordering::AcqRel) == 0;
+    let in_flight_guard = scopeguard::guard(in_flight, |it| {
+        it.fetch_sub(1, Ordering::AcqRel);
+    });
+
+    let _ = duplex_stream_tx.send(DuplexStreamEntry {
+        stream: theirs,
+        conn_token: conn_token.clone(),
+        conn_addrs,
+        request_id: request_id.clone().filter(|_| is_alone),
+    });
     let req_upgrade_type = get_upgrade_type(req.headers());
     let req_upgrade = req_upgrade_type
         .clone()
@@ -138,6 +161,11 @@ async fn handle_request(
     // spawn a task to poll the connection and drive the HTTP state
     tokio::task::spawn({
         async move {
+            // NOTE: The request is in flight until its connection token is
+            // cancelled, which happens once the response body has been sent
+            // or dropped.
+            let _in_flight_guard = in_flight_guard;
+
             match connection.without_shutdown().await {
                 Err(e) => {
                     error!(
@@ -151,11 +179,12 @@ async fn handle_request(
                     if let Some((requested, req_upgrade)) = req_upgrade {
                         if let Ok((Some(accepted), status)) = upgrade_rx.await {
                             if status == StatusCode::SWITCHING_PROTOCOLS && accepted == requested {
-                                tokio::spawn(relay_upgraded_request_and_response(
+                                relay_upgraded_request_and_response(
                                     req_upgrade,
                                     parts,
                                     request_idle_timeout_ms,
-                                ));
+                                )
+                                .await;
 
                                 return;
                             }
@@ -184,7 +213,13 @@ async fn handle_request(
     let res = tokio::select! {
         resp = request_sender.send_request(req) => resp,
         _ = maybe_cancel_fut => {
-            Ok(emit_status_code(http_v02::StatusCode::GATEWAY_TIMEOUT, None, false))
+            Ok(emit_error_response(
+                StatusCode::GATEWAY_TIMEOUT,
+                "WorkerIdleTimedOut",
+                "the worker did not respond in time",
+                request_id.as_deref(),
+                false,
+            ))
         }
     };
 
@@ -200,7 +235,13 @@ async fn handle_request(
         match res_upgrade_type {
             Some(accepted) if accepted == requested => {}
             _ => {
-                drop(res_tx.send(Ok(emit_status_code(StatusCode::BAD_GATEWAY, None, true))));
+                drop(res_tx.send(Ok(emit_error_response(
+                    StatusCode::BAD_GATEWAY,
+                    "InvalidUpgradeResponse",
+                    "the worker did not accept the requested protocol upgrade",
+                    request_id.as_deref(),
+                    true,
+                ))));
                 return Ok(());
             }
         }
@@ -632,14 +673,17 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
 
     let worker_req_handle: tokio::task::JoinHandle<Result<(), Error>> = tokio::task::spawn({
         let stream_tx = duplex_stream_tx;
+        let in_flight = Arc::<AtomicUsize>::default();
         async move {
             while let Some(msg) = worker_req_rx.recv().await {
                 tokio::task::spawn({
                     let flags = flags.clone();
                     let stream_tx_inner = stream_tx.clone();
+                    let in_flight = in_flight.clone();
                     async move {
                         if let Err(err) =
-                            handle_request(flags, worker_kind, stream_tx_inner, msg).await
+                            handle_request(flags, worker_kind, stream_tx_inner, in_flight, msg)
+                                .await
                         {
                             error!("worker failed to handle request: {:?}", err);
                         }
@@ -685,16 +729,18 @@ pub async fn create_worker<Opt: Into<CreateWorkerArgs>>(
 
 pub async fn send_user_worker_request(
     worker_request_msg_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
-    req: Request<Body>,
+    mut req: Request<Body>,
     cancel: CancellationToken,
     exit: WorkerExit,
     conn_token: Option<CancellationToken>,
 ) -> Result<Response<Body>, Error> {
     let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper_v014::Error>>();
+    let conn_addrs = req.extensions_mut().remove::<ConnAddrs>();
     let msg = WorkerRequestMsg {
         req,
         res_tx,
         conn_token,
+        conn_addrs,
     };
 
     // send the message to worker
@@ -741,7 +787,7 @@ pub async fn create_main_worker(
     termination_token: Option<TerminationToken>,
     inspector: Option<Inspector>,
     jsx: Option<JsxImportSourceConfig>,
-) -> Result<mpsc::UnboundedSender<WorkerRequestMsg>, Error> {
+) -> Result<WorkerCtx, Error> {
     let mut service_path = main_worker_path.clone();
     let mut maybe_eszip = None;
     if let Some(ext) = main_worker_path.extension() {
@@ -778,7 +824,7 @@ pub async fn create_main_worker(
     .await
     .map_err(|err| anyhow!("main worker boot error: {}", err))?;
 
-    Ok(ctx.msg_tx)
+    Ok(ctx)
 }
 
 pub async fn create_events_worker(
@@ -880,6 +926,7 @@ pub async fn create_user_worker_pool(
                         }
                     }, if !termination_requested => {
                         termination_requested = true;
+                        worker_pool.clear_min_instances();
 
                         if worker_pool.user_workers.is_empty() {
                             if let Some(token) = token {
@@ -919,6 +966,14 @@ pub async fn create_user_worker_pool(
                                 worker_pool.idle(&key);
                             }
 
+                            Some(UserWorkerMsgs::BootFailed(service_path)) => {
+                                worker_pool.boot_failed(&service_path);
+                            }
+
+                            Some(UserWorkerMsgs::ServiceStats(tx)) => {
+                                let _ = tx.send(worker_pool.service_stats());
+                            }
+
                             Some(UserWorkerMsgs::Shutdown(key)) => {
                                 worker_pool.shutdown(&key);
 
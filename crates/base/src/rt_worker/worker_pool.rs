@@ -1,25 +1,31 @@
 use crate::inspector_server::Inspector;
+use crate::rt_worker::utils::send_event_if_event_worker_available;
 use crate::rt_worker::worker_ctx::{create_worker, send_user_worker_request};
-use crate::server::ServerFlags;
+use crate::server::{ServerFlags, REQUEST_ID_HEADER};
 use anyhow::{anyhow, bail, Context, Error};
 use enum_as_inner::EnumAsInner;
-use event_worker::events::WorkerEventWithMetadata;
+use event_worker::events::{
+    CircuitState, CircuitStateChangedEvent, EventMetadata, WorkerEventWithMetadata, WorkerEvents,
+};
+use http_utils::body_limit::limit_request_body;
 use http_v02::Request;
 use hyper_v014::Body;
-use log::error;
+use log::{error, warn};
+use rand::seq::SliceRandom;
 use sb_core::util::sync::AtomicFlag;
 use sb_core::SharedMetricSource;
 use sb_workers::context::{
-    CreateUserWorkerResult, SendRequestResult, Timing, TimingStatus, UserWorkerMsgs,
-    UserWorkerProfile, WorkerContextInitOpts, WorkerRuntimeOpts,
+    CreateUserWorkerResult, QueuePriority, SendRequestResult, Timing, TimingStatus, UserWorkerMsgs,
+    UserWorkerProfile, UserWorkerServiceStats, WorkerContextInitOpts, WorkerRuntimeOpts,
 };
 use sb_workers::errors::WorkerError;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
+use std::future::pending;
 use std::str::FromStr;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::oneshot::Sender;
 use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError};
@@ -28,6 +34,10 @@ use uuid::Uuid;
 
 use super::worker_ctx::TerminationToken;
 
+/// How long to wait before booting another spare worker for a service whose
+/// last one failed to boot.
+const SPARE_WORKER_RETRY_DELAY: Duration = Duration::from_secs(5);
+
 #[derive(Debug, Clone, Copy, EnumAsInner)]
 pub enum SupervisorPolicy {
     PerWorker,
@@ -63,11 +73,51 @@ impl SupervisorPolicy {
     }
 }
 
+/// How a request is routed to one of the workers of a service.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum RoutingStrategy {
+    #[default]
+    RoundRobin,
+    /// Picks the worker with the fewest requests in flight.
+    LeastOutstanding,
+    /// Picks the less busy of two workers taken at random, which is nearly as
+    /// good as looking at all of them.
+    PowerOfTwoChoices,
+}
+
+impl FromStr for RoutingStrategy {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round_robin" => Ok(Self::RoundRobin),
+            "least_outstanding" => Ok(Self::LeastOutstanding),
+            "p2c" => Ok(Self::PowerOfTwoChoices),
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// When to stop creating workers for a service that keeps failing, and for
+/// how long.
+#[derive(Debug, Clone, Copy)]
+pub struct CircuitBreakerPolicy {
+    /// Failures in a row after which the circuit of a service opens.
+    pub threshold: usize,
+    /// How long the circuit stays open the first time. It is doubled every
+    /// time the circuit opens again without the service recovering.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
 #[derive(Clone)]
 pub struct WorkerPoolPolicy {
     supervisor_policy: SupervisorPolicy,
+    routing: RoutingStrategy,
     max_parallelism: usize,
     request_wait_timeout_ms: u64,
+    max_queued_requests: Option<usize>,
+    circuit_breaker: Option<CircuitBreakerPolicy>,
 }
 
 impl Default for WorkerPoolPolicy {
@@ -79,8 +129,11 @@ impl Default for WorkerPoolPolicy {
 
         Self {
             supervisor_policy: SupervisorPolicy::default(),
+            routing: RoutingStrategy::default(),
             max_parallelism: available_parallelism,
             request_wait_timeout_ms: 10000,
+            max_queued_requests: None,
+            circuit_breaker: None,
         }
     }
 }
@@ -95,12 +148,28 @@ impl WorkerPoolPolicy {
 
         Self {
             supervisor_policy: supervisor.into().unwrap_or(default.supervisor_policy),
+            routing: default.routing,
             max_parallelism: max_parallelism.into().unwrap_or(default.max_parallelism),
             request_wait_timeout_ms: server_flags
                 .request_wait_timeout_ms
                 .unwrap_or(default.request_wait_timeout_ms),
+            max_queued_requests: server_flags.max_queued_requests,
+            circuit_breaker: default.circuit_breaker,
         }
     }
+
+    pub fn with_routing(mut self, routing: RoutingStrategy) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    pub fn with_circuit_breaker(
+        mut self,
+        circuit_breaker: impl Into<Option<CircuitBreakerPolicy>>,
+    ) -> Self {
+        self.circuit_breaker = circuit_breaker.into();
+        self
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -126,11 +195,182 @@ impl std::hash::Hash for WorkerId {
     }
 }
 
+/// What a request turned away by the queue is told to wait before trying
+/// again, while no request has left the queue yet to go by.
+const DEFAULT_QUEUE_RETRY_AFTER_MS: u64 = 1000;
+
+/// Requests of a service that are waiting for one of its workers, in a lane
+/// per priority.
+struct RequestQueue {
+    lanes: [QueueLane; 3],
+    len: AtomicUsize,
+    dequeued: AtomicUsize,
+    wait_ms: AtomicU64,
+    shed: AtomicUsize,
+}
+
+/// The requests waiting in a lane, oldest first. Each of them is woken up
+/// through a channel of its own, which holds one notification at most.
+#[derive(Default)]
+struct QueueLane {
+    waiters: Mutex<VecDeque<flume::Sender<Option<Uuid>>>>,
+}
+
+impl RequestQueue {
+    fn new() -> Self {
+        Self {
+            lanes: Default::default(),
+            len: AtomicUsize::new(0),
+            dequeued: AtomicUsize::new(0),
+            wait_ms: AtomicU64::new(0),
+            shed: AtomicUsize::new(0),
+        }
+    }
+
+    fn lane(&self, priority: QueuePriority) -> &QueueLane {
+        match priority {
+            QueuePriority::High => &self.lanes[0],
+            QueuePriority::Normal => &self.lanes[1],
+            QueuePriority::Low => &self.lanes[2],
+        }
+    }
+
+    /// How long a request turned away should wait before trying again, which is
+    /// how long the requests that have left the queue waited on average.
+    fn retry_after_ms(&self) -> u64 {
+        let dequeued = self.dequeued.load(Ordering::Relaxed) as u64;
+
+        if dequeued == 0 {
+            return DEFAULT_QUEUE_RETRY_AFTER_MS;
+        }
+
+        (self.wait_ms.load(Ordering::Relaxed) / dequeued).max(1)
+    }
+
+    /// Puts a request in the queue, unless there are already as many waiting
+    /// as `maybe_max_len` allows for its priority. The request leaves the
+    /// queue when the returned ticket is dropped.
+    fn enter(
+        self: &Arc<Self>,
+        priority: QueuePriority,
+        maybe_max_len: Option<usize>,
+    ) -> Option<QueueTicket> {
+        // NOTE: Lower priorities are turned away while there is still room
+        // left, so that the rest of the queue is kept for the higher ones.
+        let maybe_limit = maybe_max_len.map(|it| match priority {
+            QueuePriority::High => it,
+            QueuePriority::Normal => it - it / 4,
+            QueuePriority::Low => it - it / 2,
+        });
+
+        let entered = self
+            .len
+            .fetch_update(
+                Ordering::AcqRel,
+                Ordering::Acquire,
+                |len| match maybe_limit {
+                    Some(limit) if len >= limit => None,
+                    _ => Some(len + 1),
+                },
+            )
+            .is_ok();
+
+        if !entered {
+            self.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let (notify_tx, notify_rx) = flume::bounded(1);
+
+        self.lane(priority)
+            .waiters
+            .lock()
+            .unwrap()
+            .push_back(notify_tx.clone());
+
+        Some(QueueTicket {
+            queue: self.clone(),
+            priority,
+            entered_at: Instant::now(),
+            notify_tx,
+            notify_rx,
+        })
+    }
+
+    /// Lets the request of the highest priority that is waiting know that a
+    /// worker has freed up.
+    ///
+    /// Requests that have yet to handle a previous notification are passed
+    /// over. If there is no request left, the notification is dropped, as
+    /// nobody is waiting for it.
+    fn notify(&self, maybe_key: Option<Uuid>) {
+        for lane in self.lanes.iter() {
+            let waiters = lane.waiters.lock().unwrap();
+
+            if waiters.iter().any(|it| it.try_send(maybe_key).is_ok()) {
+                return;
+            }
+        }
+    }
+
+    /// Lets every waiting request know that a worker has gone away.
+    fn notify_all(&self) {
+        for lane in self.lanes.iter() {
+            for waiter in lane.waiters.lock().unwrap().iter() {
+                let _ = waiter.try_send(None);
+            }
+        }
+    }
+}
+
+struct QueueTicket {
+    queue: Arc<RequestQueue>,
+    priority: QueuePriority,
+    entered_at: Instant,
+    notify_tx: flume::Sender<Option<Uuid>>,
+    notify_rx: flume::Receiver<Option<Uuid>>,
+}
+
+impl QueueTicket {
+    /// Waits until a worker frees up. `Some` is the key of a worker that has
+    /// become idle, and `None` means a new worker may be created.
+    async fn notified(&self) -> Option<Uuid> {
+        // the ticket holds a sender of the channel, so it never disconnects.
+        self.notify_rx.recv_async().await.ok().flatten()
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        let queue = &self.queue;
+
+        queue.len.fetch_sub(1, Ordering::AcqRel);
+        queue
+            .lane(self.priority)
+            .waiters
+            .lock()
+            .unwrap()
+            .retain(|it| !it.same_channel(&self.notify_tx));
+
+        // a notification that came in after the request stopped waiting (e.g.
+        // it timed out) is handed on, so that it is not lost.
+        if let Ok(maybe_key) = self.notify_rx.try_recv() {
+            queue.notify(maybe_key);
+        }
+
+        queue.dequeued.fetch_add(1, Ordering::Relaxed);
+        queue.wait_ms.fetch_add(
+            self.entered_at.elapsed().as_millis() as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
 // Simple implementation of Round Robin for the Active Workers
 pub struct ActiveWorkerRegistry {
     workers: HashSet<WorkerId>,
     next: Option<usize>,
-    notify_pair: (flume::Sender<Option<Uuid>>, flume::Receiver<Option<Uuid>>),
+    queue: Arc<RequestQueue>,
     sem: Arc<Semaphore>,
 }
 
@@ -139,17 +379,26 @@ impl ActiveWorkerRegistry {
         Self {
             workers: HashSet::default(),
             next: Option::default(),
-            notify_pair: flume::unbounded(),
+            queue: Arc::new(RequestQueue::new()),
             sem: Arc::new(Semaphore::const_new(max_parallelism)),
         }
     }
 
-    fn mark_used_and_try_advance(&mut self, policy: SupervisorPolicy) -> Option<&Uuid> {
+    fn mark_used_and_try_advance(
+        &mut self,
+        policy: SupervisorPolicy,
+        routing: RoutingStrategy,
+        outstanding_fn: impl Fn(&Uuid) -> usize,
+    ) -> Option<&Uuid> {
         if self.workers.is_empty() {
             let _ = self.next.take();
             return None;
         }
 
+        if !matches!(routing, RoutingStrategy::RoundRobin) {
+            return self.mark_used_least_loaded(policy, routing, outstanding_fn);
+        }
+
         let len = self.workers.len();
         let idx = self
             .next
@@ -181,6 +430,53 @@ impl ActiveWorkerRegistry {
         }
     }
 
+    fn mark_used_least_loaded(
+        &mut self,
+        policy: SupervisorPolicy,
+        routing: RoutingStrategy,
+        outstanding_fn: impl Fn(&Uuid) -> usize,
+    ) -> Option<&Uuid> {
+        let candidates = self
+            .workers
+            .iter()
+            .filter(|it| it.1)
+            .map(|it| it.0)
+            .collect::<Vec<_>>();
+
+        let key = match routing {
+            RoutingStrategy::PowerOfTwoChoices if candidates.len() > 2 => {
+                let mut rng = rand::thread_rng();
+
+                candidates
+                    .choose_multiple(&mut rng, 2)
+                    .min_by_key(|it| outstanding_fn(it))
+                    .copied()
+            }
+
+            _ => {
+                // NOTE: Workers that are equally loaded take turns, rather
+                // than the first of them getting every request.
+                let start = self.next.unwrap_or(0) % candidates.len().max(1);
+
+                self.next = Some(start + 1);
+
+                candidates
+                    .iter()
+                    .cycle()
+                    .skip(start)
+                    .take(candidates.len())
+                    .min_by_key(|it| outstanding_fn(it))
+                    .copied()
+            }
+        }?;
+
+        if policy.is_per_request() {
+            let _ = self.workers.replace(WorkerId(key, false));
+        }
+
+        self.workers.get(&key).map(|it| &it.0)
+    }
+
     fn mark_idle(&mut self, key: &Uuid, policy: SupervisorPolicy) {
         if let Some(WorkerId(key, mark)) = self.workers.get(key).cloned() {
             if policy.is_per_request() {
@@ -191,12 +487,119 @@ impl ActiveWorkerRegistry {
                 let _ = self.workers.replace(WorkerId(key, true));
             }
 
-            let (notify_tx, _) = self.notify_pair.clone();
-            let _ = notify_tx.send(Some(key));
+            self.queue.notify(Some(key));
         }
     }
 }
 
+/// A service that is kept warm, i.e. for which a minimum number of workers
+/// is kept around even if no request is coming in.
+struct WarmService {
+    min_instances: usize,
+    /// Options the spare workers are booted with.
+    template: WorkerContextInitOpts,
+    termination_token: Option<TerminationToken>,
+    /// Spare workers that are still booting.
+    pending: HashSet<Uuid>,
+}
+
+/// Failures of a service in a row, and whether workers may be created for it
+/// in the meantime.
+struct Circuit {
+    state: CircuitState,
+    failures: usize,
+    /// How long the circuit stays open. `None` until it has opened once since
+    /// the service last recovered.
+    maybe_backoff: Option<Duration>,
+    /// When the circuit opened, or when the last probe was let through.
+    since: Instant,
+}
+
+impl Circuit {
+    fn new() -> Self {
+        Self {
+            state: CircuitState::Closed,
+            failures: 0,
+            maybe_backoff: None,
+            since: Instant::now(),
+        }
+    }
+
+    fn retry_after(&self) -> Duration {
+        match self.state {
+            CircuitState::Closed => Duration::ZERO,
+            CircuitState::Open | CircuitState::HalfOpen => self
+                .maybe_backoff
+                .unwrap_or_default()
+                .saturating_sub(self.since.elapsed()),
+        }
+    }
+
+    /// Decides whether a worker may be created for the service. Returns
+    /// whether the state of the circuit has changed.
+    fn try_pass(&mut self) -> Result<bool, WorkerError> {
+        if self.state == CircuitState::Closed {
+            return Ok(false);
+        }
+
+        let retry_after = self.retry_after();
+
+        if !retry_after.is_zero() {
+            return Err(WorkerError::CircuitOpen {
+                retry_after_ms: retry_after.as_millis() as u64,
+            });
+        }
+
+        // NOTE: A single worker is let through as a probe. Should it go
+        // missing, e.g. because its request timed out before it got to boot,
+        // another one is let through once the backoff has passed again.
+        let is_changed = self.state == CircuitState::Open;
+
+        self.state = CircuitState::HalfOpen;
+        self.since = Instant::now();
+
+        Ok(is_changed)
+    }
+
+    /// Returns whether the circuit has opened.
+    fn fail(&mut self, policy: &CircuitBreakerPolicy) -> bool {
+        self.failures += 1;
+
+        let should_open = match self.state {
+            CircuitState::Closed => self.failures >= policy.threshold,
+            CircuitState::HalfOpen => true,
+            CircuitState::Open => false,
+        };
+
+        if !should_open {
+            return false;
+        }
+
+        self.state = CircuitState::Open;
+        self.since = Instant::now();
+        self.maybe_backoff = Some(match self.maybe_backoff {
+            Some(it) => it.saturating_mul(2).min(policy.max_backoff),
+            None => policy.backoff,
+        });
+
+        true
+    }
+
+    /// Returns whether the circuit has closed.
+    fn booted(&mut self) -> bool {
+        if self.state != CircuitState::HalfOpen {
+            return false;
+        }
+
+        // NOTE: The failures are kept, so that the circuit opens again, for
+        // longer, with the first one after the probe. They are only forgotten
+        // once a worker of the service has exited cleanly.
+        self.state = CircuitState::Closed;
+
+        true
+    }
+}
+
 // every new worker gets a new UUID (can reuse execution_id)
 // user_workers - maintain a hashmap of (uuid - workerProfile (include service path))
 // active_workers - hashmap of (service_path - uuid)
@@ -216,6 +619,9 @@ pub struct WorkerPool {
 
     // TODO: refactor this out of worker pool
     pub worker_event_sender: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+
+    warm_services: HashMap<String, WarmService>,
+    circuits: HashMap<String, Circuit>,
 }
 
 impl WorkerPool {
@@ -236,6 +642,8 @@ impl WorkerPool {
             active_workers: HashMap::new(),
             maybe_inspector: inspector,
             worker_pool_msgs_tx,
+            warm_services: HashMap::new(),
+            circuits: HashMap::new(),
         }
     }
 
@@ -252,12 +660,31 @@ impl WorkerPool {
             .to_string();
 
         let is_oneshot_policy = self.policy.supervisor_policy.is_oneshot();
-        let inspector = self.maybe_inspector.clone();
+        let priority = worker_options
+            .conf
+            .as_user_worker()
+            .map(|it| it.queue_priority)
+            .unwrap_or_default();
+
         let force_create = worker_options
             .conf
             .as_user_worker()
             .map_or(false, |it| !is_oneshot_policy && it.force_create);
 
+        // the spare workers of a service are booted with the options it was
+        // last asked for with.
+        let maybe_min_instances = worker_options
+            .conf
+            .as_user_worker()
+            .and_then(|it| it.min_instances)
+            .map(|it| {
+                (
+                    it,
+                    clone_worker_options(&worker_options),
+                    termination_token.clone(),
+                )
+            });
+
         if let Some(ref active_worker_uuid) = self.maybe_active_worker(&service_path, force_create)
         {
             if tx
@@ -268,6 +695,20 @@ impl WorkerPool {
             {
                 error!("main worker receiver dropped")
             }
+
+            if let Some((min_instances, maybe_template, token)) = maybe_min_instances {
+                self.set_min_instances(&service_path, min_instances, maybe_template, token);
+                self.replenish(&service_path);
+            }
+
+            return;
+        }
+
+        if let Err(err) = self.try_pass_circuit(&service_path) {
+            if tx.send(Err(anyhow!(err))).is_err() {
+                error!("main worker receiver dropped")
+            }
+
             return;
         }
 
@@ -280,46 +721,74 @@ impl WorkerPool {
             ),
         }
 
-        let wait_fence_fut = {
-            let registry = self
-                .active_workers
-                .entry(service_path.clone())
-                .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
+        enum Fence {
+            Passed(FlowAfterFence),
+            Wait(QueueTicket, Sender<Result<CreateUserWorkerResult, Error>>),
+        }
 
-            let sem = registry.sem.clone();
-            let (_, notify_rx) = registry.notify_pair.clone();
+        let registry = self
+            .active_workers
+            .entry(service_path.clone())
+            .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
+
+        let sem = registry.sem.clone();
+
+        // NOTE: The request joins the queue right here, in the loop of the
+        // pool, which is also where the notifications are sent from. That way,
+        // none of them can be sent after the request has found no permit, but
+        // before it has started waiting.
+        let fence = {
+            use FlowAfterFence::*;
+
+            match sem.clone().try_acquire_owned() {
+                Ok(permit) => Fence::Passed(Create(Some(permit), tx)),
+                Err(TryAcquireError::NoPermits) if force_create => {
+                    // NOTE(Nyannyacha): Do we need to consider counting the
+                    // permit count (that means it affects maximum
+                    // parallelism) if in the force creation mode?
+                    Fence::Passed(Create(None, tx))
+                }
+
+                _ => match registry
+                    .queue
+                    .enter(priority, self.policy.max_queued_requests)
+                {
+                    Some(ticket) => Fence::Wait(ticket, tx),
+                    None => {
+                        if tx
+                            .send(Err(anyhow!(WorkerError::RequestQueueFull {
+                                retry_after_ms: registry.queue.retry_after_ms(),
+                            })))
+                            .is_err()
+                        {
+                            error!("main worker receiver dropped");
+                        }
+
+                        Fence::Passed(Stop)
+                    }
+                },
+            }
+        };
+
+        let wait_fence_fut = {
             let wait_timeout =
                 tokio::time::sleep(Duration::from_millis(self.policy.request_wait_timeout_ms));
 
             async move {
                 use FlowAfterFence::*;
 
-                match sem.clone().try_acquire_owned() {
-                    Ok(permit) => return Create(Some(permit), tx),
-                    Err(TryAcquireError::NoPermits) if force_create => {
-                        // NOTE(Nyannyacha): Do we need to consider counting the
-                        // permit count (that means it affects maximum
-                        // parallelism) if in the force creation mode?
-                        return Create(None, tx);
-                    }
-
-                    _ => {}
-                }
+                let (ticket, tx) = match fence {
+                    Fence::Passed(flow) => return flow,
+                    Fence::Wait(ticket, tx) => (ticket, tx),
+                };
 
                 tokio::pin!(wait_timeout);
                 loop {
                     tokio::select! {
-                        maybe_key = notify_rx.recv_async() => {
+                        maybe_key = ticket.notified() => {
                             match maybe_key {
-                                Err(x) => {
-                                    if tx.send(Err(anyhow!("worker channel is no longer valid: {}", x))).is_err() {
-                                        error!("main worker receiver dropped");
-                                    }
-                                    return Stop;
-                                }
-
-                                Ok(Some(_)) => return Resend(tx),
-                                Ok(None) => {
+                                Some(_) => return Resend(tx),
+                                None => {
                                     if let Ok(permit) = sem.clone().try_acquire_owned() {
                                         return Create(Some(permit), tx);
                                     }
@@ -338,10 +807,9 @@ impl WorkerPool {
             }
         };
 
-        let flags = self.flags.clone();
+        let booter = self.booter();
         let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
-        let events_msg_tx = self.worker_event_sender.clone();
-        let supervisor_policy = self.policy.supervisor_policy;
+        let boot_service_path = service_path.clone();
 
         drop(tokio::spawn(async move {
             let (permit, tx) = match wait_fence_fut.await {
@@ -395,94 +863,84 @@ impl WorkerPool {
                 FlowAfterFence::Create(permit, tx) => (permit, tx),
             };
 
-            let Ok(mut user_worker_rt_opts) = worker_options.conf.into_user_worker() else {
-                return;
-            };
-
-            let uuid = uuid::Uuid::new_v4();
-            let cancel = CancellationToken::new();
-            let (req_start_timing_tx, req_start_timing_rx) =
-                mpsc::unbounded_channel::<Arc<Notify>>();
-
-            let status = TimingStatus {
-                demand: Arc::new(AtomicUsize::new(0)),
-                is_retired: Arc::new(AtomicFlag::default()),
-            };
-
-            let (req_end_timing_tx, req_end_timing_rx) = mpsc::unbounded_channel::<()>();
-
-            user_worker_rt_opts.service_path = Some(service_path.clone());
-            user_worker_rt_opts.key = Some(uuid);
-
-            user_worker_rt_opts.pool_msg_tx = Some(worker_pool_msgs_tx.clone());
-            user_worker_rt_opts.events_msg_tx = events_msg_tx;
-            user_worker_rt_opts.cancel = Some(cancel.clone());
-
-            worker_options.timing = Some(Timing {
-                status: status.clone(),
-                req: (req_start_timing_rx, req_end_timing_rx),
-            });
-
-            worker_options.conf = WorkerRuntimeOpts::UserWorker(user_worker_rt_opts);
-
-            match create_worker(
-                flags,
-                (worker_options, supervisor_policy, termination_token.clone()),
-                inspector,
-            )
-            .await
+            match booter
+                .boot(Uuid::new_v4(), worker_options, permit, termination_token)
+                .await
             {
-                Ok(ctx) => {
-                    let profile = UserWorkerProfile {
-                        worker_request_msg_tx: ctx.msg_tx,
-                        timing_tx_pair: (req_start_timing_tx, req_end_timing_tx),
-                        service_path,
-                        permit: permit.map(Arc::new),
-                        status: status.clone(),
-                        exit: ctx.exit,
-                        cancel,
+                Ok((key, status)) => {
+                    if tx.send(Ok(CreateUserWorkerResult { key })).is_err() {
+                        error!("main worker receiver dropped")
                     };
 
+                    status.demand.fetch_add(1, Ordering::Release);
+                }
+                Err(err) => {
+                    error!("{err:#}");
+
+                    // NOTE: The pool is told first, so that the failure is
+                    // counted before another worker can be asked for.
                     if worker_pool_msgs_tx
-                        .send(UserWorkerMsgs::Created(uuid, profile))
+                        .send(UserWorkerMsgs::BootFailed(boot_service_path))
                         .is_err()
                     {
                         error!("user worker msgs receiver dropped")
                     }
-                    if tx.send(Ok(CreateUserWorkerResult { key: uuid })).is_err() {
-                        error!("main worker receiver dropped")
-                    };
 
-                    status.demand.fetch_add(1, Ordering::Release);
-                }
-                Err(err) => {
-                    error!("{err:#}");
                     if tx.send(Err(err)).is_err() {
                         error!("main worker receiver dropped")
                     }
                 }
             }
         }));
+
+        // NOTE: The service is topped up once the worker above has been
+        // added, so that it counts towards the minimum.
+        if let Some((min_instances, maybe_template, token)) = maybe_min_instances {
+            self.set_min_instances(&service_path, min_instances, maybe_template, token);
+        }
     }
 
     pub fn add_user_worker(&mut self, key: Uuid, profile: UserWorkerProfile) {
+        let service_path = profile.service_path.clone();
         let registry = self
             .active_workers
-            .entry(profile.service_path.clone())
+            .entry(service_path.clone())
             .or_insert_with(|| ActiveWorkerRegistry::new(self.policy.max_parallelism));
 
-        registry
-            .workers
-            .insert(WorkerId(key, self.policy.supervisor_policy.is_per_worker()));
+        // a spare worker has no request to serve yet, so it is idle from the
+        // start whatever the policy.
+        let is_spare = self
+            .warm_services
+            .get_mut(&service_path)
+            .is_some_and(|it| it.pending.remove(&key));
+
+        registry.workers.insert(WorkerId(
+            key,
+            is_spare || self.policy.supervisor_policy.is_per_worker(),
+        ));
+
+        if is_spare {
+            registry.queue.notify(Some(key));
+        }
 
         self.user_workers.insert(key, profile);
         self.metric_src.incl_active_user_workers();
+
+        if self
+            .circuits
+            .get_mut(&service_path)
+            .is_some_and(Circuit::booted)
+        {
+            self.send_circuit_event(&service_path);
+        }
+
+        self.replenish(&service_path);
     }
 
     pub fn send_request(
         &self,
         key: &Uuid,
-        req: Request<Body>,
+        mut req: Request<Body>,
         res_tx: Sender<Result<SendRequestResult, Error>>,
         conn_token: Option<CancellationToken>,
     ) {
@@ -493,6 +951,11 @@ impl WorkerPool {
                 let exit = worker.exit.clone();
                 let cancel = worker.cancel.clone();
                 let (req_start_tx, req_end_tx) = profile.timing_tx_pair.clone();
+                let maybe_body_limit = profile
+                    .max_request_body_bytes
+                    .map(|it| limit_request_body(&mut req, it));
+
+                let request_id = get_request_id(&req).map(str::to_string);
 
                 // Create a closure to handle the request and send the response
                 let request_handler = async move {
@@ -534,14 +997,39 @@ impl WorkerPool {
                         }
                     }
 
-                    let result = send_user_worker_request(
-                        profile.worker_request_msg_tx,
-                        req,
-                        cancel,
-                        exit,
-                        conn_token,
-                    )
-                    .await;
+                    // NOTE: A request refused by its `Content-Length` never
+                    // reaches the worker, but it has already been counted as
+                    // the demand of the worker, so its end must be reported
+                    // just like any other.
+                    let maybe_body_limit = match maybe_body_limit.transpose() {
+                        Ok(it) => it,
+                        Err(err) => {
+                            return Ok((err.to_response(request_id.as_deref(), false), req_end_tx))
+                        }
+                    };
+
+                    let body_limit_fut = async {
+                        match maybe_body_limit {
+                            Some(limit) => limit.exceeded().await,
+                            None => pending().await,
+                        }
+                    };
+
+                    let result = tokio::select! {
+                        biased;
+
+                        err = body_limit_fut => {
+                            Ok(err.to_response(request_id.as_deref(), false))
+                        }
+
+                        result = send_user_worker_request(
+                            profile.worker_request_msg_tx,
+                            req,
+                            cancel,
+                            exit,
+                            conn_token,
+                        ) => result,
+                    };
 
                     match result {
                         Ok(req) => Ok((req, req_end_tx)),
@@ -589,18 +1077,76 @@ impl WorkerPool {
     pub fn shutdown(&mut self, key: &Uuid) {
         self.retire(key);
 
-        let Some((notify_tx, _)) = self
-            .user_workers
-            .remove(key)
-            .and_then(|it| self.active_workers.get(&it.service_path))
-            .map(|it| it.notify_pair.clone())
-        else {
+        let Some(profile) = self.user_workers.remove(key) else {
+            // NOTE: A spare worker that failed to boot is reported as shut
+            // down, so that another one can take its place.
+            let maybe_service_path =
+                self.warm_services
+                    .iter_mut()
+                    .find_map(|(service_path, it)| {
+                        it.pending.remove(key).then(|| service_path.clone())
+                    });
+
+            if let Some(service_path) = maybe_service_path {
+                self.replenish(&service_path);
+            }
+
             return;
         };
 
-        let _ = notify_tx.send(None);
+        if let Some(registry) = self.active_workers.get(&profile.service_path) {
+            registry.queue.notify(None);
+        }
 
         self.metric_src.decl_active_user_workers();
+
+        if profile.exit.is_uncaught_exception() {
+            self.fail_circuit(&profile.service_path);
+        } else if self
+            .circuits
+            .get(&profile.service_path)
+            .is_some_and(|it| it.state == CircuitState::Closed)
+        {
+            // the service has recovered.
+            self.circuits.remove(&profile.service_path);
+        }
+
+        self.replenish(&profile.service_path);
+    }
+
+    pub fn boot_failed(&mut self, service_path: &str) {
+        self.fail_circuit(service_path);
+    }
+
+    /// Stops keeping services warm, e.g. because the pool is shutting down.
+    pub fn clear_min_instances(&mut self) {
+        self.warm_services.clear();
+    }
+
+    pub fn service_stats(&self) -> HashMap<String, UserWorkerServiceStats> {
+        let mut stats = HashMap::<String, UserWorkerServiceStats>::new();
+
+        for profile in self.user_workers.values() {
+            let entry = stats.entry(profile.service_path.clone()).or_default();
+
+            if profile.status.is_retired.is_raised() {
+                entry.retired += 1;
+            } else {
+                entry.active += 1;
+            }
+        }
+
+        for (service_path, registry) in self.active_workers.iter() {
+            let entry = stats.entry(service_path.clone()).or_default();
+            let queue = &registry.queue;
+
+            entry.queued = queue.len.load(Ordering::Relaxed);
+            entry.dequeued = queue.dequeued.load(Ordering::Relaxed);
+            entry.queue_wait_ms = queue.wait_ms.load(Ordering::Relaxed);
+            entry.shed = queue.shed.load(Ordering::Relaxed);
+        }
+
+        stats
     }
 
     fn retire(&mut self, key: &Uuid) {
@@ -611,19 +1157,200 @@ impl WorkerPool {
                 .expect("registry must be initialized at this point");
 
             let _ = profile.permit.take();
-            let (notify_tx, _) = registry.notify_pair.clone();
 
-            for _ in 0..notify_tx.receiver_count() {
-                let _ = notify_tx.send(None);
-            }
+            registry.queue.notify_all();
 
             if registry.workers.contains(key) {
                 registry.workers.remove(key);
                 self.metric_src.incl_retired_user_worker();
+
+                let service_path = profile.service_path.clone();
+
+                self.replenish(&service_path);
             }
         }
     }
 
+    fn try_pass_circuit(&mut self, service_path: &str) -> Result<(), WorkerError> {
+        let Some(circuit) = self.circuits.get_mut(service_path) else {
+            return Ok(());
+        };
+
+        if circuit.try_pass()? {
+            self.send_circuit_event(service_path);
+        }
+
+        Ok(())
+    }
+
+    fn fail_circuit(&mut self, service_path: &str) {
+        let Some(policy) = self.policy.circuit_breaker else {
+            return;
+        };
+
+        let is_opened = self
+            .circuits
+            .entry(service_path.to_string())
+            .or_insert_with(Circuit::new)
+            .fail(&policy);
+
+        if is_opened {
+            self.send_circuit_event(service_path);
+        }
+    }
+
+    fn send_circuit_event(&self, service_path: &str) {
+        let Some(circuit) = self.circuits.get(service_path) else {
+            return;
+        };
+
+        if circuit.state == CircuitState::Open {
+            warn!(
+                "not creating workers of {} for {}ms: it has failed {} times in a row",
+                service_path,
+                circuit.retry_after().as_millis(),
+                circuit.failures
+            );
+        }
+
+        send_event_if_event_worker_available(
+            self.worker_event_sender.as_ref(),
+            WorkerEvents::CircuitStateChanged(CircuitStateChangedEvent {
+                state: circuit.state,
+                failures: circuit.failures,
+                retry_after_ms: circuit.retry_after().as_millis() as u64,
+            }),
+            EventMetadata {
+                service_path: Some(service_path.to_string()),
+                ..Default::default()
+            },
+        );
+    }
+
+    fn set_min_instances(
+        &mut self,
+        service_path: &str,
+        min_instances: usize,
+        maybe_template: Option<WorkerContextInitOpts>,
+        termination_token: Option<TerminationToken>,
+    ) {
+        let maybe_prev = self.warm_services.remove(service_path);
+
+        if min_instances == 0 {
+            return;
+        }
+
+        let Some(template) = maybe_template else {
+            warn!(
+                "not keeping workers of {} warm: only workers loaded from a service path can be",
+                service_path
+            );
+
+            return;
+        };
+
+        self.warm_services.insert(
+            service_path.to_string(),
+            WarmService {
+                min_instances,
+                template,
+                termination_token,
+                pending: maybe_prev.map(|it| it.pending).unwrap_or_default(),
+            },
+        );
+    }
+
+    /// Boots spare workers until the service has as many as its minimum, as
+    /// far as the permits of the service allow.
+    fn replenish(&mut self, service_path: &str) {
+        // NOTE: Only the probe may boot while the circuit of the service isn't
+        // closed.
+        if self
+            .circuits
+            .get(service_path)
+            .is_some_and(|it| it.state != CircuitState::Closed)
+        {
+            return;
+        }
+
+        let booter = self.booter();
+        let Some(warm) = self.warm_services.get_mut(service_path) else {
+            return;
+        };
+
+        let Some(registry) = self.active_workers.get(service_path) else {
+            return;
+        };
+
+        let live = registry
+            .workers
+            .iter()
+            .filter(|it| {
+                self.user_workers
+                    .get(&it.0)
+                    .is_some_and(|it| !it.status.is_retired.is_raised())
+            })
+            .count();
+
+        for _ in (live + warm.pending.len())..warm.min_instances {
+            let Ok(permit) = registry.sem.clone().try_acquire_owned() else {
+                break;
+            };
+
+            let Some(worker_options) = clone_worker_options(&warm.template) else {
+                break;
+            };
+
+            let key = Uuid::new_v4();
+            let booter = booter.clone();
+            let worker_pool_msgs_tx = self.worker_pool_msgs_tx.clone();
+            let service_path = service_path.to_string();
+            let termination_token = warm
+                .termination_token
+                .as_ref()
+                .map(TerminationToken::child_token);
+
+            warm.pending.insert(key);
+
+            drop(tokio::spawn(async move {
+                if let Err(err) = booter
+                    .boot(key, worker_options, Some(permit), termination_token)
+                    .await
+                {
+                    error!("failed to boot a spare worker: {err:#}");
+
+                    if worker_pool_msgs_tx
+                        .send(UserWorkerMsgs::BootFailed(service_path))
+                        .is_err()
+                    {
+                        error!("user worker msgs receiver dropped")
+                    }
+
+                    // keeps a service that can't boot from being retried
+                    // over and over.
+                    tokio::time::sleep(SPARE_WORKER_RETRY_DELAY).await;
+
+                    if worker_pool_msgs_tx
+                        .send(UserWorkerMsgs::Shutdown(key))
+                        .is_err()
+                    {
+                        error!("user worker msgs receiver dropped")
+                    }
+                }
+            }));
+        }
+    }
+
+    fn booter(&self) -> UserWorkerBooter {
+        UserWorkerBooter {
+            flags: self.flags.clone(),
+            supervisor_policy: self.policy.supervisor_policy,
+            worker_pool_msgs_tx: self.worker_pool_msgs_tx.clone(),
+            events_msg_tx: self.worker_event_sender.clone(),
+            inspector: self.maybe_inspector.clone(),
+        }
+    }
+
     fn maybe_active_worker(&mut self, service_path: &String, force_create: bool) -> Option<Uuid> {
         if force_create {
             return None;
@@ -631,8 +1358,18 @@ impl WorkerPool {
 
         let registry = self.active_workers.get_mut(service_path)?;
         let policy = self.policy.supervisor_policy;
-
-        let mut advance_fn = move || registry.mark_used_and_try_advance(policy).copied();
+        let routing = self.policy.routing;
+        let user_workers = &self.user_workers;
+
+        let mut advance_fn = move || {
+            registry
+                .mark_used_and_try_advance(policy, routing, |key| {
+                    user_workers
+                        .get(key)
+                        .map_or(0, |it| it.status.outstanding())
+                })
+                .copied()
+        };
         let worker_uuid = advance_fn()?;
 
         match self
@@ -657,3 +1394,241 @@ impl WorkerPool {
         }
     }
 }
+
+fn get_request_id(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|it| it.to_str().ok())
+}
+
+/// Everything needed to boot a user worker away from the pool.
+#[derive(Clone)]
+struct UserWorkerBooter {
+    flags: Arc<ServerFlags>,
+    supervisor_policy: SupervisorPolicy,
+    worker_pool_msgs_tx: mpsc::UnboundedSender<UserWorkerMsgs>,
+    events_msg_tx: Option<mpsc::UnboundedSender<WorkerEventWithMetadata>>,
+    inspector: Option<Inspector>,
+}
+
+impl UserWorkerBooter {
+    /// Boots a user worker under `key`, and hands it over to the pool once it
+    /// is up.
+    async fn boot(
+        self,
+        key: Uuid,
+        mut worker_options: WorkerContextInitOpts,
+        permit: Option<OwnedSemaphorePermit>,
+        termination_token: Option<TerminationToken>,
+    ) -> Result<(Uuid, TimingStatus), Error> {
+        let service_path = worker_options
+            .service_path
+            .to_str()
+            .unwrap_or("")
+            .to_string();
+
+        let Ok(mut user_worker_rt_opts) = worker_options.conf.into_user_worker() else {
+            bail!("expected the options of a user worker");
+        };
+
+        let cancel = CancellationToken::new();
+        let (req_start_timing_tx, req_start_timing_rx) = mpsc::unbounded_channel::<Arc<Notify>>();
+
+        let status = TimingStatus {
+            demand: Arc::new(AtomicUsize::new(0)),
+            served: Arc::new(AtomicUsize::new(0)),
+            is_retired: Arc::new(AtomicFlag::default()),
+        };
+
+        let (req_end_timing_tx, req_end_timing_rx) = mpsc::unbounded_channel::<()>();
+
+        let max_request_body_bytes = user_worker_rt_opts.max_request_body_bytes;
+
+        user_worker_rt_opts.service_path = Some(service_path.clone());
+        user_worker_rt_opts.key = Some(key);
+
+        user_worker_rt_opts.pool_msg_tx = Some(self.worker_pool_msgs_tx.clone());
+        user_worker_rt_opts.events_msg_tx = self.events_msg_tx;
+        user_worker_rt_opts.cancel = Some(cancel.clone());
+
+        worker_options.timing = Some(Timing {
+            status: status.clone(),
+            req: (req_start_timing_rx, req_end_timing_rx),
+        });
+
+        worker_options.conf = WorkerRuntimeOpts::UserWorker(user_worker_rt_opts);
+
+        let ctx = create_worker(
+            self.flags,
+            (worker_options, self.supervisor_policy, termination_token),
+            self.inspector,
+        )
+        .await?;
+
+        let profile = UserWorkerProfile {
+            worker_request_msg_tx: ctx.msg_tx,
+            timing_tx_pair: (req_start_timing_tx, req_end_timing_tx),
+            service_path,
+            max_request_body_bytes,
+            permit: permit.map(Arc::new),
+            status: status.clone(),
+            exit: ctx.exit,
+            cancel,
+        };
+
+        if self
+            .worker_pool_msgs_tx
+            .send(UserWorkerMsgs::Created(key, profile))
+            .is_err()
+        {
+            error!("user worker msgs receiver dropped")
+        }
+
+        Ok((key, status))
+    }
+}
+
+/// Copies the options of a user worker, so that more workers can be booted
+/// with them later. Returns `None` for a worker whose code was handed over
+/// along with its options, as that can't be copied.
+fn clone_worker_options(opts: &WorkerContextInitOpts) -> Option<WorkerContextInitOpts> {
+    if opts.maybe_eszip.is_some() || opts.maybe_module_code.is_some() {
+        return None;
+    }
+
+    Some(WorkerContextInitOpts {
+        service_path: opts.service_path.clone(),
+        no_module_cache: opts.no_module_cache,
+        env_vars: opts.env_vars.clone(),
+        conf: WorkerRuntimeOpts::UserWorker(opts.conf.as_user_worker()?.clone()),
+        static_patterns: opts.static_patterns.clone(),
+        import_map_path: opts.import_map_path.clone(),
+        timing: None,
+        maybe_eszip: None,
+        maybe_module_code: None,
+        maybe_entrypoint: opts.maybe_entrypoint.clone(),
+        maybe_decorator: opts.maybe_decorator,
+        maybe_jsx_import_source_config: opts.maybe_jsx_import_source_config.clone(),
+        maybe_s3_fs_config: opts.maybe_s3_fs_config.clone(),
+        maybe_tmp_fs_config: opts.maybe_tmp_fs_config.clone(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use uuid::Uuid;
+
+    use super::{ActiveWorkerRegistry, RoutingStrategy, SupervisorPolicy, WorkerId};
+
+    fn registry_with(loads: &[usize]) -> (ActiveWorkerRegistry, HashMap<Uuid, usize>) {
+        let mut registry = ActiveWorkerRegistry::new(loads.len());
+        let mut outstanding = HashMap::new();
+
+        for load in loads {
+            let key = Uuid::new_v4();
+
+            registry.workers.insert(WorkerId(key, true));
+            outstanding.insert(key, *load);
+        }
+
+        (registry, outstanding)
+    }
+
+    fn key_with_load(outstanding: &HashMap<Uuid, usize>, load: usize) -> Uuid {
+        *outstanding.iter().find(|(_, it)| **it == load).unwrap().0
+    }
+
+    #[test]
+    fn test_least_outstanding_picks_least_loaded() {
+        let (mut registry, outstanding) = registry_with(&[2, 0, 1]);
+
+        for _ in 0..10 {
+            let key = registry
+                .mark_used_least_loaded(
+                    SupervisorPolicy::PerWorker,
+                    RoutingStrategy::LeastOutstanding,
+                    |it| outstanding[it],
+                )
+                .copied();
+
+            assert_eq!(key, Some(key_with_load(&outstanding, 0)));
+        }
+    }
+
+    #[test]
+    fn test_least_outstanding_takes_turns_when_equally_loaded() {
+        let (mut registry, outstanding) = registry_with(&[0, 0, 0]);
+        let mut picked = (0..3)
+            .filter_map(|_| {
+                registry
+                    .mark_used_least_loaded(
+                        SupervisorPolicy::PerWorker,
+                        RoutingStrategy::LeastOutstanding,
+                        |it| outstanding[it],
+                    )
+                    .copied()
+            })
+            .collect::<Vec<_>>();
+
+        picked.sort();
+        picked.dedup();
+
+        assert_eq!(picked.len(), 3);
+    }
+
+    #[test]
+    fn test_least_outstanding_skips_busy_workers_per_request() {
+        let (mut registry, outstanding) = registry_with(&[0, 1]);
+        let mut next = || {
+            registry
+                .mark_used_least_loaded(
+                    SupervisorPolicy::PerRequest { oneshot: false },
+                    RoutingStrategy::LeastOutstanding,
+                    |it| outstanding[it],
+                )
+                .copied()
+        };
+
+        // a worker serves a single request at a time under this policy.
+        assert_eq!(next(), Some(key_with_load(&outstanding, 0)));
+        assert_eq!(next(), Some(key_with_load(&outstanding, 1)));
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn test_power_of_two_choices_never_picks_most_loaded() {
+        let (mut registry, outstanding) = registry_with(&[0, 1, 2]);
+        let most_loaded = key_with_load(&outstanding, 2);
+
+        // whichever two workers are drawn, the most loaded one loses.
+        for _ in 0..100 {
+            let key = registry
+                .mark_used_least_loaded(
+                    SupervisorPolicy::PerWorker,
+                    RoutingStrategy::PowerOfTwoChoices,
+                    |it| outstanding[it],
+                )
+                .copied();
+
+            assert!(key.is_some());
+            assert_ne!(key, Some(most_loaded));
+        }
+    }
+
+    #[test]
+    fn test_power_of_two_choices_with_two_workers_picks_least_loaded() {
+        let (mut registry, outstanding) = registry_with(&[1, 0]);
+
+        let key = registry
+            .mark_used_least_loaded(
+                SupervisorPolicy::PerWorker,
+                RoutingStrategy::PowerOfTwoChoices,
+                |it| outstanding[it],
+            )
+            .copied();
+
+        assert_eq!(key, Some(key_with_load(&outstanding, 0)));
+    }
+}
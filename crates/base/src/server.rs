@@ -1,26 +1,41 @@
+use crate::access_log::AccessLogger;
+use crate::compression::Compressor;
+use crate::conn_limiter::{ConnGuard, ConnLimiter};
+use crate::health::{HealthCheck, Probe};
+use crate::http1::{serve_http1, status};
 use crate::inspector_server::Inspector;
+use crate::metrics_server::MetricsExporter;
+use crate::proxy_protocol::ProxyProtocolListener;
+use crate::rate_limiter::RateLimiter;
 use crate::rt_worker::worker_ctx::{
-    create_events_worker, create_main_worker, create_user_worker_pool, TerminationToken,
+    create_events_worker, create_main_worker, create_user_worker_pool, TerminationToken, WorkerCtx,
 };
 use crate::rt_worker::worker_pool::WorkerPoolPolicy;
+use crate::socket_activation::{self, HandoverFds};
+use crate::tls::{self, CertConfig, CertReloader, CertSource, ClientCertIdentity, TlsFiles};
 use crate::InspectorOption;
-use anyhow::{anyhow, bail, Context, Error};
+use anyhow::{bail, Context, Error};
+use arc_swap::ArcSwap;
 use deno_config::JsxImportSourceConfig;
 use enum_as_inner::EnumAsInner;
 use futures_util::future::{poll_fn, BoxFuture};
-use futures_util::{FutureExt, Stream};
+use futures_util::stream::BoxStream;
+use futures_util::{FutureExt, Stream, StreamExt};
+use http_utils::body_limit::limit_request_body;
+use http_utils::utils::emit_error_response;
+use http_v02::{header, HeaderMap, HeaderValue, Method, StatusCode, Uri, Version};
 use hyper_v014::{server::conn::Http, service::Service, Body, Request, Response};
+use ipnetwork::IpNetwork;
 use log::{debug, error, info, trace, warn};
-use rustls_pemfile::read_one_from_slice;
-use rustls_pemfile::Item;
-use sb_core::SharedMetricSource;
+use sb_core::net::ConnAddrs;
+use sb_core::{RuntimeMetricSource, SharedMetricSource};
 use sb_graph::DecoratorType;
 use sb_workers::context::{MainWorkerRuntimeOpts, WorkerRequestMsg};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::future::{pending, Future};
 use std::net::IpAddr;
-use std::net::Ipv4Addr;
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::str;
 use std::str::FromStr;
@@ -34,11 +49,18 @@ use tokio::pin;
 use tokio::sync::mpsc::{Sender, UnboundedSender};
 use tokio::sync::{mpsc, oneshot};
 use tokio::time::{sleep, timeout};
-use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::pki_types::CertificateDer;
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
 use tokio_rustls::TlsAcceptor;
 use tokio_util::sync::CancellationToken;
 use url::Url;
+use uuid::Uuid;
+
+pub use crate::access_log::{AccessLogConfig, AccessLogFormat, AccessLogTarget};
+pub use crate::compression::{CompressionConfig, ContentCoding};
+pub use crate::rate_limiter::{RateLimitConfig, RateLimitKey};
+pub use crate::socket_activation::ListenFds;
 
 mod signal {
     pub use tokio::signal::ctrl_c;
@@ -47,6 +69,78 @@ mod signal {
     pub use tokio::signal::unix;
 }
 
+#[cfg(unix)]
+mod unix_socket {
+    use std::io;
+    use std::os::unix::fs::FileTypeExt;
+    use std::path::Path;
+
+    use anyhow::{bail, Context};
+    use tokio::net::{UnixListener, UnixStream};
+
+    use crate::socket_activation::InheritedListeners;
+
+    pub type Listener = UnixListener;
+    pub type Stream = UnixStream;
+
+    pub fn take_inherited(listeners: &mut InheritedListeners) -> io::Result<Option<Listener>> {
+        listeners
+            .unix
+            .take()
+            .map(UnixListener::from_std)
+            .transpose()
+    }
+
+    pub fn bind(path: &Path) -> Result<Listener, anyhow::Error> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            // a socket file left behind by a previous process can be removed
+            // safely, but anything else at the path is not ours to replace.
+            if !metadata.file_type().is_socket() {
+                bail!(
+                    "can't bind to {}: the path exists and is not a socket",
+                    path.display()
+                );
+            }
+
+            std::fs::remove_file(path)?;
+        }
+
+        UnixListener::bind(path).with_context(|| format!("can't bind to {}", path.display()))
+    }
+
+    pub async fn accept(listener: Option<&Listener>) -> io::Result<Stream> {
+        match listener {
+            Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod unix_socket {
+    use std::io;
+    use std::path::Path;
+
+    use anyhow::bail;
+
+    use crate::socket_activation::InheritedListeners;
+
+    pub enum Listener {}
+    pub type Stream = tokio::io::DuplexStream;
+
+    pub fn take_inherited(_listeners: &mut InheritedListeners) -> io::Result<Option<Listener>> {
+        Ok(None)
+    }
+
+    pub fn bind(_path: &Path) -> Result<Listener, anyhow::Error> {
+        bail!("unix domain sockets are not supported on this platform")
+    }
+
+    pub async fn accept(_listener: Option<&Listener>) -> io::Result<Stream> {
+        std::future::pending().await
+    }
+}
+
 pub enum ServerEvent {
     ConnectionError(hyper_v014::Error),
     #[cfg(debug_assertions)]
@@ -59,6 +153,90 @@ pub enum ServerHealth {
     Failure,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HttpProtocol {
+    Http1,
+    Http2,
+    /// Serves HTTP/1, but switches to HTTP/2 if the connection starts with the
+    /// HTTP/2 connection preface. (h2c with prior knowledge)
+    Auto,
+}
+
+/// Name of the header that carries the server name a client asked for
+/// through SNI. Only set on requests received over TLS.
+pub const TLS_SERVER_NAME_HEADER: &str = "x-edge-runtime-tls-server-name";
+
+/// Names of the headers that carry the identity of a client certificate
+/// verified by mutual TLS.
+///
+/// The SAN header lists the subject alternative names separated by `, `, each
+/// prefixed with its type. (e.g. `DNS:example.com, URI:spiffe://example/foo`)
+/// The fingerprint is the lowercase hex-encoded SHA-256 digest of the DER
+/// encoded certificate.
+pub const CLIENT_CERT_SUBJECT_HEADER: &str = "x-edge-runtime-client-cert-subject";
+pub const CLIENT_CERT_SAN_HEADER: &str = "x-edge-runtime-client-cert-san";
+pub const CLIENT_CERT_FINGERPRINT_HEADER: &str = "x-edge-runtime-client-cert-fingerprint";
+
+/// Name of the header that carries the ID of a request.
+///
+/// Every request forwarded to the main worker has one, and it is echoed back
+/// on the response. An ID sent by the client is only kept if the runtime is
+/// told to trust it; otherwise a new one is generated.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request ID accepted from a client.
+const MAX_REQUEST_ID_LEN: usize = 200;
+
+/// Headers whose values are populated by the runtime itself, so the main
+/// worker can trust them. Anything a client sends under these names is
+/// discarded.
+const TRUSTED_HEADERS: &[&str] = &[
+    TLS_SERVER_NAME_HEADER,
+    CLIENT_CERT_SUBJECT_HEADER,
+    CLIENT_CERT_SAN_HEADER,
+    CLIENT_CERT_FINGERPRINT_HEADER,
+];
+
+/// Properties of a connection that are forwarded to the main worker with every
+/// request made over it.
+#[derive(Debug, Clone, Default)]
+struct ConnectionInfo {
+    /// Addresses of a TCP connection. If the PROXY protocol is enabled, the
+    /// remote address is the one decoded from the header rather than the
+    /// address of the peer.
+    addrs: Option<ConnAddrs>,
+    tls: bool,
+    tls_server_name: Option<String>,
+    client_cert: Option<Arc<ClientCertIdentity>>,
+}
+
+impl ConnectionInfo {
+    fn apply_trusted_headers(&self, headers: &mut HeaderMap) {
+        for name in TRUSTED_HEADERS {
+            headers.remove(*name);
+        }
+
+        let mut insert = |name: &'static str, value: &str| {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                headers.insert(name, value);
+            }
+        };
+
+        if let Some(server_name) = self.tls_server_name.as_deref() {
+            insert(TLS_SERVER_NAME_HEADER, server_name);
+        }
+
+        if let Some(cert) = self.client_cert.as_deref() {
+            insert(CLIENT_CERT_SUBJECT_HEADER, &cert.subject);
+            insert(CLIENT_CERT_FINGERPRINT_HEADER, &cert.fingerprint);
+
+            if !cert.sans.is_empty() {
+                insert(CLIENT_CERT_SAN_HEADER, &cert.sans.join(", "));
+            }
+        }
+    }
+}
+
 struct CancelOnDrop<S> {
     inner: S,
     cancel: Option<CancellationToken>,
@@ -87,12 +265,17 @@ impl<S: Stream + Unpin> Stream for CancelOnDrop<S> {
     }
 }
 
+/// Where requests for the main worker are sent. It points to another main
+/// worker once the main service has been reloaded.
+type MainWorkerReqTx = Arc<ArcSwap<mpsc::UnboundedSender<WorkerRequestMsg>>>;
+
 #[derive(Clone)]
 struct TerminationTokens {
     input: Option<TerminationToken>,
     event: Option<TerminationToken>,
     pool: TerminationToken,
-    main: TerminationToken,
+    /// The token of the main worker that is currently serving requests.
+    main: Arc<ArcSwap<TerminationToken>>,
 }
 
 impl TerminationTokens {
@@ -101,13 +284,13 @@ impl TerminationTokens {
             input: maybe_input,
             event: with_event.then(TerminationToken::new),
             pool: TerminationToken::new(),
-            main: TerminationToken::new(),
+            main: Arc::new(ArcSwap::from_pointee(TerminationToken::new())),
         }
     }
 
     async fn terminate(&self) {
         self.pool.cancel_and_wait().await;
-        self.main.cancel_and_wait().await;
+        self.main.load_full().cancel_and_wait().await;
 
         if let Some(token) = self.event.as_ref() {
             token.cancel_and_wait().await;
@@ -123,23 +306,177 @@ impl TerminationTokens {
     }
 }
 
+/// Everything [`create_main_worker`] needs, kept around so that the main
+/// worker can be created again.
+struct MainWorkerOpts {
+    flags: Arc<ServerFlags>,
+    path: PathBuf,
+    import_map_path: Option<String>,
+    runtime_opts: MainWorkerRuntimeOpts,
+    maybe_entrypoint: Option<String>,
+    maybe_decorator: Option<DecoratorType>,
+    maybe_inspector: Option<Inspector>,
+    maybe_jsx_config: Option<JsxImportSourceConfig>,
+}
+
+impl MainWorkerOpts {
+    async fn create(&self, termination_token: TerminationToken) -> Result<WorkerCtx, Error> {
+        create_main_worker(
+            self.flags.clone(),
+            self.path.clone(),
+            self.import_map_path.clone(),
+            self.flags.no_module_cache,
+            self.runtime_opts.clone(),
+            self.maybe_entrypoint.clone(),
+            self.maybe_decorator,
+            Some(termination_token),
+            self.maybe_inspector.clone(),
+            self.maybe_jsx_config.clone(),
+        )
+        .await
+    }
+}
+
+/// Replaces the main worker of a [`Server`] with a new one created from the
+/// same main service, leaving the user worker pool as it is.
+#[derive(Clone)]
+pub struct MainWorkerReloader {
+    opts: Arc<MainWorkerOpts>,
+    req_tx: MainWorkerReqTx,
+    termination_token: Arc<ArcSwap<TerminationToken>>,
+    maybe_runtime_metric_src: Option<Arc<ArcSwap<RuntimeMetricSource>>>,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl MainWorkerReloader {
+    /// Creates a new main worker and sends every request that comes in from
+    /// now on to it. The previous main worker is terminated once it is done
+    /// with the requests it has already received, or when the graceful exit
+    /// deadline expires.
+    ///
+    /// If the new main worker fails to boot, the current one stays in place.
+    pub async fn reload(&self) -> Result<(), Error> {
+        let _lock = self.lock.lock().await;
+        let termination_token = TerminationToken::new();
+        let ctx = self.opts.create(termination_token.clone()).await?;
+
+        if let Some(src) = self.maybe_runtime_metric_src.as_ref() {
+            if let Ok(runtime) = ctx.metric.into_runtime() {
+                src.store(Arc::new(runtime));
+            }
+        }
+
+        let prev_termination_token = self
+            .termination_token
+            .swap(Arc::new(termination_token.clone()));
+        let prev_req_tx = self.req_tx.swap(Arc::new(ctx.msg_tx));
+
+        // the server is shutting down, and won't know about the new worker.
+        if prev_termination_token.inbound.is_cancelled() {
+            termination_token.cancel();
+        }
+
+        let graceful_exit_deadline_sec = self.opts.flags.graceful_exit_deadline_sec;
+
+        tokio::spawn(async move {
+            static REQ_CHECK_SLEEP_DUR: Duration = Duration::from_millis(10);
+
+            // NOTE: Every request sent to a main worker holds on to its
+            // sender until the request has been handled (see
+            // `WorkerService::call`), so the sender being the last one left
+            // means there is nothing to wait for anymore.
+            let _ = timeout(Duration::from_secs(graceful_exit_deadline_sec), async {
+                while Arc::strong_count(&prev_req_tx) > 1 {
+                    sleep(REQ_CHECK_SLEEP_DUR).await;
+                }
+            })
+            .await;
+
+            prev_termination_token.cancel_and_wait().await;
+        });
+
+        Ok(())
+    }
+}
+
+const ADMIN_RELOAD_PATH: &str = "/reload";
+
+/// Serves the health checks on the admin listener, along with `POST /reload`,
+/// which does the same as sending SIGUSR1.
+async fn serve_admin(
+    listener: TcpListener,
+    token: CancellationToken,
+    health_check: HealthCheck,
+    reloader: MainWorkerReloader,
+) {
+    serve_http1(listener, token, "admin", move |req| {
+        let health_check = health_check.clone();
+        let reloader = reloader.clone();
+
+        async move {
+            if req.uri().path() == ADMIN_RELOAD_PATH {
+                if req.method() != Method::POST {
+                    return status(StatusCode::METHOD_NOT_ALLOWED);
+                }
+
+                return match reloader.reload().await {
+                    Ok(()) => {
+                        info!("main worker reloaded");
+                        status(StatusCode::OK)
+                    }
+
+                    Err(err) => {
+                        error!("failed to reload the main worker: {:#}", err);
+                        status(StatusCode::INTERNAL_SERVER_ERROR)
+                    }
+                };
+            }
+
+            match Probe::from_path(req.uri().path()) {
+                Some(probe) => health_check.respond(probe, req.method()).await,
+                None => status(StatusCode::NOT_FOUND),
+            }
+        }
+    })
+    .await
+}
+
+/// How [`WorkerService`] treats requests. This is the same for every
+/// connection.
+#[derive(Clone, Default)]
+struct WorkerServiceOpts {
+    maybe_hard_timeout_dur: Option<Duration>,
+    trust_request_id: bool,
+    maybe_rate_limiter: Option<Arc<RateLimiter>>,
+    maybe_access_logger: Option<AccessLogger>,
+    maybe_health_check: Option<HealthCheck>,
+    maybe_max_request_body_bytes: Option<u64>,
+    maybe_compressor: Option<Arc<Compressor>>,
+}
+
 struct WorkerService {
     metric_src: SharedMetricSource,
-    worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    worker_req_tx: MainWorkerReqTx,
+    conn_info: ConnectionInfo,
     cancel: CancellationToken,
+    opts: WorkerServiceOpts,
 }
 
 impl WorkerService {
     fn new(
         metric_src: SharedMetricSource,
-        worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+        worker_req_tx: MainWorkerReqTx,
+        conn_info: ConnectionInfo,
+        opts: WorkerServiceOpts,
     ) -> (Self, CancellationToken) {
         let cancel = CancellationToken::new();
         (
             Self {
                 metric_src,
                 worker_req_tx,
+                conn_info,
                 cancel: cancel.clone(),
+                opts,
             },
             cancel,
         )
@@ -155,12 +492,86 @@ impl Service<Request<Body>> for WorkerService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, req: Request<Body>) -> Self::Future {
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        if let Some(health_check) = self.opts.maybe_health_check.clone() {
+            if let Some(probe) = Probe::from_path(req.uri().path()) {
+                let method = req.method().clone();
+
+                return Box::pin(async move { Ok(health_check.respond(probe, &method).await) });
+            }
+        }
+
+        downgrade_h2_request(&mut req);
+        self.conn_info.apply_trusted_headers(req.headers_mut());
+
+        let request_id = assign_request_id(req.headers_mut(), self.opts.trust_request_id);
+        let request_id_header = req.headers().get(REQUEST_ID_HEADER).cloned();
+        let maybe_access_log_record = self.opts.maybe_access_logger.as_ref().map(|it| {
+            it.record(
+                &mut req,
+                self.conn_info.addrs.map(|it| it.remote),
+                self.conn_info.tls,
+                &request_id,
+            )
+        });
+
+        let maybe_retry_after = self.opts.maybe_rate_limiter.as_ref().and_then(|it| {
+            it.check(
+                req.uri(),
+                req.headers(),
+                self.conn_info.addrs.map(|it| it.remote.ip()),
+            )
+            .err()
+        });
+
+        let maybe_body_limit = self
+            .opts
+            .maybe_max_request_body_bytes
+            .map(|it| limit_request_body(&mut req, it));
+
+        let maybe_compression = self.opts.maybe_compressor.clone().map(|it| {
+            let coding = it.negotiate(req.method(), req.headers());
+            (it, coding)
+        });
+
         // create a response in a future.
         let cancel = self.cancel.child_token();
         let metric_src = self.metric_src.clone();
-        let worker_req_tx = self.worker_req_tx.clone();
+        let worker_req_tx = self.worker_req_tx.load_full();
+        let conn_addrs = self.conn_info.addrs;
+        let maybe_hard_timeout_dur = self.opts.maybe_hard_timeout_dur;
         let fut = async move {
+            if let Some(retry_after) = maybe_retry_after {
+                metric_src.incl_rate_limited_requests();
+
+                let mut res = emit_error_response(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "RateLimited",
+                    "too many requests",
+                    Some(&request_id),
+                    false,
+                );
+
+                // round up so that the client doesn't come back too early.
+                let retry_after_secs =
+                    retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+                res.headers_mut()
+                    .insert(header::RETRY_AFTER, HeaderValue::from(retry_after_secs));
+
+                return Ok(res);
+            }
+
+            let maybe_body_limit = match maybe_body_limit {
+                Some(Ok(limit)) => Some(limit),
+                Some(Err(err)) => {
+                    debug!("refused a request: {}", err);
+                    return Ok(err.to_response(Some(&request_id), true));
+                }
+
+                None => None,
+            };
+
             let (res_tx, res_rx) = oneshot::channel::<Result<Response<Body>, hyper_v014::Error>>();
 
             let req_uri = req.uri().clone();
@@ -168,37 +579,93 @@ impl Service<Request<Body>> for WorkerService {
                 req,
                 res_tx,
                 conn_token: Some(cancel.clone()),
+                conn_addrs,
             };
 
             worker_req_tx.send(msg)?;
             metric_src.incl_received_requests();
 
+            // NOTE: The request is considered handled once its token is
+            // cancelled, which happens when the response body is dropped, the
+            // connection goes away, or the hard timeout expires. Counting it
+            // only here keeps `handled_requests` balanced against
+            // `received_requests` on every path.
             tokio::spawn({
                 let metric_src_inner = metric_src.clone();
                 let cancel = cancel.clone();
 
                 async move {
-                    tokio::select! {
-                        _ = cancel.cancelled() => {
-                            metric_src_inner.incl_handled_requests();
-                        }
-                        // TODO: I think it would be good to introduce the hard
-                        // timeout here to prevent the requester's inability to get
-                        // the response for any reason.
-                    }
+                    cancel.cancelled().await;
+                    metric_src_inner.incl_handled_requests();
+
+                    // keeps the main worker from being terminated while it
+                    // still has the request. (see `MainWorkerReloader`)
+                    drop(worker_req_tx);
                 }
             });
 
-            let res = match res_rx.await {
-                Ok(res) => res,
-                Err(err) => {
-                    metric_src.incl_handled_requests();
+            let res_fut = async {
+                if let Some(dur) = maybe_hard_timeout_dur {
+                    timeout(dur, res_rx).await.ok()
+                } else {
+                    Some(res_rx.await)
+                }
+            };
+
+            let body_limit_fut = async {
+                match maybe_body_limit {
+                    Some(limit) => limit.exceeded().await,
+                    None => pending().await,
+                }
+            };
+
+            // NOTE: Once the body goes over the limit, the worker only gets to
+            // see a truncated body, so whatever it would respond is replaced.
+            // The rest of the body is left unread, hence the connection must
+            // be closed.
+            let maybe_res = tokio::select! {
+                biased;
+
+                err = body_limit_fut => {
+                    cancel.cancel();
+                    return Ok(err.to_response(Some(&request_id), true));
+                }
+
+                res = res_fut => res,
+            };
+
+            let res = match maybe_res {
+                Some(Ok(res)) => res,
+                Some(Err(err)) => {
+                    cancel.cancel();
                     return Err(err.into());
                 }
+
+                None => {
+                    error!(
+                        "request timed out (uri: {:?} client: {:?})",
+                        req_uri.to_string(),
+                        conn_addrs.map(|it| it.remote),
+                    );
+
+                    cancel.cancel();
+                    return Ok(emit_error_response(
+                        StatusCode::GATEWAY_TIMEOUT,
+                        "RequestTimedOut",
+                        "the request did not complete in time",
+                        Some(&request_id),
+                        false,
+                    ));
+                }
             };
 
             let res = match res {
                 Ok(res) => {
+                    let res = match maybe_compression {
+                        Some((compressor, coding)) => compressor.compress(coding, res),
+                        None => res,
+                    };
+
                     let (parts, body) = res.into_parts();
                     Response::from_parts(
                         parts,
@@ -211,27 +678,50 @@ impl Service<Request<Body>> for WorkerService {
 
                 Err(e) => {
                     error!(
-                        "request failed (uri: {:?} reason: {:?})",
+                        "request failed (uri: {:?} client: {:?} reason: {:?})",
                         req_uri.to_string(),
+                        conn_addrs.map(|it| it.remote),
                         e
                     );
 
-                    // FIXME: add an error body
-                    Response::builder()
-                        .status(http_v02::StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::wrap_stream(CancelOnDrop {
-                            inner: Body::empty(),
+                    let (parts, body) = emit_error_response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "WorkerRequestFailed",
+                        "the worker failed to handle the request",
+                        Some(&request_id),
+                        false,
+                    )
+                    .into_parts();
+
+                    Response::from_parts(
+                        parts,
+                        Body::wrap_stream(CancelOnDrop {
+                            inner: body,
                             cancel: Some(cancel),
-                        }))
-                        .unwrap()
+                        }),
+                    )
                 }
             };
 
-            Ok(res)
+            Ok::<_, Error>(res)
         };
 
         // Return the response as an immediate future
-        Box::pin(fut)
+        Box::pin(async move {
+            // NOTE: If the future fails or is dropped before a response is
+            // produced, the access log record is written without a status.
+            let mut res = fut.await?;
+
+            if let Some(value) = request_id_header {
+                res.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+
+            if let Some(record) = maybe_access_log_record {
+                res = record.attach(res);
+            }
+
+            Ok(res)
+        })
     }
 }
 
@@ -241,18 +731,39 @@ pub struct WorkerEntrypoints {
     pub events: Option<String>,
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone)]
 pub struct ServerFlags {
     pub no_module_cache: bool,
     pub allow_main_inspector: bool,
     pub tcp_nodelay: bool,
 
+    pub unix_socket: Option<PathBuf>,
+    pub unix_socket_only: bool,
+    pub h2c: bool,
+    pub proxy_protocol: bool,
+    pub proxy_protocol_trusted_ranges: Vec<IpNetwork>,
+    pub metrics_addr: Option<SocketAddr>,
+    pub admin_addr: Option<SocketAddr>,
+    pub health_on_main_port: bool,
+    pub trust_request_id: bool,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub rate_limit: Option<RateLimitConfig>,
+    pub access_log: Option<AccessLogConfig>,
+    pub max_request_body_bytes: Option<u64>,
+    pub compression: Option<CompressionConfig>,
+    pub listener_handover: bool,
+    pub listen_fds: ListenFds,
+
     pub graceful_exit_deadline_sec: u64,
     pub graceful_exit_keepalive_deadline_ms: Option<u64>,
     pub event_worker_exit_deadline_sec: u64,
     pub request_wait_timeout_ms: Option<u64>,
+    pub max_queued_requests: Option<usize>,
     pub request_idle_timeout_ms: Option<u64>,
     pub request_read_timeout_ms: Option<u64>,
+    pub request_hard_timeout_ms: Option<u64>,
+    pub keepalive_idle_timeout_ms: Option<u64>,
     pub request_buffer_size: Option<u64>,
 
     pub beforeunload_wall_clock_pct: Option<u8>,
@@ -260,84 +771,192 @@ pub struct ServerFlags {
     pub beforeunload_memory_pct: Option<u8>,
 }
 
-#[derive(Debug)]
-pub struct Tls {
-    port: u16,
-    key: PrivateKeyDer<'static>,
-    cert_chain: Vec<CertificateDer<'static>>,
+// the successor boots its main worker before it starts listening, which can
+// take a while.
+static HANDOVER_READY_TIMEOUT_DUR: Duration = Duration::from_secs(60);
+
+const ALPN_H2: &[u8] = b"h2";
+const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+/// Whether clients of a TLS listener must present a certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuthMode {
+    /// Handshakes without a valid client certificate are rejected.
+    Required,
+    /// Clients may omit the certificate, but one that is presented must be
+    /// valid.
+    Optional,
 }
 
-impl Clone for Tls {
-    fn clone(&self) -> Self {
-        Self {
-            port: self.port,
-            key: self.key.clone_key(),
-            cert_chain: self.cert_chain.clone(),
+impl FromStr for ClientAuthMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "required" => Ok(Self::Required),
+            "optional" => Ok(Self::Optional),
+            _ => Err(anyhow::anyhow!("unknown client auth mode: {}", s)),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Tls {
+    port: u16,
+    certs: CertConfig,
+    client_auth: Option<(Vec<CertificateDer<'static>>, ClientAuthMode)>,
+}
+
 impl Tls {
     pub fn new(port: u16, key: &[u8], cert: &[u8]) -> anyhow::Result<Self> {
-        let Some((key_item, _)) =
-            read_one_from_slice(key).map_err(|err| anyhow!("can't resolve key: {:?}", err))?
-        else {
-            bail!("invalid key data")
-        };
+        Ok(Self::with_default_cert(
+            port,
+            CertSource::from_pem(key, cert)?,
+        ))
+    }
 
-        let mut cert_chain = vec![];
-        let mut cert_slice = cert;
-        loop {
-            let Some((Item::X509Certificate(cert), remain_cert_slice)) =
-                read_one_from_slice(cert_slice)
-                    .map_err(|err| anyhow!("can't resolve cert: {:?}", err))?
-            else {
-                bail!("invalid cert data")
-            };
+    /// Loads the key and certificate from PEM files.
+    ///
+    /// Unlike [`Tls::new`], the certificate can be reloaded from the files
+    /// while the server is running. This happens whenever the files change or
+    /// when the process receives `SIGHUP`.
+    pub fn from_files(port: u16, key_path: &Path, cert_path: &Path) -> anyhow::Result<Self> {
+        Ok(Self::with_default_cert(
+            port,
+            CertSource::from_files(TlsFiles {
+                key: key_path.to_path_buf(),
+                cert: cert_path.to_path_buf(),
+            })?,
+        ))
+    }
+
+    fn with_default_cert(port: u16, default: CertSource) -> Self {
+        Self {
+            port,
+            certs: CertConfig {
+                default,
+                sni: vec![],
+                sni_dirs: vec![],
+            },
+            client_auth: None,
+        }
+    }
 
-            cert_chain.push(cert);
+    /// Verifies client certificates against the given PEM-encoded CA bundle.
+    ///
+    /// The identity of a verified certificate is forwarded to the main worker
+    /// with every request, through the `x-edge-runtime-client-cert-*`
+    /// headers.
+    pub fn with_client_auth(
+        mut self,
+        ca_bundle: &[u8],
+        mode: ClientAuthMode,
+    ) -> anyhow::Result<Self> {
+        self.client_auth = Some((
+            tls::parse_cert_chain(ca_bundle).with_context(|| "can't resolve CA bundle")?,
+            mode,
+        ));
+
+        Ok(self)
+    }
 
-            if remain_cert_slice.is_empty() {
-                break;
+    /// Serves the given certificate to clients that ask for `server_name`
+    /// through SNI. `server_name` may start with `*.` to match any single
+    /// label.
+    ///
+    /// Clients that don't use SNI, or ask for a name that has no certificate,
+    /// get the default certificate.
+    pub fn with_sni_cert(
+        mut self,
+        server_name: &str,
+        key: &[u8],
+        cert: &[u8],
+    ) -> anyhow::Result<Self> {
+        self.certs
+            .sni
+            .push((server_name.to_string(), CertSource::from_pem(key, cert)?));
+
+        Ok(self)
+    }
+
+    /// Same as [`Tls::with_sni_cert`], but loads the key and certificate from
+    /// PEM files, which makes them reloadable.
+    pub fn with_sni_files(
+        mut self,
+        server_name: &str,
+        key_path: &Path,
+        cert_path: &Path,
+    ) -> anyhow::Result<Self> {
+        self.certs.sni.push((
+            server_name.to_string(),
+            CertSource::from_files(TlsFiles {
+                key: key_path.to_path_buf(),
+                cert: cert_path.to_path_buf(),
+            })?,
+        ));
+
+        Ok(self)
+    }
+
+    /// Serves the certificates found in a directory by SNI. Each certificate
+    /// is named after the server name it serves, either as `<name>.pem` with
+    /// `<name>-key.pem` or as `<name>.crt` with `<name>.key`. A name starting
+    /// with `_wildcard.` matches any single label. (e.g.
+    /// `_wildcard.example.com.pem` serves `*.example.com`)
+    ///
+    /// The directory is scanned again whenever the certificates are reloaded.
+    pub fn with_sni_dir(mut self, dir: &Path) -> Self {
+        self.certs.sni_dirs.push(dir.to_path_buf());
+        self
+    }
+
+    fn into_acceptor(self) -> anyhow::Result<(TlsAcceptor, CertReloader)> {
+        let (reloader, resolver) =
+            CertReloader::new(self.certs).with_context(|| "can't make TLS acceptor")?;
+
+        let builder = ServerConfig::builder();
+        let builder = if let Some((ca_certs, mode)) = self.client_auth {
+            let mut roots = RootCertStore::empty();
+
+            for cert in ca_certs {
+                roots.add(cert).with_context(|| "invalid CA certificate")?;
             }
 
-            cert_slice = remain_cert_slice;
-        }
+            let mut verifier = WebPkiClientVerifier::builder(Arc::new(roots));
 
-        let key = match key_item {
-            Item::Pkcs1Key(key) => PrivateKeyDer::Pkcs1(key),
-            Item::Pkcs8Key(key) => PrivateKeyDer::Pkcs8(key),
-            Item::Sec1Key(key) => PrivateKeyDer::Sec1(key),
-            _ => bail!("invalid key data"),
+            if mode == ClientAuthMode::Optional {
+                verifier = verifier.allow_unauthenticated();
+            }
+
+            builder.with_client_cert_verifier(
+                verifier
+                    .build()
+                    .with_context(|| "can't make client certificate verifier")?,
+            )
+        } else {
+            builder.with_no_client_auth()
         };
 
-        Ok(Self {
-            port,
-            key,
-            cert_chain,
-        })
-    }
+        let mut config = builder.with_cert_resolver(resolver);
 
-    fn into_acceptor(self) -> anyhow::Result<TlsAcceptor> {
-        Ok(Arc::new(
-            ServerConfig::builder()
-                .with_no_client_auth()
-                .with_single_cert(self.cert_chain, self.key)
-                .with_context(|| "can't make TLS acceptor")?,
-        )
-        .into())
+        config.alpn_protocols = vec![ALPN_H2.to_vec(), ALPN_HTTP_1_1.to_vec()];
+
+        Ok((Arc::new(config).into(), reloader))
     }
 }
 
 pub struct Server {
-    ip: Ipv4Addr,
+    ip: IpAddr,
     port: u16,
     tls: Option<Tls>,
-    main_worker_req_tx: mpsc::UnboundedSender<WorkerRequestMsg>,
+    main_worker_req_tx: MainWorkerReqTx,
+    main_worker_reloader: MainWorkerReloader,
     callback_tx: Option<Sender<ServerHealth>>,
     termination_tokens: TerminationTokens,
     flags: Arc<ServerFlags>,
     metric_src: SharedMetricSource,
+    metrics_exporter: Option<MetricsExporter>,
+    maybe_runtime_metric_src: Option<Arc<ArcSwap<RuntimeMetricSource>>>,
 }
 
 impl Server {
@@ -409,21 +1028,18 @@ impl Server {
         .await?;
 
         // create main worker
-        let main_worker_path = Path::new(&main_service_path).to_path_buf();
-        let main_worker_req_tx = create_main_worker(
-            flags.clone(),
-            main_worker_path,
-            import_map_path.clone(),
-            flags.no_module_cache,
-            MainWorkerRuntimeOpts {
-                worker_pool_tx,
+        let main_worker_opts = Arc::new(MainWorkerOpts {
+            flags: flags.clone(),
+            path: Path::new(&main_service_path).to_path_buf(),
+            import_map_path: import_map_path.clone(),
+            runtime_opts: MainWorkerRuntimeOpts {
+                worker_pool_tx: worker_pool_tx.clone(),
                 shared_metric_src: Some(shared_metric_src.clone()),
                 event_worker_metric_src,
             },
-            maybe_main_entrypoint,
+            maybe_entrypoint: maybe_main_entrypoint,
             maybe_decorator,
-            Some(termination_tokens.main.clone()),
-            if flags.allow_main_inspector {
+            maybe_inspector: if flags.allow_main_inspector {
                 inspector.map(|it| Inspector {
                     option: InspectorOption::Inspect(it.option.socket_addr()),
                     server: it.server,
@@ -431,21 +1047,45 @@ impl Server {
             } else {
                 None
             },
-            jsx_config,
-        )
-        .await?;
+            maybe_jsx_config: jsx_config,
+        });
+
+        let main_worker_ctx = main_worker_opts
+            .create(TerminationToken::clone(&termination_tokens.main.load()))
+            .await?;
+
+        let main_worker_req_tx = Arc::new(ArcSwap::from_pointee(main_worker_ctx.msg_tx));
+        let maybe_runtime_metric_src = main_worker_ctx
+            .metric
+            .into_runtime()
+            .ok()
+            .map(|it| Arc::new(ArcSwap::from_pointee(it)));
+        let metrics_exporter = maybe_runtime_metric_src
+            .clone()
+            .map(|it| MetricsExporter::new(it, worker_pool_tx));
+
+        let main_worker_reloader = MainWorkerReloader {
+            opts: main_worker_opts,
+            req_tx: main_worker_req_tx.clone(),
+            termination_token: termination_tokens.main.clone(),
+            maybe_runtime_metric_src: maybe_runtime_metric_src.clone(),
+            lock: Arc::default(),
+        };
 
-        let ip = Ipv4Addr::from_str(ip)?;
+        let ip = IpAddr::from_str(ip).with_context(|| format!("invalid ip address: {}", ip))?;
 
         Ok(Self {
             ip,
             port,
             tls,
             main_worker_req_tx,
+            main_worker_reloader,
             callback_tx,
             termination_tokens,
             flags,
             metric_src: shared_metric_src,
+            metrics_exporter,
+            maybe_runtime_metric_src,
         })
     }
 
@@ -453,19 +1093,156 @@ impl Server {
         self.termination_tokens.terminate().await;
     }
 
+    pub fn main_worker_reloader(&self) -> MainWorkerReloader {
+        self.main_worker_reloader.clone()
+    }
+
     pub async fn listen(&mut self) -> Result<Option<i32>, Error> {
-        let addr = SocketAddr::new(IpAddr::V4(self.ip), self.port);
-        let non_secure_listener = TcpListener::bind(&addr).await?;
+        let addr = SocketAddr::new(self.ip, self.port);
+        let proxy_protocol_trusted_ranges = self
+            .flags
+            .proxy_protocol
+            .then(|| self.flags.proxy_protocol_trusted_ranges.clone());
+
+        // NOTE: The TCP listeners take a slot of the limiter for each
+        // connection before accepting it, so the connections that are still
+        // reading their PROXY header or doing their TLS handshake are counted
+        // as well.
+        let conn_limiter = ConnLimiter::new(
+            self.flags.max_connections,
+            self.flags.max_connections_per_ip,
+        );
+
+        // NOTE: Listeners passed down to us (see `socket_activation`) take the
+        // place of the ones we would otherwise bind. Those that weren't passed
+        // are bound as usual.
+        let mut inherited = socket_activation::take_listeners(&self.flags.listen_fds)?;
+        let mut handover_fds = HandoverFds::default();
+
+        let mut non_secure_listener = match inherited.http.take() {
+            Some(listener) => Some(TcpListener::from_std(listener)?),
+            None if self.flags.unix_socket_only => None,
+            None => Some(bind_tcp_listener(addr)?),
+        }
+        .map(|listener| {
+            handover_fds.push(socket_activation::LISTENER_HTTP, &listener);
+            ProxyProtocolListener::new(
+                listener,
+                proxy_protocol_trusted_ranges.clone(),
+                conn_limiter.clone(),
+            )
+        });
+
+        let maybe_inherited_unix_listener = unix_socket::take_inherited(&mut inherited)?;
+        let unix_listener_inherited = maybe_inherited_unix_listener.is_some();
+        let unix_listener = match maybe_inherited_unix_listener {
+            Some(listener) => Some(listener),
+            None => self
+                .flags
+                .unix_socket
+                .as_deref()
+                .map(unix_socket::bind)
+                .transpose()?,
+        };
+
+        #[cfg(unix)]
+        if let Some(listener) = unix_listener.as_ref() {
+            handover_fds.push(socket_activation::LISTENER_UNIX, listener);
+        }
+
+        // a socket file is only removed by whoever created it.
+        let mut unix_socket_guard = scopeguard::guard(
+            unix_listener
+                .as_ref()
+                .and(self.flags.unix_socket.clone())
+                .filter(|_| !unix_listener_inherited),
+            |maybe_path| {
+                if let Some(path) = maybe_path {
+                    let _ = std::fs::remove_file(path);
+                }
+            },
+        );
+
+        let mut cert_reloader = None;
         let mut secure_listener = if let Some(tls) = self.tls.take() {
-            let addr = SocketAddr::new(IpAddr::V4(self.ip), tls.port);
+            let listener = match inherited.https.take() {
+                Some(listener) => TcpListener::from_std(listener)?,
+                None => bind_tcp_listener(SocketAddr::new(self.ip, tls.port))?,
+            };
+
+            let addr = listener.local_addr()?;
+            let (acceptor, reloader) = tls.into_acceptor()?;
+
+            cert_reloader = Some(reloader);
+            handover_fds.push(socket_activation::LISTENER_HTTPS, &listener);
+
             Some((
-                TlsListener::new(tls.into_acceptor()?, TcpListener::bind(addr).await?),
+                TlsListener::new(
+                    acceptor,
+                    ProxyProtocolListener::new(
+                        listener,
+                        proxy_protocol_trusted_ranges,
+                        conn_limiter.clone(),
+                    ),
+                ),
                 addr,
             ))
         } else {
+            if inherited.https.is_some() {
+                bail!("inherited an `https` listener, but TLS is not configured");
+            }
+
             None
         };
 
+        let cert_watch_token = CancellationToken::new();
+        let _cert_watch_guard = cert_watch_token.clone().drop_guard();
+
+        if let Some(reloader) = cert_reloader.clone() {
+            tokio::spawn(reloader.watch(cert_watch_token));
+        }
+
+        let metrics_token = CancellationToken::new();
+        let _metrics_guard = metrics_token.clone().drop_guard();
+
+        if let Some((addr, exporter)) = self.flags.metrics_addr.zip(self.metrics_exporter.clone()) {
+            let listener = match inherited.metrics.take() {
+                Some(listener) => TcpListener::from_std(listener)?,
+                None => bind_tcp_listener(addr)?,
+            };
+
+            handover_fds.push(socket_activation::LISTENER_METRICS, &listener);
+
+            debug!("metrics are served on {:?}", listener.local_addr()?);
+            tokio::spawn(exporter.serve(listener, metrics_token.clone()));
+        }
+
+        let draining_token = CancellationToken::new();
+        let health_check = HealthCheck::new(
+            self.maybe_runtime_metric_src.clone(),
+            draining_token.clone(),
+        );
+
+        let admin_token = CancellationToken::new();
+        let _admin_guard = admin_token.clone().drop_guard();
+
+        if let Some(addr) = self.flags.admin_addr {
+            let listener = match inherited.admin.take() {
+                Some(listener) => TcpListener::from_std(listener)?,
+                None => bind_tcp_listener(addr)?,
+            };
+
+            handover_fds.push(socket_activation::LISTENER_ADMIN, &listener);
+
+            debug!("health checks are served on {:?}", listener.local_addr()?);
+            tokio::spawn(serve_admin(
+                listener,
+                admin_token.clone(),
+                health_check.clone(),
+                self.main_worker_reloader.clone(),
+            ));
+        }
+
         let metric_src = self.metric_src.clone();
         let termination_tokens = &self.termination_tokens;
         let input_termination_token = termination_tokens.input.as_ref();
@@ -475,15 +1252,22 @@ impl Server {
         let mut interrupted = false;
         let (event_tx, event_rx) = mpsc::unbounded_channel();
 
-        debug!(
-            "edge-runtime is listening on {:?}",
-            non_secure_listener.local_addr()?
-        );
+        if let Some(listener) = non_secure_listener.as_ref() {
+            debug!("edge-runtime is listening on {:?}", listener.local_addr()?);
+        }
+
+        if let Some(path) = self.flags.unix_socket.as_ref() {
+            debug!("edge-runtime is listening on {:?} (unix)", path);
+        }
 
         if let Some((_, addr)) = secure_listener.as_ref() {
             debug!("edge-runtime is listening on {:?} (secure)", addr);
         }
 
+        // the process that handed its listeners over to us can stop accepting
+        // connections now.
+        socket_activation::notify_ready(&self.flags.listen_fds);
+
         if let Some(callback) = self.callback_tx.clone() {
             can_receive_event = true;
             let _ = callback
@@ -496,14 +1280,67 @@ impl Server {
 
         let ServerFlags {
             tcp_nodelay,
+            h2c,
             request_read_timeout_ms,
+            request_hard_timeout_ms,
+            trust_request_id,
+            keepalive_idle_timeout_ms,
+            max_request_body_bytes,
+            listener_handover,
             mut graceful_exit_deadline_sec,
             mut graceful_exit_keepalive_deadline_ms,
             ..
         } = *self.flags;
 
         let request_read_timeout_dur = request_read_timeout_ms.map(Duration::from_millis);
+        let keepalive_idle_timeout_dur = keepalive_idle_timeout_ms.map(Duration::from_millis);
+        let service_opts = WorkerServiceOpts {
+            maybe_hard_timeout_dur: request_hard_timeout_ms.map(Duration::from_millis),
+            trust_request_id,
+            maybe_rate_limiter: self
+                .flags
+                .rate_limit
+                .clone()
+                .map(|it| Arc::new(RateLimiter::new(it))),
+            maybe_access_logger: match self.flags.access_log.as_ref() {
+                Some(config) => Some(AccessLogger::spawn(config, metric_src.clone()).await?),
+                None => None,
+            },
+            // NOTE: The paths of the health checks are taken away from the
+            // main worker only when asked for, as it may serve them itself.
+            maybe_health_check: self.flags.health_on_main_port.then_some(health_check),
+            maybe_max_request_body_bytes: max_request_body_bytes,
+            maybe_compressor: self
+                .flags
+                .compression
+                .clone()
+                .map(|it| Arc::new(Compressor::new(it))),
+        };
+
+        // NOTE: A connection of the Unix domain socket is only accepted once a
+        // slot has been reserved for it. While the limit is reached, the
+        // listener is not polled, so new connections wait in the backlog of
+        // the socket instead of piling up in memory. (the TCP listeners do
+        // the same by themselves)
+        let mut maybe_conn_permit = None;
+        let non_secure_protocol = if h2c {
+            HttpProtocol::Auto
+        } else {
+            HttpProtocol::Http1
+        };
         let mut terminate_signal_fut = get_termination_signal();
+        let mut cert_reload_signal = if cert_reloader.is_some() {
+            get_cert_reload_signal()
+        } else {
+            futures_util::stream::pending().boxed()
+        };
+        let mut main_worker_reload_signal = get_main_worker_reload_signal();
+        let mut maybe_handover = None::<BoxFuture<'static, Result<u32, Error>>>;
+        let mut handover_signal = if listener_handover {
+            get_handover_signal()
+        } else {
+            futures_util::stream::pending().boxed()
+        };
 
         loop {
             let main_worker_req_tx = self.main_worker_req_tx.clone();
@@ -511,20 +1348,49 @@ impl Server {
             let metric_src = metric_src.clone();
 
             tokio::select! {
-                msg = non_secure_listener.accept() => {
+                permit = conn_limiter.reserve(), if unix_listener.is_some() && maybe_conn_permit.is_none() => {
+                    maybe_conn_permit = Some(permit);
+                }
+
+                msg = async {
+                    if let Some(listener) = non_secure_listener.as_mut() {
+                        listener.accept()
+                    } else {
+                        pending::<()>().await;
+                        unreachable!();
+                    }.await
+                } => {
                     match msg {
-                        Ok((stream, _)) => {
+                        Ok((mut stream, remote_addr)) => {
+                            let permit = stream.take_permit();
+                            let Some(conn_guard) = conn_limiter.admit(permit, Some(remote_addr.ip())) else {
+                                continue;
+                            };
+
                             if tcp_nodelay {
-                                let _ = stream.set_nodelay(true);
+                                let _ = stream.get_ref().set_nodelay(true);
                             }
 
+                            let conn_info = ConnectionInfo {
+                                addrs: stream.get_ref().local_addr().ok().map(|local| ConnAddrs {
+                                    local,
+                                    remote: remote_addr,
+                                }),
+                                ..Default::default()
+                            };
+
                             accept_stream(
                                 stream,
+                                non_secure_protocol,
+                                conn_info,
                                 main_worker_req_tx,
                                 event_tx,
                                 metric_src,
                                 graceful_exit_token.clone(),
-                                request_read_timeout_dur
+                                request_read_timeout_dur,
+                                keepalive_idle_timeout_dur,
+                                service_opts.clone(),
+                                conn_guard
                             )
                         }
                         Err(e) => error!("socket error: {}", e)
@@ -540,18 +1406,77 @@ impl Server {
                     }.await
                 } => {
                     match msg {
-                        Ok((stream, _)) => {
+                        Ok((mut stream, remote_addr)) => {
+                            let permit = stream.get_mut().0.take_permit();
+                            let Some(conn_guard) = conn_limiter.admit(permit, Some(remote_addr.ip())) else {
+                                continue;
+                            };
+
+                            let (reserved, tls_conn) = stream.get_ref();
+                            let tcp_stream = reserved.get_ref();
+
                             if tcp_nodelay {
-                                let _ = stream.get_ref().0.set_nodelay(true);
+                                let _ = tcp_stream.set_nodelay(true);
                             }
 
+                            let protocol = if tls_conn.alpn_protocol() == Some(ALPN_H2) {
+                                HttpProtocol::Http2
+                            } else {
+                                HttpProtocol::Http1
+                            };
+
+                            let conn_info = ConnectionInfo {
+                                addrs: tcp_stream.local_addr().ok().map(|local| ConnAddrs {
+                                    local,
+                                    remote: remote_addr,
+                                }),
+                                tls: true,
+                                tls_server_name: tls_conn.server_name().map(str::to_string),
+                                client_cert: tls_conn
+                                    .peer_certificates()
+                                    .and_then(|it| it.first())
+                                    .and_then(ClientCertIdentity::from_der)
+                                    .map(Arc::new),
+                            };
+
                             accept_stream(
                                 stream,
+                                protocol,
+                                conn_info,
                                 main_worker_req_tx,
                                 event_tx,
                                 metric_src,
                                 graceful_exit_token.clone(),
-                                request_read_timeout_dur
+                                request_read_timeout_dur,
+                                keepalive_idle_timeout_dur,
+                                service_opts.clone(),
+                                conn_guard
+                            )
+                        }
+                        Err(e) => error!("socket error: {}", e)
+                    }
+                }
+
+                msg = unix_socket::accept(unix_listener.as_ref()), if maybe_conn_permit.is_some() => {
+                    match msg {
+                        Ok(stream) => {
+                            let permit = maybe_conn_permit.take().unwrap();
+                            let Some(conn_guard) = conn_limiter.admit(permit, None) else {
+                                continue;
+                            };
+
+                            accept_stream(
+                                stream,
+                                non_secure_protocol,
+                                ConnectionInfo::default(),
+                                main_worker_req_tx,
+                                event_tx,
+                                metric_src,
+                                graceful_exit_token.clone(),
+                                request_read_timeout_dur,
+                                keepalive_idle_timeout_dur,
+                                service_opts.clone(),
+                                conn_guard
                             )
                         }
                         Err(e) => error!("socket error: {}", e)
@@ -576,6 +1501,69 @@ impl Server {
                     break;
                 }
 
+                Some(_) = cert_reload_signal.next() => {
+                    info!("certificate reload signal received");
+
+                    if let Some(reloader) = cert_reloader.as_ref() {
+                        reloader.reload();
+                    }
+                }
+
+                Some(_) = main_worker_reload_signal.next() => {
+                    info!("main worker reload signal received");
+
+                    tokio::spawn({
+                        let reloader = self.main_worker_reloader.clone();
+
+                        async move {
+                            match reloader.reload().await {
+                                Ok(()) => info!("main worker reloaded"),
+                                Err(err) => error!("failed to reload the main worker: {:#}", err),
+                            }
+                        }
+                    });
+                }
+
+                Some(_) = handover_signal.next() => {
+                    info!("handover signal received");
+
+                    if maybe_handover.is_some() {
+                        warn!("ignoring the handover signal: a handover is already in progress");
+                        continue;
+                    }
+
+                    // NOTE: We keep accepting connections until the successor
+                    // tells us it is listening. If it never does, it is killed
+                    // and we carry on as if nothing happened.
+                    match socket_activation::spawn_successor(&handover_fds) {
+                        Ok(successor) => {
+                            info!("waiting for process {} to take the listeners over", successor.id());
+                            maybe_handover = Some(successor.ready(HANDOVER_READY_TIMEOUT_DUR).boxed());
+                        }
+
+                        Err(err) => error!("failed to hand the listeners over: {:#}", err),
+                    }
+                }
+
+                result = async { maybe_handover.as_mut().unwrap().await }, if maybe_handover.is_some() => {
+                    maybe_handover = None;
+
+                    match result {
+                        Ok(pid) => {
+                            info!("handed the listeners over to process {}", pid);
+
+                            // the successor is in charge of the socket file and
+                            // of serving metrics and health checks from now on.
+                            *unix_socket_guard = None;
+                            metrics_token.cancel();
+                            admin_token.cancel();
+                            break;
+                        }
+
+                        Err(err) => error!("failed to hand the listeners over: {:#}", err),
+                    }
+                }
+
                 signum = &mut terminate_signal_fut => {
                     info!("shutdown signal received: {}", signum);
                     ret = Some(signum);
@@ -590,6 +1578,8 @@ impl Server {
             }
         }
 
+        draining_token.cancel();
+
         if !interrupted && graceful_exit_deadline_sec > 0 {
             static REQ_METRIC_CHECK_SLEEP_DUR: Duration = Duration::from_millis(10);
 
@@ -681,6 +1671,84 @@ impl Server {
     }
 }
 
+fn bind_tcp_listener(addr: SocketAddr) -> Result<TcpListener, Error> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+
+    if addr.is_ipv6() {
+        // NOTE: Whether an IPv6 socket also accepts IPv4-mapped connections
+        // depends on the platform default (e.g. `net.ipv6.bindv6only` on
+        // Linux). We clear `IPV6_V6ONLY` explicitly so binding to `::` always
+        // results in a dual-stack listener.
+        socket.set_only_v6(false)?;
+    }
+
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+
+    socket.set_nonblocking(true)?;
+    socket
+        .bind(&addr.into())
+        .with_context(|| format!("can't bind to {}", addr))?;
+    socket.listen(1024)?;
+
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// Makes sure the request carries an ID in [`REQUEST_ID_HEADER`] and returns
+/// it. The ID sent by the client is kept only if it is trusted and looks sane.
+fn assign_request_id(headers: &mut HeaderMap, trusted: bool) -> String {
+    if trusted {
+        let maybe_request_id = headers
+            .get(REQUEST_ID_HEADER)
+            .and_then(|it| it.to_str().ok())
+            .filter(|it| {
+                !it.is_empty()
+                    && it.len() <= MAX_REQUEST_ID_LEN
+                    && it.bytes().all(|b| b.is_ascii_graphic())
+            });
+
+        if let Some(request_id) = maybe_request_id {
+            return request_id.to_string();
+        }
+    }
+
+    let request_id = Uuid::new_v4().to_string();
+
+    headers.insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&request_id).unwrap(),
+    );
+
+    request_id
+}
+
+/// Requests are forwarded to workers over an HTTP/1.1 connection (see
+/// `handle_request`), so a request that arrived over HTTP/2 must be rewritten
+/// into a form that is valid there.
+fn downgrade_h2_request(req: &mut Request<Body>) {
+    if req.version() != Version::HTTP_2 {
+        return;
+    }
+
+    *req.version_mut() = Version::HTTP_11;
+
+    let uri = std::mem::take(req.uri_mut());
+    let mut parts = uri.into_parts();
+
+    // HTTP/2 carries the host in the `:authority` pseudo-header instead of
+    // `Host`.
+    if let Some(authority) = parts.authority.take() {
+        if !req.headers().contains_key(header::HOST) {
+            if let Ok(value) = HeaderValue::from_str(authority.as_str()) {
+                req.headers_mut().insert(header::HOST, value);
+            }
+        }
+    }
+
+    parts.scheme = None;
+    *req.uri_mut() = Uri::from_parts(parts).unwrap_or_else(|_| Uri::from_static("/"));
+}
+
 #[cfg(unix)]
 fn get_termination_signal() -> BoxFuture<'static, i32> {
     use signal::unix::signal;
@@ -715,33 +1783,112 @@ fn get_termination_signal() -> BoxFuture<'static, i32> {
     pending().boxed()
 }
 
+// NOTE: Each signal does one thing only:
+//
+// - SIGHUP reloads the TLS certificate and key. Without TLS, it is left to its
+//   default action, which is terminating the process.
+// - SIGUSR1 reloads the main worker, draining the previous one.
+// - SIGUSR2 hands the listeners over to a new process (`--listener-handover`).
+// - SIGTERM shuts the server down gracefully.
+
+#[cfg(unix)]
+fn get_cert_reload_signal() -> BoxStream<'static, ()> {
+    use signal::unix::signal;
+    use signal::unix::SignalKind;
+
+    let mut hangup = signal(SignalKind::hangup()).unwrap();
+
+    futures_util::stream::poll_fn(move |cx| hangup.poll_recv(cx)).boxed()
+}
+
+#[cfg(not(unix))]
+fn get_cert_reload_signal() -> BoxStream<'static, ()> {
+    futures_util::stream::pending().boxed()
+}
+
+#[cfg(unix)]
+fn get_main_worker_reload_signal() -> BoxStream<'static, ()> {
+    use signal::unix::signal;
+    use signal::unix::SignalKind;
+
+    let mut user_defined1 = signal(SignalKind::user_defined1()).unwrap();
+
+    futures_util::stream::poll_fn(move |cx| user_defined1.poll_recv(cx)).boxed()
+}
+
+#[cfg(not(unix))]
+fn get_main_worker_reload_signal() -> BoxStream<'static, ()> {
+    futures_util::stream::pending().boxed()
+}
+
+#[cfg(unix)]
+fn get_handover_signal() -> BoxStream<'static, ()> {
+    use signal::unix::signal;
+    use signal::unix::SignalKind;
+
+    let mut user_defined2 = signal(SignalKind::user_defined2()).unwrap();
+
+    futures_util::stream::poll_fn(move |cx| user_defined2.poll_recv(cx)).boxed()
+}
+
+#[cfg(not(unix))]
+fn get_handover_signal() -> BoxStream<'static, ()> {
+    futures_util::stream::pending().boxed()
+}
+
+#[allow(clippy::too_many_arguments)]
 fn accept_stream<I>(
     io: I,
-    req_tx: UnboundedSender<WorkerRequestMsg>,
+    protocol: HttpProtocol,
+    conn_info: ConnectionInfo,
+    req_tx: MainWorkerReqTx,
     event_tx: Option<UnboundedSender<ServerEvent>>,
     metric_src: SharedMetricSource,
     graceful_exit_token: CancellationToken,
     maybe_req_read_timeout_dur: Option<Duration>,
+    maybe_keepalive_idle_timeout_dur: Option<Duration>,
+    service_opts: WorkerServiceOpts,
+    conn_guard: ConnGuard,
 ) where
     I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     metric_src.incl_active_io();
     tokio::task::spawn({
         async move {
-            let (service, cancel) = WorkerService::new(metric_src.clone(), req_tx);
-            let (io, maybe_timeout_tx) = if let Some(timeout_dur) = maybe_req_read_timeout_dur {
-                crate::timeout::Stream::with_timeout(io, timeout_dur)
+            let (service, cancel) =
+                WorkerService::new(metric_src.clone(), req_tx, conn_info, service_opts);
+            let (io, maybe_timeout_tx) = if maybe_req_read_timeout_dur.is_some()
+                || maybe_keepalive_idle_timeout_dur.is_some()
+            {
+                crate::timeout::Stream::with_timeout(
+                    io,
+                    maybe_req_read_timeout_dur,
+                    maybe_keepalive_idle_timeout_dur,
+                )
             } else {
                 crate::timeout::Stream::with_bypass(io)
             };
 
+            let _conn_guard = conn_guard;
             let _guard = cancel.drop_guard();
             let _active_io_count_guard = scopeguard::guard(metric_src, |it| {
                 it.decl_active_io();
             });
 
+            let mut http = Http::new();
+
+            match protocol {
+                HttpProtocol::Http1 => {
+                    http.http1_only(true);
+                }
+                HttpProtocol::Http2 => {
+                    http.http2_only(true);
+                }
+                HttpProtocol::Auto => {}
+            }
+
             let mut shutting_down = false;
-            let conn_fut = Http::new()
+            let conn_fut = http
                 .serve_connection(io, crate::timeout::Service::new(service, maybe_timeout_tx))
                 .with_upgrades();
 
@@ -0,0 +1,579 @@
+// Takes over listening sockets that were opened by someone else, either by
+// systemd (socket activation) or by a previous process handing them over on
+// its way out. Both use the protocol described in `sd_listen_fds(3)`.
+
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use log::warn;
+
+const LISTEN_PID_ENV: &str = "LISTEN_PID";
+const LISTEN_FDS_ENV: &str = "LISTEN_FDS";
+const LISTEN_FDNAMES_ENV: &str = "LISTEN_FDNAMES";
+/// Set by a process handing its listeners over. It is the descriptor of a pipe
+/// that the successor writes to once it is listening.
+const READY_FD_ENV: &str = "EDGE_RUNTIME_READY_FD";
+
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Name of the listener serving plain HTTP. Inherited TCP sockets without a
+/// name we know of are assumed to be this one.
+pub(crate) const LISTENER_HTTP: &str = "http";
+/// Name of the listener serving HTTPS.
+pub(crate) const LISTENER_HTTPS: &str = "https";
+/// Name of the Unix domain socket listener. Any inherited Unix domain socket
+/// is assumed to be this one, whatever its name.
+pub(crate) const LISTENER_UNIX: &str = "unix";
+/// Name of the listener serving metrics.
+pub(crate) const LISTENER_METRICS: &str = "metrics";
+/// Name of the listener serving health checks.
+pub(crate) const LISTENER_ADMIN: &str = "admin";
+
+/// The descriptors passed down to this process.
+#[derive(Debug, Default, Clone)]
+pub struct ListenFds {
+    fds: Vec<(i32, String)>,
+    maybe_ready_fd: Option<i32>,
+}
+
+impl ListenFds {
+    /// Reads the descriptors passed down to this process from the environment,
+    /// then clears the variables, so that they don't leak into the processes
+    /// it spawns.
+    ///
+    /// # Safety
+    ///
+    /// This modifies the environment, which is only sound while no other
+    /// thread is running. It is meant to be called first thing in `main`.
+    pub unsafe fn take_from_env() -> Result<Self, Error> {
+        let keys = [
+            LISTEN_PID_ENV,
+            LISTEN_FDS_ENV,
+            LISTEN_FDNAMES_ENV,
+            READY_FD_ENV,
+        ];
+
+        let [pid, count, names, ready_fd] = keys.map(|key| std::env::var(key).ok());
+
+        for key in keys {
+            std::env::remove_var(key);
+        }
+
+        let this = Self::parse(
+            pid.as_deref(),
+            count.as_deref(),
+            names.as_deref(),
+            ready_fd.as_deref(),
+            std::process::id(),
+        )?;
+
+        // the processes spawned before the server takes the descriptors over
+        // must not inherit them either.
+        #[cfg(unix)]
+        for fd in this
+            .fds
+            .iter()
+            .map(|(fd, _)| *fd)
+            .chain(this.maybe_ready_fd)
+        {
+            if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+                return Err(std::io::Error::last_os_error())
+                    .with_context(|| format!("inherited file descriptor {} is not open", fd));
+            }
+        }
+
+        Ok(this)
+    }
+
+    fn parse(
+        maybe_pid: Option<&str>,
+        maybe_count: Option<&str>,
+        maybe_names: Option<&str>,
+        maybe_ready_fd: Option<&str>,
+        own_pid: u32,
+    ) -> Result<Self, Error> {
+        let Some(count) = maybe_count else {
+            return Ok(Self::default());
+        };
+
+        // NOTE: systemd always sets `LISTEN_PID`, but a process handing its
+        // listeners over can't know the PID of its successor before spawning
+        // it, so a missing `LISTEN_PID` is accepted as well.
+        if let Some(pid) = maybe_pid {
+            if pid.parse::<u32>().ok() != Some(own_pid) {
+                warn!("ignoring the inherited listeners: they were passed to another process");
+                return Ok(Self::default());
+            }
+        }
+
+        let count = count
+            .parse::<u16>()
+            .with_context(|| format!("invalid value for {}: {:?}", LISTEN_FDS_ENV, count))?;
+
+        let names = maybe_names
+            .unwrap_or_default()
+            .split(':')
+            .collect::<Vec<_>>();
+        let fds = (0..count)
+            .map(|idx| {
+                let name = names.get(idx as usize).copied().unwrap_or_default();
+
+                (SD_LISTEN_FDS_START + idx as i32, name.to_string())
+            })
+            .collect();
+
+        let maybe_ready_fd = maybe_ready_fd
+            .map(|it| {
+                it.parse::<i32>()
+                    .with_context(|| format!("invalid value for {}: {:?}", READY_FD_ENV, it))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            fds,
+            maybe_ready_fd,
+        })
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct InheritedListeners {
+    pub http: Option<std::net::TcpListener>,
+    pub https: Option<std::net::TcpListener>,
+    pub metrics: Option<std::net::TcpListener>,
+    pub admin: Option<std::net::TcpListener>,
+    #[cfg(unix)]
+    pub unix: Option<std::os::unix::net::UnixListener>,
+}
+
+/// The listeners of this process, to be passed on to its successor.
+#[derive(Default)]
+pub(crate) struct HandoverFds {
+    #[cfg(unix)]
+    fds: Vec<(std::os::unix::io::RawFd, &'static str)>,
+}
+
+impl HandoverFds {
+    #[cfg(unix)]
+    pub fn push(&mut self, name: &'static str, listener: &impl std::os::unix::io::AsRawFd) {
+        self.fds.push((listener.as_raw_fd(), name));
+    }
+
+    #[cfg(not(unix))]
+    pub fn push<T>(&mut self, _name: &'static str, _listener: &T) {}
+}
+
+/// Takes over the listeners passed down to this process. It must be called once
+/// at most, as the listeners own the descriptors afterwards.
+#[cfg(unix)]
+pub(crate) fn take_listeners(listen_fds: &ListenFds) -> Result<InheritedListeners, Error> {
+    use std::os::unix::io::FromRawFd;
+
+    use anyhow::bail;
+    use log::debug;
+    use socket2::Socket;
+
+    let mut listeners = InheritedListeners::default();
+
+    for (fd, name) in listen_fds.fds.iter() {
+        let fd = *fd;
+
+        // SAFETY: The protocol hands the descriptors from 3 onwards over to
+        // us, and nothing else in this process refers to them.
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+
+        let is_unix = socket
+            .local_addr()
+            .with_context(|| format!("inherited file descriptor {} is not a socket", fd))?
+            .is_unix();
+
+        let (slot_name, duplicate) = if is_unix {
+            (
+                LISTENER_UNIX,
+                listeners.unix.replace(socket.into()).is_some(),
+            )
+        } else {
+            match name.as_str() {
+                LISTENER_HTTPS => (
+                    LISTENER_HTTPS,
+                    listeners.https.replace(socket.into()).is_some(),
+                ),
+                LISTENER_METRICS => (
+                    LISTENER_METRICS,
+                    listeners.metrics.replace(socket.into()).is_some(),
+                ),
+                LISTENER_ADMIN => (
+                    LISTENER_ADMIN,
+                    listeners.admin.replace(socket.into()).is_some(),
+                ),
+                _ => (
+                    LISTENER_HTTP,
+                    listeners.http.replace(socket.into()).is_some(),
+                ),
+            }
+        };
+
+        if duplicate {
+            bail!("inherited more than one listener for `{}`", slot_name);
+        }
+
+        debug!(
+            "inherited file descriptor {} as the `{}` listener",
+            fd, slot_name
+        );
+    }
+
+    Ok(listeners)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn take_listeners(_listen_fds: &ListenFds) -> Result<InheritedListeners, Error> {
+    Ok(InheritedListeners::default())
+}
+
+/// Tells the process that handed its listeners over to us that we are
+/// listening, so that it can stop accepting connections.
+#[cfg(unix)]
+pub(crate) fn notify_ready(listen_fds: &ListenFds) {
+    use std::io::Write;
+    use std::os::unix::io::FromRawFd;
+
+    let Some(fd) = listen_fds.maybe_ready_fd else {
+        return;
+    };
+
+    // SAFETY: The descriptor was passed down to us for this alone, and it is
+    // closed once the file is dropped.
+    let mut pipe = unsafe { std::fs::File::from_raw_fd(fd) };
+
+    if let Err(err) = pipe.write_all(&[1]) {
+        warn!(
+            "failed to tell the previous process that we are ready: {}",
+            err
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn notify_ready(_listen_fds: &ListenFds) {}
+
+/// A process that is taking the listeners over.
+#[cfg(unix)]
+pub(crate) struct Successor {
+    child: tokio::process::Child,
+    ready_rx: tokio::net::unix::pipe::Receiver,
+}
+
+#[cfg(unix)]
+impl Successor {
+    pub fn id(&self) -> u32 {
+        self.child.id().unwrap_or_default()
+    }
+
+    /// Waits until the successor is listening. If it goes away or doesn't get
+    /// ready in time, it is killed and an error is returned, in which case the
+    /// caller should keep serving.
+    pub async fn ready(mut self, timeout_dur: Duration) -> Result<u32, Error> {
+        use anyhow::bail;
+        use tokio::io::AsyncReadExt;
+
+        let pid = self.id();
+        let mut buf = [0u8; 1];
+        let result = tokio::time::timeout(timeout_dur, self.ready_rx.read(&mut buf)).await;
+
+        if let Ok(Ok(1)) = result {
+            return Ok(pid);
+        }
+
+        let _ = self.child.kill().await;
+
+        match result {
+            Ok(Ok(_)) => bail!("process {} went away before it was ready", pid),
+            Ok(Err(err)) => Err(err).with_context(|| format!("can't wait for process {}", pid)),
+            Err(_) => bail!("process {} did not get ready in {:?}", pid, timeout_dur),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) enum Successor {}
+
+#[cfg(not(unix))]
+impl Successor {
+    pub fn id(&self) -> u32 {
+        match *self {}
+    }
+
+    pub async fn ready(self, _timeout_dur: Duration) -> Result<u32, Error> {
+        match self {}
+    }
+}
+
+/// Starts another instance of this program with the same arguments, passing
+/// it the listeners. The caller is expected to keep accepting connections
+/// until the successor is ready, then drain the ones it holds.
+#[cfg(unix)]
+pub(crate) fn spawn_successor(fds: &HandoverFds) -> Result<Successor, Error> {
+    let mut args = std::env::args_os();
+
+    // NOTE: The program is looked up again rather than taken from
+    // `current_exe`, which keeps pointing at the old binary after it has been
+    // replaced on disk. Picking up the new binary is what makes an upgrade
+    // without downtime possible.
+    let program = match args.next() {
+        Some(it) => it,
+        None => std::env::current_exe()?.into_os_string(),
+    };
+
+    let mut cmd = tokio::process::Command::new(&program);
+
+    cmd.args(args);
+    spawn_with(cmd, fds)
+        .with_context(|| format!("can't spawn the successor process: {:?}", program))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn spawn_successor(_fds: &HandoverFds) -> Result<Successor, Error> {
+    anyhow::bail!("handing the listeners over is not supported on this platform")
+}
+
+#[cfg(unix)]
+fn spawn_with(mut cmd: tokio::process::Command, fds: &HandoverFds) -> Result<Successor, Error> {
+    use std::os::raw::c_int;
+    use std::os::unix::io::AsRawFd;
+
+    let (ready_rx, ready_tx) = pipe()?;
+    let names = fds.fds.iter().map(|(_, name)| *name).collect::<Vec<_>>();
+    let mut src_fds = fds.fds.iter().map(|(fd, _)| *fd).collect::<Vec<_>>();
+
+    // the write end of the pipe is placed right after the listeners.
+    cmd.env_remove(LISTEN_PID_ENV)
+        .env(LISTEN_FDS_ENV, src_fds.len().to_string())
+        .env(LISTEN_FDNAMES_ENV, names.join(":"))
+        .env(
+            READY_FD_ENV,
+            (SD_LISTEN_FDS_START + src_fds.len() as c_int).to_string(),
+        );
+
+    src_fds.push(ready_tx.as_raw_fd());
+
+    let mut moved_fds = vec![0 as c_int; src_fds.len()];
+
+    // SAFETY: The closure runs in the child between `fork` and `exec`, and
+    // only makes async-signal-safe calls without allocating.
+    unsafe {
+        cmd.pre_exec(move || {
+            let end = SD_LISTEN_FDS_START + src_fds.len() as c_int;
+
+            // the descriptors are first moved out of the way, as one of them
+            // may already occupy a slot another one has to be placed into.
+            for (src, moved) in src_fds.iter().zip(moved_fds.iter_mut()) {
+                *moved = libc::fcntl(*src, libc::F_DUPFD_CLOEXEC, end);
+
+                if *moved < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            // `dup2` leaves `FD_CLOEXEC` cleared on the new descriptor.
+            for (idx, moved) in moved_fds.iter().enumerate() {
+                if libc::dup2(*moved, SD_LISTEN_FDS_START + idx as c_int) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    let child = cmd.spawn()?;
+
+    // our copy of the write end is closed, so that reading from the pipe ends
+    // once the successor goes away.
+    drop(ready_tx);
+
+    Ok(Successor {
+        child,
+        ready_rx: tokio::net::unix::pipe::Receiver::from_owned_fd(ready_rx)?,
+    })
+}
+
+#[cfg(unix)]
+fn pipe() -> std::io::Result<(std::os::unix::io::OwnedFd, std::os::unix::io::OwnedFd)> {
+    use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+
+    let mut fds = [0; 2];
+
+    // SAFETY: `fds` has room for the two descriptors.
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    // SAFETY: Both descriptors were just opened, and nothing else owns them.
+    let (rx, tx) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+    for fd in [&rx, &tx] {
+        // SAFETY: The descriptor is open for as long as `fd` lives.
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok((rx, tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_fds() {
+        let listen_fds =
+            ListenFds::parse(Some("42"), Some("3"), Some("https:metrics"), None, 42).unwrap();
+
+        assert_eq!(
+            listen_fds.fds,
+            [
+                (3, "https".to_string()),
+                (4, "metrics".to_string()),
+                (5, String::new()),
+            ]
+        );
+        assert_eq!(listen_fds.maybe_ready_fd, None);
+    }
+
+    #[test]
+    fn test_parse_listen_fds_without_pid() {
+        let listen_fds = ListenFds::parse(None, Some("1"), None, Some("4"), 42).unwrap();
+
+        assert_eq!(listen_fds.fds, [(3, String::new())]);
+        assert_eq!(listen_fds.maybe_ready_fd, Some(4));
+    }
+
+    #[test]
+    fn test_parse_listen_fds_of_another_process() {
+        let listen_fds = ListenFds::parse(Some("7"), Some("2"), None, Some("5"), 42).unwrap();
+
+        assert!(listen_fds.fds.is_empty());
+        assert_eq!(listen_fds.maybe_ready_fd, None);
+    }
+
+    #[test]
+    fn test_parse_listen_fds_not_set() {
+        let listen_fds = ListenFds::parse(None, None, Some("http"), Some("5"), 42).unwrap();
+
+        assert!(listen_fds.fds.is_empty());
+        assert_eq!(listen_fds.maybe_ready_fd, None);
+    }
+
+    #[test]
+    fn test_parse_listen_fds_invalid() {
+        assert!(ListenFds::parse(None, Some("-1"), None, None, 42).is_err());
+        assert!(ListenFds::parse(None, Some("two"), None, None, 42).is_err());
+        assert!(ListenFds::parse(None, Some("1"), None, Some("fd"), 42).is_err());
+    }
+
+    #[cfg(unix)]
+    const HANDOVER_CHILD_ENV: &str = "EDGE_RUNTIME_TEST_HANDOVER_CHILD";
+
+    // NOTE: This is the successor spawned by `test_handover`. It does nothing
+    // when run by the test harness itself.
+    #[cfg(unix)]
+    #[test]
+    fn handover_child() {
+        use std::io::Write;
+
+        if std::env::var_os(HANDOVER_CHILD_ENV).is_none() {
+            return;
+        }
+
+        let var = |key| std::env::var(key).ok();
+        let listen_fds = ListenFds::parse(
+            var(LISTEN_PID_ENV).as_deref(),
+            var(LISTEN_FDS_ENV).as_deref(),
+            var(LISTEN_FDNAMES_ENV).as_deref(),
+            var(READY_FD_ENV).as_deref(),
+            std::process::id(),
+        )
+        .unwrap();
+
+        let listener = take_listeners(&listen_fds).unwrap().http.unwrap();
+
+        listener.set_nonblocking(false).unwrap();
+        notify_ready(&listen_fds);
+
+        let (mut stream, _) = listener.accept().unwrap();
+
+        stream.write_all(b"hello").unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handover() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut fds = HandoverFds::default();
+
+        fds.push(LISTENER_HTTP, &listener);
+
+        let mut cmd = tokio::process::Command::new(std::env::current_exe().unwrap());
+
+        cmd.args(["--exact", "socket_activation::tests::handover_child"])
+            .env(HANDOVER_CHILD_ENV, "1");
+
+        spawn_with(cmd, &fds)
+            .unwrap()
+            .ready(Duration::from_secs(30))
+            .await
+            .unwrap();
+
+        // the successor accepts it, as we never do.
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut buf = String::new();
+
+        stream.read_to_string(&mut buf).await.unwrap();
+        assert_eq!(buf, "hello");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handover_successor_exits() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut fds = HandoverFds::default();
+
+        fds.push(LISTENER_HTTP, &listener);
+
+        let result = spawn_with(tokio::process::Command::new("true"), &fds)
+            .unwrap()
+            .ready(Duration::from_secs(30))
+            .await;
+
+        assert!(result.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_handover_successor_times_out() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut fds = HandoverFds::default();
+        let mut cmd = tokio::process::Command::new("sleep");
+
+        fds.push(LISTENER_HTTP, &listener);
+        cmd.arg("30");
+
+        let result = spawn_with(cmd, &fds)
+            .unwrap()
+            .ready(Duration::from_millis(200))
+            .await;
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("did not get ready"));
+    }
+}
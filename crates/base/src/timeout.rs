@@ -3,13 +3,14 @@
 
 use std::{
     pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
     task::{ready, Poll},
     time::Duration,
 };
 
 use enum_as_inner::EnumAsInner;
 use futures_util::Future;
-use pin_project::pin_project;
+use pin_project::{pin_project, pinned_drop};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
@@ -21,11 +22,38 @@ pub(super) enum State {
     Reset,
 }
 
+struct Timer {
+    sleep: Pin<Box<Sleep>>,
+    duration: Duration,
+}
+
+impl Timer {
+    fn new(duration: Duration) -> Self {
+        Self {
+            sleep: Box::pin(sleep(duration)),
+            duration,
+        }
+    }
+
+    fn reset(&mut self) {
+        let deadline = Instant::now() + self.duration;
+
+        self.sleep.as_mut().reset(deadline);
+    }
+}
+
 enum StreamKind {
     UseTimeout {
-        sleep: Pin<Box<Sleep>>,
-        duration: Duration,
-        waiting: bool,
+        maybe_read: Option<Timer>,
+        // NOTE: Unlike the read timer, which also covers the time spent on
+        // receiving the request header, the idle timer is stopped as soon as
+        // the first byte of the next request arrives.
+        maybe_idle: Option<Timer>,
+        idle: bool,
+        // NOTE: An HTTP/2 connection can have several requests in flight at
+        // the same time, so the timer must only be restarted once all of them
+        // have finished writing their responses.
+        in_flight: usize,
         finished: bool,
         rx: UnboundedReceiver<State>,
     },
@@ -45,7 +73,8 @@ impl<S> Stream<S> {
 
     pub(super) fn with_timeout(
         inner: S,
-        duration: Duration,
+        maybe_read_duration: Option<Duration>,
+        maybe_idle_duration: Option<Duration>,
     ) -> (Self, Option<UnboundedSender<State>>) {
         let (tx, rx) = mpsc::unbounded_channel();
 
@@ -53,9 +82,10 @@ impl<S> Stream<S> {
             Self::new(
                 inner,
                 StreamKind::UseTimeout {
-                    sleep: Box::pin(sleep(duration)),
-                    duration,
-                    waiting: false,
+                    maybe_read: maybe_read_duration.map(Timer::new),
+                    maybe_idle: maybe_idle_duration.map(Timer::new),
+                    idle: true,
+                    in_flight: 0,
                     finished: false,
                     rx,
                 },
@@ -77,36 +107,53 @@ impl<S: AsyncRead + Unpin> AsyncRead for Stream<S> {
     ) -> Poll<std::io::Result<()>> {
         match &mut self.kind {
             StreamKind::UseTimeout {
-                sleep,
-                duration,
-                waiting,
+                maybe_read,
+                maybe_idle,
+                idle,
+                in_flight,
                 finished,
                 rx,
             } => {
-                if !*finished {
-                    match Pin::new(rx).poll_recv(cx) {
+                while !*finished {
+                    match Pin::new(&mut *rx).poll_recv(cx) {
                         Poll::Ready(Some(State::Reset)) => {
-                            *waiting = false;
-
-                            let deadline = Instant::now() + *duration;
-
-                            sleep.as_mut().reset(deadline);
+                            *in_flight = in_flight.saturating_sub(1);
+
+                            if *in_flight == 0 {
+                                *idle = true;
+
+                                for timer in [maybe_read.as_mut(), maybe_idle.as_mut()]
+                                    .into_iter()
+                                    .flatten()
+                                {
+                                    timer.reset();
+                                }
+                            }
                         }
 
                         // enter waiting mode (for response body last chunk)
-                        Poll::Ready(Some(State::Wait)) => *waiting = true,
+                        Poll::Ready(Some(State::Wait)) => *in_flight += 1,
                         Poll::Ready(None) => *finished = true,
-                        Poll::Pending => (),
+                        Poll::Pending => break,
                     }
                 }
 
-                if !*waiting {
+                if *in_flight == 0 {
                     // return error if timer is elapsed
-                    if let Poll::Ready(()) = sleep.as_mut().poll(cx) {
-                        return Poll::Ready(Err(std::io::Error::new(
-                            std::io::ErrorKind::TimedOut,
-                            "request header read timed out",
-                        )));
+                    if let Some(Timer { sleep, .. }) = maybe_read.as_mut() {
+                        if let Poll::Ready(()) = sleep.as_mut().poll(cx) {
+                            return Poll::Ready(Err(std::io::Error::new(
+                                std::io::ErrorKind::TimedOut,
+                                "request header read timed out",
+                            )));
+                        }
+                    }
+
+                    // an idle connection is closed as if the client hung up.
+                    if let Some(Timer { sleep, .. }) = maybe_idle.as_mut().filter(|_| *idle) {
+                        if let Poll::Ready(()) = sleep.as_mut().poll(cx) {
+                            return Poll::Ready(Ok(()));
+                        }
                     }
                 }
             }
@@ -114,7 +161,17 @@ impl<S: AsyncRead + Unpin> AsyncRead for Stream<S> {
             StreamKind::Bypass => {}
         }
 
-        Pin::new(&mut self.inner).poll_read(cx, buf)
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        let result = Pin::new(&mut this.inner).poll_read(cx, buf);
+
+        if let StreamKind::UseTimeout { idle, .. } = &mut this.kind {
+            if buf.filled().len() > filled {
+                *idle = false;
+            }
+        }
+
+        result
     }
 }
 
@@ -187,7 +244,7 @@ where
     }
 }
 
-#[pin_project]
+#[pin_project(PinnedDrop)]
 pub struct ServiceFuture<F> {
     #[pin]
     inner: F,
@@ -200,6 +257,17 @@ impl<F> ServiceFuture<F> {
     }
 }
 
+#[pinned_drop]
+impl<F> PinnedDrop for ServiceFuture<F> {
+    fn drop(self: Pin<&mut Self>) {
+        // the future can be dropped before it has produced a body (e.g. the
+        // HTTP/2 stream was reset by the peer before the response head).
+        if let Some(tx) = self.project().tx.take() {
+            let _ = tx.send(State::Reset);
+        }
+    }
+}
+
 impl<F, B, Error> Future for ServiceFuture<F>
 where
     F: Future<Output = Result<hyper_v014::Response<B>, Error>>,
@@ -209,22 +277,54 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
 
-        this.inner.poll(cx).map(|result| {
-            result.map(|response| response.map(|body| Body::new(body, this.tx.take())))
-        })
+        match ready!(this.inner.poll(cx)) {
+            Ok(response) => Poll::Ready(Ok(response.map(|body| Body::new(body, this.tx.take())))),
+            Err(err) => {
+                // there will be no body to release the timer.
+                if let Some(tx) = this.tx.take() {
+                    let _ = tx.send(State::Reset);
+                }
+
+                Poll::Ready(Err(err))
+            }
+        }
     }
 }
 
-#[pin_project]
+#[pin_project(PinnedDrop)]
 pub struct Body<B> {
     #[pin]
     inner: B,
     tx: Option<UnboundedSender<State>>,
+    reset_sent: AtomicBool,
 }
 
 impl<B> Body<B> {
     fn new(inner: B, tx: Option<UnboundedSender<State>>) -> Self {
-        Self { inner, tx }
+        Self {
+            inner,
+            tx,
+            reset_sent: AtomicBool::new(false),
+        }
+    }
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for Body<B> {
+    fn drop(self: Pin<&mut Self>) {
+        // the body can be dropped before its end (e.g. the HTTP/2 stream was
+        // reset by the peer).
+        if let Some(tx) = self.tx.as_ref() {
+            send_reset(tx, &self.reset_sent);
+        }
+    }
+}
+
+fn send_reset(tx: &UnboundedSender<State>, reset_sent: &AtomicBool) {
+    // each response must release the timer exactly once, otherwise it would
+    // also release the other requests that are in flight on the connection.
+    if !reset_sent.swap(true, Ordering::Relaxed) {
+        let _ = tx.send(State::Reset);
     }
 }
 
@@ -245,7 +345,7 @@ where
             let option = ready!(this.inner.poll_data(cx));
 
             if option.is_none() {
-                let _ = tx.send(State::Reset);
+                send_reset(tx, this.reset_sent);
             }
 
             Poll::Ready(option)
@@ -266,7 +366,7 @@ where
             let is_end_stream = self.inner.is_end_stream();
 
             if is_end_stream {
-                let _ = tx.send(State::Reset);
+                send_reset(tx, &self.reset_sent);
             }
 
             is_end_stream
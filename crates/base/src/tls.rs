@@ -0,0 +1,435 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context};
+use arc_swap::ArcSwap;
+use log::{debug, error, info, warn};
+use notify::{RecursiveMode, Watcher};
+use rustls_pemfile::{read_one_from_slice, Item};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tokio_rustls::rustls::crypto::ring::sign::any_supported_type;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+use tokio_util::sync::CancellationToken;
+use x509_parser::extensions::GeneralName;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+/// Certificate and key files will often be replaced in several steps (e.g.
+/// the atomic writer of Kubernetes swaps a symlink of the directory), so we
+/// wait for the changes to settle down before reloading.
+static RELOAD_DEBOUNCE_DUR: Duration = Duration::from_millis(500);
+
+pub(crate) fn parse_key(key: &[u8]) -> anyhow::Result<PrivateKeyDer<'static>> {
+    let Some((key_item, _)) =
+        read_one_from_slice(key).map_err(|err| anyhow!("can't resolve key: {:?}", err))?
+    else {
+        bail!("invalid key data")
+    };
+
+    Ok(match key_item {
+        Item::Pkcs1Key(key) => PrivateKeyDer::Pkcs1(key),
+        Item::Pkcs8Key(key) => PrivateKeyDer::Pkcs8(key),
+        Item::Sec1Key(key) => PrivateKeyDer::Sec1(key),
+        _ => bail!("invalid key data"),
+    })
+}
+
+pub(crate) fn parse_cert_chain(cert: &[u8]) -> anyhow::Result<Vec<CertificateDer<'static>>> {
+    let mut cert_chain = vec![];
+    let mut cert_slice = cert;
+    loop {
+        let Some((Item::X509Certificate(cert), remain_cert_slice)) =
+            read_one_from_slice(cert_slice)
+                .map_err(|err| anyhow!("can't resolve cert: {:?}", err))?
+        else {
+            bail!("invalid cert data")
+        };
+
+        cert_chain.push(cert);
+
+        if remain_cert_slice.is_empty() {
+            break;
+        }
+
+        cert_slice = remain_cert_slice;
+    }
+
+    Ok(cert_chain)
+}
+
+pub(crate) fn new_certified_key(
+    key: &PrivateKeyDer<'static>,
+    cert_chain: Vec<CertificateDer<'static>>,
+) -> anyhow::Result<CertifiedKey> {
+    let signing_key = any_supported_type(key).with_context(|| "unsupported private key type")?;
+
+    Ok(CertifiedKey::new(cert_chain, signing_key))
+}
+
+/// Paths of the PEM files that a certificate was loaded from.
+#[derive(Debug, Clone)]
+pub(crate) struct TlsFiles {
+    pub key: PathBuf,
+    pub cert: PathBuf,
+}
+
+impl TlsFiles {
+    fn read(&self) -> anyhow::Result<(PrivateKeyDer<'static>, Vec<CertificateDer<'static>>)> {
+        let key = std::fs::read(&self.key)
+            .with_context(|| format!("can't read key file: {}", self.key.display()))?;
+        let cert = std::fs::read(&self.cert)
+            .with_context(|| format!("can't read cert file: {}", self.cert.display()))?;
+
+        Ok((parse_key(&key)?, parse_cert_chain(&cert)?))
+    }
+}
+
+/// A certificate and its key, optionally backed by files so that it can be
+/// reloaded.
+#[derive(Debug)]
+pub(crate) struct CertSource {
+    key: PrivateKeyDer<'static>,
+    cert_chain: Vec<CertificateDer<'static>>,
+    files: Option<TlsFiles>,
+}
+
+impl Clone for CertSource {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key.clone_key(),
+            cert_chain: self.cert_chain.clone(),
+            files: self.files.clone(),
+        }
+    }
+}
+
+impl CertSource {
+    pub fn from_pem(key: &[u8], cert: &[u8]) -> anyhow::Result<Self> {
+        Ok(Self {
+            key: parse_key(key)?,
+            cert_chain: parse_cert_chain(cert)?,
+            files: None,
+        })
+    }
+
+    pub fn from_files(files: TlsFiles) -> anyhow::Result<Self> {
+        let (key, cert_chain) = files.read()?;
+
+        Ok(Self {
+            key,
+            cert_chain,
+            files: Some(files),
+        })
+    }
+
+    fn load(&self, reload: bool) -> anyhow::Result<CertifiedKey> {
+        match self.files.as_ref() {
+            Some(files) if reload => {
+                let (key, cert_chain) = files.read()?;
+                new_certified_key(&key, cert_chain)
+            }
+
+            _ => new_certified_key(&self.key, self.cert_chain.clone()),
+        }
+    }
+}
+
+/// Scans a directory for certificate/key pairs, named after the server name
+/// they serve.
+///
+/// Both the `<name>.pem` + `<name>-key.pem` (mkcert style) and `<name>.crt` +
+/// `<name>.key` conventions are recognized. A leading `_wildcard.` in the name
+/// stands for `*.`.
+fn scan_sni_dir(dir: &Path) -> anyhow::Result<Vec<(String, TlsFiles)>> {
+    let mut pairs = vec![];
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("can't read directory: {}", dir.display()))?;
+
+    for entry in entries {
+        let cert = entry?.path();
+        let Some(file_name) = cert.file_name().and_then(|it| it.to_str()) else {
+            continue;
+        };
+
+        let (name, key) = if let Some(name) = file_name.strip_suffix(".crt") {
+            (name, cert.with_file_name(format!("{}.key", name)))
+        } else if let Some(name) = file_name
+            .strip_suffix(".pem")
+            .filter(|it| !it.ends_with("-key"))
+        {
+            (name, cert.with_file_name(format!("{}-key.pem", name)))
+        } else {
+            continue;
+        };
+
+        if !key.is_file() {
+            warn!("skipping {}: no matching key file", cert.display());
+            continue;
+        }
+
+        let name = match name.strip_prefix("_wildcard.") {
+            Some(rest) => format!("*.{}", rest),
+            None => name.to_string(),
+        };
+
+        pairs.push((name, TlsFiles { key, cert }));
+    }
+
+    Ok(pairs)
+}
+
+/// Everything the certificates of a TLS listener are loaded from.
+#[derive(Debug, Clone)]
+pub(crate) struct CertConfig {
+    pub default: CertSource,
+    pub sni: Vec<(String, CertSource)>,
+    pub sni_dirs: Vec<PathBuf>,
+}
+
+impl CertConfig {
+    fn load(&self, reload: bool) -> anyhow::Result<CertStore> {
+        let mut by_name = HashMap::new();
+
+        for dir in &self.sni_dirs {
+            for (name, files) in scan_sni_dir(dir)? {
+                let (key, cert_chain) = files.read()?;
+                by_name.insert(
+                    name.to_ascii_lowercase(),
+                    Arc::new(new_certified_key(&key, cert_chain)?),
+                );
+            }
+        }
+
+        // explicitly specified pairs take precedence over the directories.
+        for (name, source) in &self.sni {
+            by_name.insert(name.to_ascii_lowercase(), Arc::new(source.load(reload)?));
+        }
+
+        Ok(CertStore {
+            default: Arc::new(self.default.load(reload)?),
+            by_name,
+        })
+    }
+
+    fn watch_dirs(&self) -> Vec<PathBuf> {
+        let files = std::iter::once(&self.default)
+            .chain(self.sni.iter().map(|(_, it)| it))
+            .filter_map(|it| it.files.as_ref())
+            .flat_map(|it| [&it.key, &it.cert])
+            .filter_map(|it| it.parent())
+            .map(|it| {
+                if it.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    it
+                }
+            });
+
+        let mut dirs = files
+            .chain(self.sni_dirs.iter().map(PathBuf::as_path))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+}
+
+struct CertStore {
+    default: Arc<CertifiedKey>,
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl CertStore {
+    fn resolve(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let Some(server_name) = server_name.map(str::to_ascii_lowercase) else {
+            return self.default.clone();
+        };
+
+        if let Some(key) = self.by_name.get(&server_name) {
+            return key.clone();
+        }
+
+        // a wildcard only covers a single label.
+        server_name
+            .split_once('.')
+            .and_then(|(_, parent)| self.by_name.get(&format!("*.{}", parent)))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+/// Serves a certificate for every handshake, selected by the server name
+/// (SNI) the client asked for, falling back to the default certificate.
+///
+/// The certificates can be swapped at any time, which only affects handshakes
+/// that happen afterward. Established connections keep using the certificate
+/// they were negotiated with.
+pub(crate) struct CertResolver {
+    current: ArcSwap<CertStore>,
+}
+
+impl std::fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertResolver").finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.load().resolve(client_hello.server_name()))
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct CertReloader {
+    config: CertConfig,
+    resolver: Arc<CertResolver>,
+}
+
+impl CertReloader {
+    pub fn new(config: CertConfig) -> anyhow::Result<(Self, Arc<CertResolver>)> {
+        let resolver = Arc::new(CertResolver {
+            current: ArcSwap::from_pointee(config.load(false)?),
+        });
+
+        Ok((
+            Self {
+                config,
+                resolver: resolver.clone(),
+            },
+            resolver,
+        ))
+    }
+
+    /// Reloads the certificates from the files. If it fails, the previous
+    /// certificates are kept.
+    pub fn reload(&self) {
+        match self.config.load(true) {
+            Ok(store) => {
+                self.resolver.current.store(Arc::new(store));
+                info!("TLS certificate reloaded");
+            }
+
+            Err(err) => {
+                error!(
+                    "failed to reload TLS certificate, keep serving with the previous one: {:#}",
+                    err
+                );
+            }
+        }
+    }
+
+    /// Watches the key and cert files and reloads the certificates whenever
+    /// they change, until the token is cancelled.
+    pub async fn watch(self, token: CancellationToken) {
+        let dirs = self.config.watch_dirs();
+
+        if dirs.is_empty() {
+            return;
+        }
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |res: notify::Result<_>| {
+            if let Ok(notify::Event { kind, .. }) = res {
+                if !matches!(kind, notify::EventKind::Access(_)) {
+                    let _ = tx.send(());
+                }
+            }
+        }) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                warn!("can't watch TLS certificate files: {}", err);
+                return;
+            }
+        };
+
+        // NOTE: We watch the parent directories rather than the files
+        // themselves, since the files are usually replaced rather than
+        // modified in place, which would silently detach a watch on them.
+        for dir in dirs {
+            if let Err(err) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                warn!("can't watch {}: {}", dir.display(), err);
+                return;
+            }
+        }
+
+        loop {
+            tokio::select! {
+                Some(_) = rx.recv() => {
+                    sleep(RELOAD_DEBOUNCE_DUR).await;
+                    while rx.try_recv().is_ok() {}
+
+                    debug!("TLS certificate files changed");
+                    self.reload();
+                }
+
+                _ = token.cancelled() => break,
+            }
+        }
+    }
+}
+
+/// Identity of a client certificate verified during the handshake.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientCertIdentity {
+    pub subject: String,
+    pub sans: Vec<String>,
+    /// Lowercase hex-encoded SHA-256 digest of the DER encoded certificate.
+    pub fingerprint: String,
+}
+
+impl ClientCertIdentity {
+    pub fn from_der(der: &CertificateDer<'_>) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der.as_ref()).ok()?;
+        let sans = cert
+            .subject_alternative_name()
+            .ok()
+            .flatten()
+            .map(|ext| {
+                ext.value
+                    .general_names
+                    .iter()
+                    .filter_map(|name| match name {
+                        GeneralName::DNSName(it) => Some(format!("DNS:{}", it)),
+                        GeneralName::RFC822Name(it) => Some(format!("email:{}", it)),
+                        GeneralName::URI(it) => Some(format!("URI:{}", it)),
+                        GeneralName::IPAddress(it) => {
+                            ip_addr_from_octets(it).map(|it| format!("IP:{}", it))
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let mut fingerprint = String::with_capacity(64);
+
+        for byte in Sha256::digest(der.as_ref()) {
+            fingerprint.push_str(&format!("{:02x}", byte));
+        }
+
+        Some(Self {
+            subject: cert.subject().to_string(),
+            sans,
+            fingerprint,
+        })
+    }
+}
+
+fn ip_addr_from_octets(octets: &[u8]) -> Option<IpAddr> {
+    if let Ok(octets) = <[u8; 4]>::try_from(octets) {
+        Some(IpAddr::from(octets))
+    } else if let Ok(octets) = <[u8; 16]>::try_from(octets) {
+        Some(IpAddr::from(octets))
+    } else {
+        None
+    }
+}
@@ -218,7 +218,7 @@ impl TestBedBuilder {
             let token = TerminationToken::new();
             (
                 create_user_worker_pool(
-                    Arc::new(self.flags),
+                    Arc::new(self.flags.clone()),
                     self.worker_pool_policy
                         .unwrap_or_else(test_user_worker_pool_policy),
                     self.worker_event_sender,
@@ -295,6 +295,7 @@ impl TestBed {
             req,
             res_tx,
             conn_token: Some(conn_token.clone()),
+            conn_addrs: None,
         });
 
         let Ok(res) = res_rx.await else {
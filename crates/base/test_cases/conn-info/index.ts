@@ -0,0 +1,3 @@
+Deno.serve((_req: Request, info: Deno.ServeHandlerInfo) => {
+    return Response.json({ remoteAddr: info.remoteAddr });
+});
@@ -0,0 +1,4 @@
+// Large and repetitive enough to be worth compressing.
+const items = Array.from({ length: 256 }, (_, idx) => ({ id: idx, name: `item ${idx}` }));
+
+Deno.serve(() => Response.json(items));
@@ -65,6 +65,19 @@ Deno.serve((req: Request) => {
       // 	return await callWorker();
       // }
 
+      if (e instanceof Deno.errors.WorkerCircuitOpen) {
+        return new Response(
+          JSON.stringify({ msg: e.toString() }),
+          {
+            status: 503,
+            headers: {
+              "Content-Type": "application/json",
+              "Retry-After": String(Math.ceil((e.retryAfterMs ?? 0) / 1000)),
+            },
+          },
+        );
+      }
+
       const error = { msg: e.toString() }
       return new Response(
         JSON.stringify(error),
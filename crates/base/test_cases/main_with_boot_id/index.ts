@@ -0,0 +1,12 @@
+// A different ID every time the main worker boots.
+const bootId = crypto.randomUUID();
+
+Deno.serve(async (req: Request) => {
+	const delayMs = Number(new URL(req.url).searchParams.get("delay") ?? 0);
+
+	if (delayMs > 0) {
+		await new Promise(res => setTimeout(res, delayMs));
+	}
+
+	return new Response(bootId);
+});
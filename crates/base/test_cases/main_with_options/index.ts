@@ -0,0 +1,70 @@
+console.log('main function started');
+
+Deno.serve(async (req: Request) => {
+	const url = new URL(req.url);
+	const { pathname } = url;
+	const path_parts = pathname.split('/');
+	const service_name = path_parts[1];
+
+	if (!service_name || service_name === '') {
+		const error = { msg: 'missing function name in request' };
+		return new Response(
+			JSON.stringify(error),
+			{ status: 400, headers: { 'Content-Type': 'application/json' } },
+		);
+	}
+
+	const servicePath = `./test_cases/${service_name}`;
+	console.error(`serving the request with ${servicePath}`);
+
+	const createWorker = async () => {
+		const memoryLimitMb = 150;
+		const workerTimeoutMs = 1 * 60 * 1000;
+		const noModuleCache = false;
+		const importMapPath = null;
+		const envVarsObj = Deno.env.toObject();
+		const envVars = Object.keys(envVarsObj).map((k) => [k, envVarsObj[k]]);
+		// extra options of `EdgeRuntime.userWorkers.create()`, given as JSON.
+		const extraOptions = JSON.parse(req.headers.get('x-worker-options') ?? '{}');
+
+		return await EdgeRuntime.userWorkers.create({
+			servicePath,
+			memoryLimitMb,
+			workerTimeoutMs,
+			noModuleCache,
+			importMapPath,
+			envVars,
+			...extraOptions,
+		});
+	};
+
+	const callWorker = async () => {
+		try {
+			const worker = await createWorker();
+			return await worker.fetch(req);
+		} catch (e) {
+			console.error(e);
+
+			if (e instanceof Deno.errors.WorkerQueueFull) {
+				return new Response(
+					JSON.stringify({ msg: e.toString() }),
+					{
+						status: 503,
+						headers: {
+							'Content-Type': 'application/json',
+							'Retry-After': String(Math.ceil((e.retryAfterMs ?? 0) / 1000)),
+						},
+					},
+				);
+			}
+
+			const error = { msg: e.toString() };
+			return new Response(
+				JSON.stringify(error),
+				{ status: 500, headers: { 'Content-Type': 'application/json' } },
+			);
+		}
+	};
+
+	return callWorker();
+});
@@ -2,7 +2,7 @@
 #![allow(clippy::async_yields_async)]
 
 use deno_config::JsxImportSourceConfig;
-use event_worker::events::{LogLevel, WorkerEvents};
+use event_worker::events::{CircuitState, LogLevel, ShutdownReason, WorkerEvents};
 use http_v02::{self as http, HeaderValue};
 use hyper_v014 as hyper;
 use reqwest_v011 as reqwest;
@@ -26,9 +26,19 @@ use base::utils::test_utils::{
     TestBedBuilder,
 };
 use base::{
+    commands::start_server,
     integration_test, integration_test_listen_fut, integration_test_with_server_flag,
-    rt_worker::worker_ctx::{create_user_worker_pool, create_worker, TerminationToken},
-    server::{Server, ServerEvent, ServerFlags, ServerHealth, Tls},
+    rt_worker::{
+        worker_ctx::{create_user_worker_pool, create_worker, TerminationToken},
+        worker_pool::{CircuitBreakerPolicy, RoutingStrategy, SupervisorPolicy, WorkerPoolPolicy},
+    },
+    server::{
+        AccessLogConfig, AccessLogFormat, AccessLogTarget, ClientAuthMode, CompressionConfig,
+        ContentCoding, RateLimitConfig, RateLimitKey, Server, ServerEvent, ServerFlags,
+        ServerHealth, Tls, WorkerEntrypoints, CLIENT_CERT_FINGERPRINT_HEADER,
+        CLIENT_CERT_SAN_HEADER, CLIENT_CERT_SUBJECT_HEADER, REQUEST_ID_HEADER,
+        TLS_SERVER_NAME_HEADER,
+    },
     DecoratorType,
 };
 use deno_core::serde_json::{self, json};
@@ -67,11 +77,16 @@ use urlencoding::encode;
 const MB: usize = 1024 * 1024;
 const NON_SECURE_PORT: u16 = 8498;
 const SECURE_PORT: u16 = 4433;
+const METRICS_PORT: u16 = 9498;
 const TESTBED_DEADLINE_SEC: u64 = 20;
 
 const TLS_LOCALHOST_ROOT_CA: &[u8] = include_bytes!("./fixture/tls/root-ca.pem");
 const TLS_LOCALHOST_CERT: &[u8] = include_bytes!("./fixture/tls/localhost.pem");
 const TLS_LOCALHOST_KEY: &[u8] = include_bytes!("./fixture/tls/localhost-key.pem");
+const TLS_SNI_CERT: &[u8] = include_bytes!("./fixture/tls/sni/edge-runtime.test.pem");
+const TLS_CLIENT_CA: &[u8] = include_bytes!("./fixture/tls/client/ca.pem");
+const TLS_CLIENT_CERT: &[u8] = include_bytes!("./fixture/tls/client/client.pem");
+const TLS_CLIENT_KEY: &[u8] = include_bytes!("./fixture/tls/client/client-key.pem");
 
 #[tokio::test]
 #[serial]
@@ -234,6 +249,7 @@ async fn test_not_trigger_pku_sigsegv_due_to_jit_compilation_non_cli() {
         req,
         res_tx,
         conn_token: Some(conn_token.clone()),
+        conn_addrs: None,
     };
 
     let _ = ctx.msg_tx.send(msg);
@@ -523,6 +539,7 @@ async fn test_main_worker_user_worker_mod_evaluate_exception() {
         req,
         res_tx,
         conn_token: Some(conn_token.clone()),
+        conn_addrs: None,
     };
 
     let _ = ctx.msg_tx.send(msg);
@@ -992,6 +1009,57 @@ async fn req_failure_case_timeout() {
     assert!(found_timeout);
 }
 
+#[tokio::test]
+#[serial]
+async fn req_failure_case_queue_full() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_options")
+        .with_worker_pool_policy(WorkerPoolPolicy::new(
+            SupervisorPolicy::oneshot(),
+            1,
+            ServerFlags {
+                // NOTE: No request may wait, so the one that doesn't get the
+                // only worker is turned away right away.
+                max_queued_requests: Some(0),
+                ..Default::default()
+            },
+        ))
+        .build()
+        .await;
+
+    let req_body_fn = |b: http::request::Builder| {
+        b.uri("/slow_resp")
+            .method("GET")
+            .body(Body::empty())
+            .context("can't make request")
+    };
+
+    let (res1, res2) = join!(tb.request(req_body_fn), tb.request(req_body_fn));
+
+    let mut found_shed = false;
+
+    for res in [res1, res2] {
+        let mut res = res.unwrap();
+
+        if res.status() == StatusCode::SERVICE_UNAVAILABLE {
+            let retry_after = res.headers().get(header::RETRY_AFTER).cloned();
+            let buf = to_bytes(res.body_mut()).await.unwrap();
+
+            assert_eq!(retry_after, Some(HeaderValue::from_static("1")));
+            assert_eq!(
+                buf,
+                "{\"msg\":\"WorkerQueueFull: too many requests are waiting for a worker of the service\"}"
+            );
+
+            found_shed = true;
+        } else {
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+    }
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+    assert!(found_shed);
+}
+
 #[tokio::test]
 #[serial]
 async fn req_failure_case_cpu_time_exhausted() {
@@ -1483,396 +1551,556 @@ async fn test_graceful_shutdown() {
 
 #[tokio::test]
 #[serial]
-async fn test_websocket_upgrade_deno_non_secure() {
-    test_websocket_upgrade(new_localhost_tls(false), false).await;
-}
-
-#[tokio::test]
-#[serial]
-async fn test_websocket_upgrade_deno_secure() {
-    test_websocket_upgrade(new_localhost_tls(true), false).await;
-}
-
-#[tokio::test]
-#[serial]
-async fn test_websocket_upgrade_node_non_secure() {
-    test_websocket_upgrade(new_localhost_tls(false), true).await;
-}
+async fn test_request_hard_timeout() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            request_hard_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |(.., metric_src)| async move {
+                let started_at = std::time::Instant::now();
+                let resp =
+                    reqwest::get(format!("http://localhost:{}/sleep-5000ms", NON_SECURE_PORT))
+                        .await
+                        .unwrap();
+
+                assert_eq!(resp.status().as_u16(), StatusCode::GATEWAY_TIMEOUT);
+                assert!(started_at.elapsed() < Duration::from_secs(5));
+
+                let request_id = resp
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|it| it.to_str().ok())
+                    .map(str::to_string)
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_websocket_upgrade_node_secure() {
-    test_websocket_upgrade(new_localhost_tls(true), true).await;
-}
+                let body = resp.json::<serde_json::Value>().await.unwrap();
 
-async fn test_decorators(ty: Option<DecoratorType>) {
-    let is_disabled = ty.is_none();
-    let client = Client::new();
+                assert_eq!(body["class"], "RequestTimedOut");
+                assert_eq!(body["requestId"], request_id.as_str());
 
-    let endpoint = if is_disabled {
-        "tc39".to_string()
-    } else {
-        serde_json::to_string(&ty).unwrap().replace('\"', "")
-    };
+                // the request must be accounted as handled so that graceful
+                // shutdown doesn't wait for it.
+                timeout(Duration::from_secs(5), async {
+                    while metric_src.received_requests() != metric_src.handled_requests() {
+                        sleep(Duration::from_millis(50)).await;
+                    }
+                })
+                .await
+                .unwrap();
 
-    let payload = if is_disabled {
-        serde_json::json!({})
-    } else {
-        serde_json::json!({
-            "decoratorType": ty
-        })
-    };
+                assert_eq!(metric_src.received_requests(), 1);
 
-    let req = client
-        .request(
-            Method::OPTIONS,
-            format!(
-                "http://localhost:{}/decorator_{}",
-                NON_SECURE_PORT, endpoint
-            ),
-        )
-        .json(&payload)
-        .build()
-        .unwrap();
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
+}
 
-    integration_test!(
-        "./test_cases/main_with_decorator",
+#[tokio::test]
+#[serial]
+async fn test_request_id() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            trust_request_id: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
         NON_SECURE_PORT,
         "",
         None,
         None,
-        Some(RequestBuilder::from_parts(client, req)),
         None,
-        (|resp| async {
-            let resp = resp.unwrap();
+        None,
+        (
+            |_| async move {
+                let client = Client::new();
+                let echo_request_id = |request_id: &'static str| {
+                    let client = client.clone();
 
-            if is_disabled {
-                assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
-                assert!(resp
-                    .text()
-                    .await
-                    .unwrap()
-                    .starts_with("{\"msg\":\"InvalidWorkerCreation: worker boot error: Uncaught SyntaxError: Invalid or unexpected token"),);
-            } else {
-                assert_eq!(resp.status(), StatusCode::OK);
-                assert_eq!(resp.text().await.unwrap().as_str(), "meow?");
+                    async move {
+                        let resp = client
+                            .get(format!("http://localhost:{}/echo-headers", NON_SECURE_PORT))
+                            .header(REQUEST_ID_HEADER, request_id)
+                            .send()
+                            .await
+                            .unwrap();
+
+                        assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+                        let echoed = resp
+                            .headers()
+                            .get(REQUEST_ID_HEADER)
+                            .and_then(|it| it.to_str().ok())
+                            .map(str::to_string)
+                            .unwrap();
+
+                        let headers = resp.json::<HashMap<String, String>>().await.unwrap();
+
+                        // the worker must see the same ID as the client.
+                        assert_eq!(headers.get(REQUEST_ID_HEADER), Some(&echoed));
+
+                        echoed
+                    }
+                };
+
+                assert_eq!(echo_request_id("req-0123456789").await, "req-0123456789");
+
+                // an ID that doesn't look sane is replaced even if trusted.
+                let generated = echo_request_id("not a request id").await;
+
+                assert_ne!(generated, "not a request id");
+                assert!(uuid::Uuid::parse_str(&generated).is_ok());
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
             }
-        }),
+        ),
         TerminationToken::new()
     );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_decorator_should_be_syntax_error() {
-    test_decorators(None).await;
-}
+async fn test_max_connections_per_ip() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            max_connections_per_ip: Some(1),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let connect = || async {
+                    let stream = TcpStream::connect(("127.0.0.1", NON_SECURE_PORT))
+                        .await
+                        .unwrap();
+                    let (sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_decorator_parse_tc39() {
-    test_decorators(Some(DecoratorType::Tc39)).await;
-}
+                    tokio::spawn(conn);
+                    sender
+                };
 
-#[tokio::test]
-#[serial]
-async fn test_decorator_parse_typescript_experimental_with_metadata() {
-    test_decorators(Some(DecoratorType::TypescriptWithMetadata)).await;
-}
+                let request = || {
+                    Request::builder()
+                        .uri("/echo-headers")
+                        .header(http::header::HOST, "localhost")
+                        .body(Body::empty())
+                        .unwrap()
+                };
 
-#[tokio::test]
-#[serial]
-async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_stability() {
-    let tb = TestBedBuilder::new("./test_cases/main")
-        .with_oneshot_policy(None)
-        .build()
-        .await;
+                let mut first = connect().await;
+                let res = first.send_request(request()).await.unwrap();
 
-    let mut resp1 = tb
-        .request(|b| {
-            b.uri("/chunked-char-1000ms")
-                .method("GET")
-                .body(Body::empty())
-                .context("can't make request")
-        })
-        .await
-        .unwrap();
+                assert_eq!(res.status(), StatusCode::OK);
 
-    assert_eq!(resp1.status().as_u16(), StatusCode::OK);
+                // the first connection is still open, so the second one must
+                // be refused.
+                let mut second = connect().await;
 
-    let resp1_body = resp1.body_mut();
-    let resp1_chunk1 = resp1_body.next().await.unwrap().unwrap();
+                assert!(second.send_request(request()).await.is_err());
 
-    assert_eq!(resp1_chunk1, "m");
+                drop(first);
 
-    drop(resp1);
-    sleep(Duration::from_secs(1)).await;
+                // the slot is released once the server notices the first
+                // connection is gone.
+                timeout(Duration::from_secs(5), async {
+                    loop {
+                        let mut third = connect().await;
 
-    // NOTE(Nyannyacha): Before dc057b0, the statement below panics with the
-    // reason `connection closed before message completed`. This is the result
-    // of `Deno.serve` failing to properly handle an exception from a previous
-    // request.
-    let resp2 = tb
-        .request(|b| {
-            b.uri("/empty-response")
-                .method("GET")
-                .body(Body::empty())
-                .context("can't make request")
-        })
-        .await
-        .unwrap();
+                        if let Ok(res) = third.send_request(request()).await {
+                            assert_eq!(res.status(), StatusCode::OK);
+                            break;
+                        }
 
-    assert_eq!(resp2.status().as_u16(), StatusCode::NO_CONTENT);
+                        sleep(Duration::from_millis(50)).await;
+                    }
+                })
+                .await
+                .unwrap();
 
-    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn oak_with_jsr_specifier() {
-    integration_test!(
+async fn test_keepalive_idle_timeout() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            keepalive_idle_timeout_ms: Some(1000),
+            ..Default::default()
+        },
         "./test_cases/main",
         NON_SECURE_PORT,
-        "oak-with-jsr",
+        "",
         None,
         None,
         None,
         None,
-        (|resp| async {
-            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
-        }),
-        TerminationToken::new()
-    );
-}
+        (
+            |_| async move {
+                let stream = TcpStream::connect(("127.0.0.1", NON_SECURE_PORT))
+                    .await
+                    .unwrap();
+                let (mut sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+                let conn_task = tokio::spawn(conn);
 
-async fn test_slowloris<F, R>(request_read_timeout_ms: u64, maybe_tls: Option<Tls>, test_fn: F)
-where
-    F: (FnOnce(Box<dyn AsyncReadWrite>) -> R) + Send + 'static,
-    R: Future<Output = bool> + Send,
-{
-    let token = TerminationToken::new();
+                let req = Request::builder()
+                    .uri("/echo-headers")
+                    .header(http::header::HOST, "localhost")
+                    .body(Body::empty())
+                    .unwrap();
 
-    let (health_tx, mut health_rx) = mpsc::channel(1);
-    let (tx, rx) = oneshot::channel();
+                let res = sender.send_request(req).await.unwrap();
 
-    let mut listen_fut = integration_test_listen_fut!(
-        NON_SECURE_PORT,
-        maybe_tls,
-        "./test_cases/main",
-        None,
-        None,
-        ServerFlags {
-            request_read_timeout_ms: Some(request_read_timeout_ms),
-            ..Default::default()
-        },
-        health_tx,
-        Some(token.clone())
-    );
+                assert_eq!(res.status(), StatusCode::OK);
 
-    let req_fut = {
-        let token = token.clone();
-        async move {
-            assert!(test_fn(maybe_tls.stream().await).await);
+                let _ = to_bytes(res.into_body()).await.unwrap();
+                let idle_since = std::time::Instant::now();
 
-            if timeout(Duration::from_secs(10), token.cancel_and_wait())
-                .await
-                .is_err()
-            {
-                panic!("failed to terminate server within 10 seconds");
-            }
+                // the server must close the connection once it has been idle
+                // for long enough.
+                timeout(Duration::from_secs(5), conn_task)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .unwrap();
 
-            tx.send(()).unwrap();
-        }
-    };
+                assert!(idle_since.elapsed() >= Duration::from_millis(900));
 
-    let join_fut = tokio::spawn(async move {
-        loop {
-            if let Some(ServerHealth::Listening(..)) = health_rx.recv().await {
-                break;
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
             }
-        }
-
-        req_fut.await;
-    });
-
-    tokio::select! {
-        _ = join_fut => {}
-        _ = &mut listen_fut => {}
-    };
-
-    if timeout(Duration::from_secs(10), rx).await.is_err() {
-        panic!("failed to check within 10 seconds");
-    }
+        ),
+        TerminationToken::new()
+    );
 }
 
-async fn test_slowloris_no_prompt_timeout(maybe_tls: Option<Tls>, invert: bool) {
-    test_slowloris(
-        if invert { u64::MAX } else { 5000 },
-        maybe_tls,
-        move |mut io| async move {
-            static HEADER: &[u8] = b"GET /oak-with-jsr HTTP/1.1\r\nHost: localhost\r\n\r\n";
+#[tokio::test]
+#[serial]
+async fn test_rate_limit() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            rate_limit: Some(RateLimitConfig {
+                key: "header:x-client-id".parse::<RateLimitKey>().unwrap(),
+                rate: 1,
+                burst: 2,
+            }),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |(.., metric_src)| async move {
+                let client = Client::new();
+                let send = |client_id: &'static str| {
+                    client
+                        .get(format!("http://localhost:{}/echo-headers", NON_SECURE_PORT))
+                        .header("x-client-id", client_id)
+                        .send()
+                };
 
-            let check_io_kind_fn = move |err: std::io::Error| {
-                if invert {
-                    return true;
-                }
+                // the requests are sent at once, so that the bucket can't
+                // refill while the user worker is booting.
+                let mut resps = futures_util::future::join_all([send("a"), send("a"), send("a")])
+                    .await
+                    .into_iter()
+                    .map(Result::unwrap)
+                    .collect::<Vec<_>>();
 
-                matches!(
-                    err.kind(),
-                    io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
-                )
-            };
+                resps.sort_by_key(|it| it.status());
 
-            // > 5000ms
-            sleep(Duration::from_secs(10)).await;
+                let resp = resps.pop().unwrap();
 
-            if let Err(err) = io.write_all(HEADER).await {
-                return check_io_kind_fn(err);
-            }
+                assert!(resps
+                    .iter()
+                    .all(|it| it.status().as_u16() == StatusCode::OK));
+                assert_eq!(resp.status().as_u16(), StatusCode::TOO_MANY_REQUESTS);
+                assert_eq!(
+                    resp.headers()
+                        .get(http::header::RETRY_AFTER)
+                        .and_then(|it| it.to_str().ok()),
+                    Some("1")
+                );
 
-            if let Err(err) = io.flush().await {
-                return check_io_kind_fn(err);
-            }
+                let body = resp.json::<serde_json::Value>().await.unwrap();
 
-            let mut buf = vec![0; 1_048_576];
+                assert_eq!(body["class"], "RateLimited");
 
-            match io.read(&mut buf).await {
-                Ok(nread) => {
-                    if invert {
-                        nread > 0
-                    } else {
-                        nread == 0
-                    }
-                }
+                // other keys have buckets of their own.
+                assert_eq!(send("b").await.unwrap().status().as_u16(), StatusCode::OK);
 
-                Err(err) => check_io_kind_fn(err),
+                // rejected requests must never reach the main worker.
+                assert_eq!(metric_src.rate_limited_requests(), 1);
+                assert_eq!(metric_src.received_requests(), 3);
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
             }
-        },
-    )
-    .await;
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_slowloris_no_prompt_timeout_non_secure() {
-    test_slowloris_no_prompt_timeout(new_localhost_tls(false), false).await;
-}
+async fn test_access_log() {
+    let log_file = tempfile::NamedTempFile::new().unwrap();
+    let log_path = log_file.path().to_path_buf();
 
-#[tokio::test]
-#[serial]
-#[ignore = "too slow"]
-async fn test_slowloris_no_prompt_timeout_non_secure_inverted() {
-    test_slowloris_no_prompt_timeout(new_localhost_tls(false), true).await;
-}
+    integration_test_with_server_flag!(
+        ServerFlags {
+            request_hard_timeout_ms: Some(1000),
+            access_log: Some(AccessLogConfig {
+                format: AccessLogFormat::Json,
+                target: AccessLogTarget::File(log_path.clone()),
+                include_query: false,
+            }),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                // the query string is left out of the log by default.
+                let resp = reqwest::get(format!(
+                    "http://localhost:{}/echo-headers?token=secret",
+                    NON_SECURE_PORT
+                ))
+                .await
+                .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_slowloris_no_prompt_timeout_secure() {
-    test_slowloris_no_prompt_timeout(new_localhost_tls(true), false).await;
-}
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
 
-#[tokio::test]
-#[serial]
-#[ignore = "too slow"]
-async fn test_slowloris_no_prompt_timeout_secure_inverted() {
-    test_slowloris_no_prompt_timeout(new_localhost_tls(true), true).await;
-}
+                let request_id = resp
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|it| it.to_str().ok())
+                    .map(str::to_string)
+                    .unwrap();
 
-async fn test_slowloris_slow_header_timedout(maybe_tls: Option<Tls>, invert: bool) {
-    test_slowloris(
-        if invert { u64::MAX } else { 5000 },
-        maybe_tls,
-        move |mut io| async move {
-            static HEADER: &[u8] = b"GET /oak-with-jsr HTTP/1.1\r\nHost: localhost\r\n\r\n";
+                let _ = resp.bytes().await.unwrap();
 
-            let check_io_kind_fn = move |err: std::io::Error| {
-                if invert {
-                    return true;
-                }
+                // the main worker never responds to this one.
+                let resp =
+                    reqwest::get(format!("http://localhost:{}/sleep-5000ms", NON_SECURE_PORT))
+                        .await
+                        .unwrap();
 
-                matches!(
-                    err.kind(),
-                    io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
-                )
-            };
+                assert_eq!(resp.status().as_u16(), StatusCode::GATEWAY_TIMEOUT);
 
-            // takes 1000ms per each character (ie. > 5000ms)
-            for &b in HEADER {
-                if let Err(err) = io.write(&[b]).await {
-                    return check_io_kind_fn(err);
-                }
+                let _ = resp.bytes().await.unwrap();
 
-                if let Err(err) = io.flush().await {
-                    return check_io_kind_fn(err);
-                }
+                let lines = timeout(Duration::from_secs(5), async {
+                    loop {
+                        let content = tokio::fs::read_to_string(&log_path).await.unwrap();
+                        let lines = content
+                            .lines()
+                            .map(|it| serde_json::from_str::<serde_json::Value>(it).unwrap())
+                            .collect::<Vec<_>>();
+
+                        if lines.len() >= 2 {
+                            break lines;
+                        }
 
-                sleep(Duration::from_secs(1)).await;
-            }
+                        sleep(Duration::from_millis(50)).await;
+                    }
+                })
+                .await
+                .unwrap();
 
-            let mut buf = vec![0; 1_048_576];
+                assert_eq!(lines[0]["method"], "GET");
+                assert_eq!(lines[0]["path"], "/echo-headers");
+                assert_eq!(lines[0]["status"], 200);
+                assert_eq!(lines[0]["tls"], false);
+                assert_eq!(lines[0]["requestId"], request_id.as_str());
+                assert!(lines[0]["bytesOut"].as_u64().unwrap() > 0);
+                assert!(lines[0]["clientAddr"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("127.0.0.1:"));
 
-            match io.read(&mut buf).await {
-                Ok(nread) => {
-                    if invert {
-                        nread > 0
-                    } else {
-                        nread == 0
-                    }
-                }
+                assert_eq!(lines[1]["path"], "/sleep-5000ms");
+                assert_eq!(lines[1]["status"], 504);
+                assert!(lines[1]["latencyMs"].as_u64().unwrap() >= 1000);
 
-                Err(err) => check_io_kind_fn(err),
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
             }
-        },
-    )
-    .await;
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_slowloris_slow_header_timedout_non_secure() {
-    test_slowloris_slow_header_timedout(new_localhost_tls(false), false).await;
-}
+async fn test_main_worker_reload() {
+    let (tx, mut rx) = mpsc::channel(1);
+    let token = TerminationToken::new();
+    let mut server = Server::new(
+        "127.0.0.1",
+        NON_SECURE_PORT,
+        None,
+        "./test_cases/main_with_boot_id".to_string(),
+        None,
+        None,
+        None,
+        None,
+        ServerFlags {
+            graceful_exit_deadline_sec: 15,
+            ..Default::default()
+        },
+        Some(tx),
+        Default::default(),
+        Some(token.clone()),
+        vec![],
+        None,
+        None,
+        None,
+    )
+    .await
+    .unwrap();
 
-#[tokio::test]
-#[serial]
-#[ignore = "too slow 2x"]
-async fn test_slowloris_slow_header_timedout_non_secure_inverted() {
-    test_slowloris_slow_header_timedout(new_localhost_tls(false), true).await;
-}
+    let reloader = server.main_worker_reloader();
+    let handle = tokio::task::spawn(async move {
+        server.listen().await.unwrap();
+    });
 
-#[tokio::test]
-#[serial]
-async fn test_slowloris_slow_header_timedout_secure() {
-    test_slowloris_slow_header_timedout(new_localhost_tls(true), false).await;
+    let _ev = loop {
+        match rx.recv().await {
+            Some(health) => break health.into_listening().unwrap(),
+            _ => continue,
+        }
+    };
+
+    let client = Client::new();
+    let get_boot_id = |delay_ms: u64| {
+        let req = client.get(format!(
+            "http://localhost:{}/?delay={}",
+            NON_SECURE_PORT, delay_ms
+        ));
+
+        async move {
+            let resp = req.send().await.unwrap();
+
+            assert_eq!(resp.status().as_u16(), 200);
+            resp.text().await.unwrap()
+        }
+    };
+
+    let prev_boot_id = get_boot_id(0).await;
+
+    // the previous main worker must still answer the requests it has received
+    // before being replaced.
+    let in_flight = tokio::task::spawn(get_boot_id(2000));
+
+    sleep(Duration::from_millis(500)).await;
+    reloader.reload().await.unwrap();
+
+    let boot_id = get_boot_id(0).await;
+
+    assert_ne!(boot_id, prev_boot_id);
+    assert_eq!(in_flight.await.unwrap(), prev_boot_id);
+
+    token.cancel();
+    handle.await.unwrap();
 }
 
 #[tokio::test]
 #[serial]
-#[ignore = "too slow 2x"]
-async fn test_slowloris_slow_header_timedout_secure_inverted() {
-    test_slowloris_slow_header_timedout(new_localhost_tls(true), true).await;
-}
+async fn test_health_checks() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            health_on_main_port: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let resp = reqwest::get(format!("http://localhost:{}/livez", NON_SECURE_PORT))
+                    .await
+                    .unwrap();
 
-async fn test_request_idle_timeout_no_streamed_response(maybe_tls: Option<Tls>) {
-    let client = maybe_tls.client();
-    let req = client
-        .request(
-            Method::GET,
-            format!(
-                "{}://localhost:{}/sleep-5000ms",
-                maybe_tls.schema(),
-                maybe_tls.port(),
-            ),
-        )
-        .build()
-        .unwrap();
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
 
-    let original = RequestBuilder::from_parts(client, req);
-    let request_builder = Some(original);
+                let resp = reqwest::get(format!("http://localhost:{}/readyz", NON_SECURE_PORT))
+                    .await
+                    .unwrap();
+
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+                let body = resp.json::<serde_json::Value>().await.unwrap();
+
+                assert_eq!(body["ready"], true);
+                assert_eq!(body["mainWorker"], true);
+                assert_eq!(body["eventWorker"], serde_json::Value::Null);
+                assert_eq!(body["draining"], false);
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
+}
 
+#[tokio::test]
+#[serial]
+async fn test_max_request_body_bytes() {
     integration_test_with_server_flag!(
         ServerFlags {
-            request_idle_timeout_ms: Some(1000),
+            max_request_body_bytes: Some(16),
             ..Default::default()
         },
         "./test_cases/main",
@@ -1880,47 +2108,160 @@ async fn test_request_idle_timeout_no_streamed_response(maybe_tls: Option<Tls>)
         "",
         None,
         None,
-        request_builder,
-        maybe_tls,
-        (|resp| async {
-            assert_eq!(resp.unwrap().status().as_u16(), StatusCode::GATEWAY_TIMEOUT);
-        }),
+        None,
+        None,
+        (
+            |_| async move {
+                let client = Client::new();
+                let send = |body: &'static str| {
+                    client
+                        .post(format!("http://localhost:{}/echo-headers", NON_SECURE_PORT))
+                        .body(body)
+                        .send()
+                };
+
+                assert_eq!(
+                    send("within the limit").await.unwrap().status().as_u16(),
+                    StatusCode::OK
+                );
+
+                let resp = send("way over the limit of sixteen bytes").await.unwrap();
+
+                assert_eq!(resp.status().as_u16(), StatusCode::PAYLOAD_TOO_LARGE);
+
+                let body = resp.json::<serde_json::Value>().await.unwrap();
+
+                assert_eq!(body["class"], "RequestBodyTooLarge");
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
         TerminationToken::new()
     );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_request_idle_timeout_no_streamed_response_non_secure() {
-    test_request_idle_timeout_no_streamed_response(new_localhost_tls(false)).await;
+async fn test_user_worker_min_instances() {
+    integration_test!(
+        "./test_cases/main_with_options",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |(.., metric_src)| async move {
+                let resp = Client::new()
+                    .get(format!("http://localhost:{}/echo-headers", NON_SECURE_PORT))
+                    .header("x-worker-options", r#"{"minInstances":2}"#)
+                    .send()
+                    .await
+                    .unwrap();
+
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+                // spare workers are only booted as far as the parallelism of
+                // the pool allows.
+                let expected = std::thread::available_parallelism()
+                    .map(|it| it.get())
+                    .unwrap_or(1)
+                    .min(2);
+
+                timeout(Duration::from_secs(10), async {
+                    while metric_src.active_user_workers() < expected {
+                        sleep(Duration::from_millis(50)).await;
+                    }
+                })
+                .await
+                .unwrap();
+
+                assert_eq!(metric_src.active_user_workers(), expected);
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_request_idle_timeout_no_streamed_response_secure() {
-    test_request_idle_timeout_no_streamed_response(new_localhost_tls(true)).await;
-}
-
-async fn test_request_idle_timeout_streamed_response(maybe_tls: Option<Tls>) {
-    let client = maybe_tls.client();
-    let req = client
-        .request(
-            Method::GET,
-            format!(
-                "{}://localhost:{}/chunked-char-variable-delay-max-6000ms",
-                maybe_tls.schema(),
-                maybe_tls.port(),
-            ),
+async fn test_least_outstanding_routing() {
+    let tb = TestBedBuilder::new("./test_cases/main_with_options")
+        .with_worker_pool_policy(
+            WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 2, ServerFlags::default())
+                .with_routing(RoutingStrategy::LeastOutstanding),
         )
         .build()
-        .unwrap();
+        .await;
 
-    let original = RequestBuilder::from_parts(client, req);
-    let request_builder = Some(original);
+    // the user worker responds with an ID of its own, after `delay_ms`.
+    async fn worker_id_of(tb: &test_utils::TestBed, delay_ms: u64) -> String {
+        let mut res = tb
+            .request(|b| {
+                b.uri(format!("/main_with_boot_id?delay={}", delay_ms))
+                    .method("GET")
+                    .header("x-worker-options", r#"{"minInstances":2}"#)
+                    .body(Body::empty())
+                    .context("can't make request")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(res.status().as_u16(), StatusCode::OK);
+
+        let buf = to_bytes(res.body_mut()).await.unwrap();
+
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+
+    // workers that are equally loaded take turns, so both of them show up
+    // once the spare one has booted.
+    timeout(Duration::from_secs(10), async {
+        let first = worker_id_of(&tb, 0).await;
+
+        while worker_id_of(&tb, 0).await == first {
+            sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .unwrap();
 
+    // while one of the workers is busy, every request must go to the other.
+    let (busy, others) = join!(worker_id_of(&tb, 3000), async {
+        sleep(Duration::from_millis(500)).await;
+
+        let mut others = vec![];
+
+        for _ in 0..3 {
+            others.push(worker_id_of(&tb, 0).await);
+        }
+
+        others
+    });
+
+    assert!(others.iter().all(|it| *it != busy));
+
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_response_compression() {
     integration_test_with_server_flag!(
         ServerFlags {
-            request_idle_timeout_ms: Some(2000),
+            compression: Some(CompressionConfig {
+                codings: vec![ContentCoding::Zstd, ContentCoding::Gzip],
+                ..Default::default()
+            }),
             ..Default::default()
         },
         "./test_cases/main",
@@ -1928,424 +2269,462 @@ async fn test_request_idle_timeout_streamed_response(maybe_tls: Option<Tls>) {
         "",
         None,
         None,
-        request_builder,
-        maybe_tls,
-        (|resp| async {
-            let resp = resp.unwrap();
+        None,
+        None,
+        (
+            |_| async move {
+                let client = Client::new();
+                let send = |path: &'static str, accept_encoding: &'static str| {
+                    client
+                        .get(format!("http://localhost:{}/{}", NON_SECURE_PORT, path))
+                        .header(http::header::ACCEPT_ENCODING, accept_encoding)
+                        .send()
+                };
 
-            assert_eq!(resp.status().as_u16(), StatusCode::OK);
-            assert!(resp.content_length().is_none());
+                // NOTE: Only `zstd` is accepted, as `Deno.serve` may already
+                // compress with the others by itself.
+                let resp = send("large-json", "zstd").await.unwrap();
 
-            let mut buf = Vec::<u8>::new();
-            let mut bytes_stream = resp.bytes_stream();
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
+                assert_eq!(
+                    resp.headers()
+                        .get(http::header::CONTENT_ENCODING)
+                        .and_then(|it| it.to_str().ok()),
+                    Some("zstd")
+                );
+                assert_eq!(
+                    resp.headers()
+                        .get(http::header::VARY)
+                        .and_then(|it| it.to_str().ok()),
+                    Some("accept-encoding")
+                );
 
-            loop {
-                match bytes_stream.next().await {
-                    Some(Ok(v)) => {
-                        buf.extend(v);
-                    }
+                let compressed_len = resp.bytes().await.unwrap().len();
+                let resp = send("large-json", "zstd;q=0").await.unwrap();
 
-                    Some(Err(_)) => {
-                        break;
-                    }
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
+                assert!(resp.headers().get(http::header::CONTENT_ENCODING).is_none());
+                assert!(compressed_len < resp.bytes().await.unwrap().len());
 
-                    None => {
-                        break;
-                    }
-                }
-            }
+                // too small to be worth it.
+                let resp = send("echo-headers", "zstd").await.unwrap();
 
-            assert_eq!(&buf, b"meo");
-        }),
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
+                assert!(resp.headers().get(http::header::CONTENT_ENCODING).is_none());
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
         TerminationToken::new()
     );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_request_idle_timeout_streamed_response_non_secure() {
-    test_request_idle_timeout_streamed_response(new_localhost_tls(false)).await;
+async fn test_websocket_upgrade_deno_non_secure() {
+    test_websocket_upgrade(new_localhost_tls(false), false).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_request_idle_timeout_streamed_response_secure() {
-    test_request_idle_timeout_streamed_response(new_localhost_tls(true)).await;
+async fn test_websocket_upgrade_deno_secure() {
+    test_websocket_upgrade(new_localhost_tls(true), false).await;
 }
 
-async fn test_request_idle_timeout_streamed_response_first_chunk_timeout(maybe_tls: Option<Tls>) {
-    let client = maybe_tls.client();
+#[tokio::test]
+#[serial]
+async fn test_websocket_upgrade_node_non_secure() {
+    test_websocket_upgrade(new_localhost_tls(false), true).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_websocket_upgrade_node_secure() {
+    test_websocket_upgrade(new_localhost_tls(true), true).await;
+}
+
+async fn test_decorators(ty: Option<DecoratorType>) {
+    let is_disabled = ty.is_none();
+    let client = Client::new();
+
+    let endpoint = if is_disabled {
+        "tc39".to_string()
+    } else {
+        serde_json::to_string(&ty).unwrap().replace('\"', "")
+    };
+
+    let payload = if is_disabled {
+        serde_json::json!({})
+    } else {
+        serde_json::json!({
+            "decoratorType": ty
+        })
+    };
+
     let req = client
         .request(
-            Method::GET,
+            Method::OPTIONS,
             format!(
-                "{}://localhost:{}/chunked-char-first-6000ms",
-                maybe_tls.schema(),
-                maybe_tls.port(),
+                "http://localhost:{}/decorator_{}",
+                NON_SECURE_PORT, endpoint
             ),
         )
+        .json(&payload)
         .build()
         .unwrap();
 
-    let original = RequestBuilder::from_parts(client, req);
-    let request_builder = Some(original);
-
-    integration_test_with_server_flag!(
-        ServerFlags {
-            request_idle_timeout_ms: Some(1000),
-            ..Default::default()
-        },
-        "./test_cases/main",
+    integration_test!(
+        "./test_cases/main_with_decorator",
         NON_SECURE_PORT,
         "",
         None,
         None,
-        request_builder,
-        maybe_tls,
+        Some(RequestBuilder::from_parts(client, req)),
+        None,
         (|resp| async {
             let resp = resp.unwrap();
 
-            assert_eq!(resp.status().as_u16(), StatusCode::OK);
-            assert!(resp.content_length().is_none());
+            if is_disabled {
+                assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+                assert!(resp
+                    .text()
+                    .await
+                    .unwrap()
+                    .starts_with("{\"msg\":\"InvalidWorkerCreation: worker boot error: Uncaught SyntaxError: Invalid or unexpected token"),);
+            } else {
+                assert_eq!(resp.status(), StatusCode::OK);
+                assert_eq!(resp.text().await.unwrap().as_str(), "meow?");
+            }
+        }),
+        TerminationToken::new()
+    );
+}
 
-            let mut buf = Vec::<u8>::new();
-            let mut bytes_stream = resp.bytes_stream();
-
-            loop {
-                match bytes_stream.next().await {
-                    Some(Ok(v)) => {
-                        buf.extend(v);
-                    }
-
-                    Some(Err(_)) => {
-                        break;
-                    }
-
-                    None => {
-                        break;
-                    }
-                }
-            }
-
-            assert_eq!(&buf, b"");
-        }),
-        TerminationToken::new()
-    );
-}
+#[tokio::test]
+#[serial]
+async fn test_decorator_should_be_syntax_error() {
+    test_decorators(None).await;
+}
 
 #[tokio::test]
 #[serial]
-async fn test_request_idle_timeout_streamed_response_first_chunk_timeout_non_secure() {
-    test_request_idle_timeout_streamed_response_first_chunk_timeout(new_localhost_tls(false)).await;
+async fn test_decorator_parse_tc39() {
+    test_decorators(Some(DecoratorType::Tc39)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_request_idle_timeout_streamed_response_first_chunk_timeout_secure() {
-    test_request_idle_timeout_streamed_response_first_chunk_timeout(new_localhost_tls(true)).await;
+async fn test_decorator_parse_typescript_experimental_with_metadata() {
+    test_decorators(Some(DecoratorType::TypescriptWithMetadata)).await;
 }
 
-async fn test_request_idle_timeout_websocket_deno(maybe_tls: Option<Tls>, use_node_ws: bool) {
-    let nonce = tungstenite::handshake::client::generate_key();
-    let client = maybe_tls.client();
-    let req = client
-        .request(
-            Method::GET,
-            format!(
-                "{}://localhost:{}/websocket-upgrade-no-send{}",
-                maybe_tls.schema(),
-                maybe_tls.port(),
-                if use_node_ws { "-node" } else { "" }
-            ),
-        )
-        .header(header::CONNECTION, "upgrade")
-        .header(header::UPGRADE, "websocket")
-        .header(header::SEC_WEBSOCKET_KEY, &nonce)
-        .header(header::SEC_WEBSOCKET_VERSION, "13")
+#[tokio::test]
+#[serial]
+async fn send_partial_payload_into_closed_pipe_should_not_be_affected_worker_stability() {
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_oneshot_policy(None)
         .build()
-        .unwrap();
-
-    let original = RequestBuilder::from_parts(client, req);
-    let request_builder = Some(original);
-
-    integration_test_with_server_flag!(
-        ServerFlags {
-            request_idle_timeout_ms: Some(1000),
-            ..Default::default()
-        },
-        "./test_cases/main",
-        NON_SECURE_PORT,
-        "",
-        None,
-        None,
-        request_builder,
-        maybe_tls,
-        (|resp| async {
-            let res = resp.unwrap();
-            let accepted = get_upgrade_type(res.headers());
-
-            assert!(res.status().as_u16() == 101);
-            assert!(accepted.is_some());
-            assert_eq!(accepted.as_ref().unwrap(), "websocket");
-
-            let upgraded = res.upgrade().await.unwrap();
-            let mut ws = WebSocketStream::from_raw_socket(
-                upgraded.compat(),
-                tungstenite::protocol::Role::Client,
-                None,
-            )
-            .await;
-
-            sleep(Duration::from_secs(3)).await;
+        .await;
 
-            ws.send(Message::Text("meow!!".into())).await.unwrap();
+    let mut resp1 = tb
+        .request(|b| {
+            b.uri("/chunked-char-1000ms")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
 
-            let err = ws.next().await.unwrap().unwrap_err();
+    assert_eq!(resp1.status().as_u16(), StatusCode::OK);
 
-            use tungstenite::error::ProtocolError;
-            use tungstenite::Error;
+    let resp1_body = resp1.body_mut();
+    let resp1_chunk1 = resp1_body.next().await.unwrap().unwrap();
 
-            assert!(matches!(
-                err,
-                Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)
-            ));
-        }),
-        TerminationToken::new()
-    );
-}
+    assert_eq!(resp1_chunk1, "m");
 
-#[tokio::test]
-#[serial]
-async fn test_request_idle_timeout_websocket_deno_non_secure() {
-    test_request_idle_timeout_websocket_deno(new_localhost_tls(false), false).await;
-}
+    drop(resp1);
+    sleep(Duration::from_secs(1)).await;
 
-#[tokio::test]
-#[serial]
-async fn test_request_idle_timeout_websocket_deno_secure() {
-    test_request_idle_timeout_websocket_deno(new_localhost_tls(true), false).await;
-}
+    // NOTE(Nyannyacha): Before dc057b0, the statement below panics with the
+    // reason `connection closed before message completed`. This is the result
+    // of `Deno.serve` failing to properly handle an exception from a previous
+    // request.
+    let resp2 = tb
+        .request(|b| {
+            b.uri("/empty-response")
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_request_idle_timeout_websocket_node_non_secure() {
-    test_request_idle_timeout_websocket_deno(new_localhost_tls(false), true).await;
-}
+    assert_eq!(resp2.status().as_u16(), StatusCode::NO_CONTENT);
 
-#[tokio::test]
-#[serial]
-async fn test_request_idle_timeout_websocket_node_secure() {
-    test_request_idle_timeout_websocket_deno(new_localhost_tls(true), true).await;
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_should_not_hang_when_forced_redirection_for_specifiers() {
-    let (tx, rx) = oneshot::channel::<()>();
-
+async fn oak_with_jsr_specifier() {
     integration_test!(
         "./test_cases/main",
         NON_SECURE_PORT,
-        "concurrent-redirect",
+        "oak-with-jsr",
         None,
         None,
         None,
         None,
         (|resp| async {
-            assert_eq!(resp.unwrap().status().as_u16(), 200);
-            tx.send(()).unwrap();
+            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
         }),
         TerminationToken::new()
     );
-
-    if timeout(Duration::from_secs(10), rx).await.is_err() {
-        panic!("failed to check within 10 seconds");
-    }
 }
 
-async fn test_allow_net<F, R>(allow_net: Option<Vec<&str>>, url: &str, callback: F)
+async fn test_slowloris<F, R>(request_read_timeout_ms: u64, maybe_tls: Option<Tls>, test_fn: F)
 where
-    F: FnOnce(Result<Response, reqwest::Error>) -> R,
-    R: Future<Output = ()>,
+    F: (FnOnce(Box<dyn AsyncReadWrite>) -> R) + Send + 'static,
+    R: Future<Output = bool> + Send,
 {
-    let payload = serde_json::json!({
-        "allowNet": allow_net,
-        "url": url
-    });
+    let token = TerminationToken::new();
 
-    let client = Client::new();
-    let req = client
-        .request(
-            Method::POST,
-            format!("http://localhost:{}/fetch", NON_SECURE_PORT),
-        )
-        .json(&payload)
-        .build()
-        .unwrap();
+    let (health_tx, mut health_rx) = mpsc::channel(1);
+    let (tx, rx) = oneshot::channel();
 
-    integration_test!(
-        "./test_cases/main_with_allow_net",
+    let mut listen_fut = integration_test_listen_fut!(
         NON_SECURE_PORT,
-        "",
-        None,
+        maybe_tls,
+        "./test_cases/main",
         None,
-        Some(RequestBuilder::from_parts(client, req)),
         None,
-        (|resp| async {
-            callback(resp).await;
-        }),
-        TerminationToken::new()
+        ServerFlags {
+            request_read_timeout_ms: Some(request_read_timeout_ms),
+            ..Default::default()
+        },
+        health_tx,
+        Some(token.clone())
     );
-}
 
-#[tokio::test]
-#[serial]
-async fn test_allow_net_fetch_google_com() {
-    #[derive(Deserialize)]
-    struct FetchResponse {
-        status: u16,
-        body: String,
-    }
+    let req_fut = {
+        let token = token.clone();
+        async move {
+            assert!(test_fn(maybe_tls.stream().await).await);
 
-    // 1. allow only specific hosts
-    test_allow_net(
-        // because google.com redirects to www.google.com
-        Some(vec!["google.com", "www.google.com"]),
-        "https://google.com",
-        |resp| async move {
-            let resp = resp.unwrap();
+            if timeout(Duration::from_secs(10), token.cancel_and_wait())
+                .await
+                .is_err()
+            {
+                panic!("failed to terminate server within 10 seconds");
+            }
 
-            assert_eq!(resp.status().as_u16(), StatusCode::OK);
+            tx.send(()).unwrap();
+        }
+    };
 
-            let payload = resp.json::<FetchResponse>().await.unwrap();
+    let join_fut = tokio::spawn(async move {
+        loop {
+            if let Some(ServerHealth::Listening(..)) = health_rx.recv().await {
+                break;
+            }
+        }
 
-            assert_eq!(payload.status, StatusCode::OK);
-            assert!(!payload.body.is_empty());
-        },
-    )
-    .await;
+        req_fut.await;
+    });
 
-    // 2. allow only specific host (but not considering the redirected host)
-    test_allow_net(
-        Some(vec!["google.com"]),
-        "https://google.com",
-        |resp| async move {
-            let resp = resp.unwrap();
+    tokio::select! {
+        _ = join_fut => {}
+        _ = &mut listen_fut => {}
+    };
 
-            assert_eq!(resp.status().as_u16(), StatusCode::INTERNAL_SERVER_ERROR);
+    if timeout(Duration::from_secs(10), rx).await.is_err() {
+        panic!("failed to check within 10 seconds");
+    }
+}
 
-            let msg = resp.text().await.unwrap();
+async fn test_slowloris_no_prompt_timeout(maybe_tls: Option<Tls>, invert: bool) {
+    test_slowloris(
+        if invert { u64::MAX } else { 5000 },
+        maybe_tls,
+        move |mut io| async move {
+            static HEADER: &[u8] = b"GET /oak-with-jsr HTTP/1.1\r\nHost: localhost\r\n\r\n";
 
-            assert_eq!(
-                msg.as_str(),
-                // google.com redirects to www.google.com, but we didn't allow it
-                "PermissionDenied: Access to www.google.com is not allowed for user worker"
-            );
+            let check_io_kind_fn = move |err: std::io::Error| {
+                if invert {
+                    return true;
+                }
+
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+                )
+            };
+
+            // > 5000ms
+            sleep(Duration::from_secs(10)).await;
+
+            if let Err(err) = io.write_all(HEADER).await {
+                return check_io_kind_fn(err);
+            }
+
+            if let Err(err) = io.flush().await {
+                return check_io_kind_fn(err);
+            }
+
+            let mut buf = vec![0; 1_048_576];
+
+            match io.read(&mut buf).await {
+                Ok(nread) => {
+                    if invert {
+                        nread > 0
+                    } else {
+                        nread == 0
+                    }
+                }
+
+                Err(err) => check_io_kind_fn(err),
+            }
         },
     )
     .await;
+}
 
-    // 3. deny all hosts
-    test_allow_net(Some(vec![]), "https://google.com", |resp| async move {
-        let resp = resp.unwrap();
+#[tokio::test]
+#[serial]
+async fn test_slowloris_no_prompt_timeout_non_secure() {
+    test_slowloris_no_prompt_timeout(new_localhost_tls(false), false).await;
+}
 
-        assert_eq!(resp.status().as_u16(), StatusCode::INTERNAL_SERVER_ERROR);
+#[tokio::test]
+#[serial]
+#[ignore = "too slow"]
+async fn test_slowloris_no_prompt_timeout_non_secure_inverted() {
+    test_slowloris_no_prompt_timeout(new_localhost_tls(false), true).await;
+}
 
-        let msg = resp.text().await.unwrap();
+#[tokio::test]
+#[serial]
+async fn test_slowloris_no_prompt_timeout_secure() {
+    test_slowloris_no_prompt_timeout(new_localhost_tls(true), false).await;
+}
 
-        assert_eq!(
-            msg.as_str(),
-            "PermissionDenied: Access to google.com is not allowed for user worker"
-        );
-    })
-    .await;
+#[tokio::test]
+#[serial]
+#[ignore = "too slow"]
+async fn test_slowloris_no_prompt_timeout_secure_inverted() {
+    test_slowloris_no_prompt_timeout(new_localhost_tls(true), true).await;
+}
 
-    // 4. allow all hosts
-    test_allow_net(None, "https://google.com", |resp| async move {
-        let resp = resp.unwrap();
+async fn test_slowloris_slow_header_timedout(maybe_tls: Option<Tls>, invert: bool) {
+    test_slowloris(
+        if invert { u64::MAX } else { 5000 },
+        maybe_tls,
+        move |mut io| async move {
+            static HEADER: &[u8] = b"GET /oak-with-jsr HTTP/1.1\r\nHost: localhost\r\n\r\n";
 
-        assert_eq!(resp.status().as_u16(), StatusCode::OK);
+            let check_io_kind_fn = move |err: std::io::Error| {
+                if invert {
+                    return true;
+                }
 
-        let payload = resp.json::<FetchResponse>().await.unwrap();
+                matches!(
+                    err.kind(),
+                    io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+                )
+            };
 
-        assert_eq!(payload.status, StatusCode::OK);
-        assert!(!payload.body.is_empty());
-    })
+            // takes 1000ms per each character (ie. > 5000ms)
+            for &b in HEADER {
+                if let Err(err) = io.write(&[b]).await {
+                    return check_io_kind_fn(err);
+                }
+
+                if let Err(err) = io.flush().await {
+                    return check_io_kind_fn(err);
+                }
+
+                sleep(Duration::from_secs(1)).await;
+            }
+
+            let mut buf = vec![0; 1_048_576];
+
+            match io.read(&mut buf).await {
+                Ok(nread) => {
+                    if invert {
+                        nread > 0
+                    } else {
+                        nread == 0
+                    }
+                }
+
+                Err(err) => check_io_kind_fn(err),
+            }
+        },
+    )
     .await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_fastify_v4_package() {
-    integration_test!(
-        "./test_cases/main",
-        NON_SECURE_PORT,
-        "fastify-v4",
-        None,
-        None,
-        None,
-        None,
-        (|resp| async {
-            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
-        }),
-        TerminationToken::new()
-    );
+async fn test_slowloris_slow_header_timedout_non_secure() {
+    test_slowloris_slow_header_timedout(new_localhost_tls(false), false).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_fastify_latest_package() {
-    integration_test!(
-        "./test_cases/main",
-        NON_SECURE_PORT,
-        "fastify-latest",
-        None,
-        None,
-        None,
-        None,
-        (|resp| async {
-            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
-        }),
-        TerminationToken::new()
-    );
+#[ignore = "too slow 2x"]
+async fn test_slowloris_slow_header_timedout_non_secure_inverted() {
+    test_slowloris_slow_header_timedout(new_localhost_tls(false), true).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_declarative_style_fetch_handler() {
-    integration_test!(
-        "./test_cases/main",
-        NON_SECURE_PORT,
-        "serve-declarative-style",
-        None,
-        None,
-        None,
-        None,
-        (|resp| async {
-            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
-        }),
-        TerminationToken::new()
-    );
+async fn test_slowloris_slow_header_timedout_secure() {
+    test_slowloris_slow_header_timedout(new_localhost_tls(true), false).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_issue_420() {
-    integration_test!(
+#[ignore = "too slow 2x"]
+async fn test_slowloris_slow_header_timedout_secure_inverted() {
+    test_slowloris_slow_header_timedout(new_localhost_tls(true), true).await;
+}
+
+async fn test_request_idle_timeout_no_streamed_response(maybe_tls: Option<Tls>) {
+    let client = maybe_tls.client();
+    let req = client
+        .request(
+            Method::GET,
+            format!(
+                "{}://localhost:{}/sleep-5000ms",
+                maybe_tls.schema(),
+                maybe_tls.port(),
+            ),
+        )
+        .build()
+        .unwrap();
+
+    let original = RequestBuilder::from_parts(client, req);
+    let request_builder = Some(original);
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            request_idle_timeout_ms: Some(1000),
+            ..Default::default()
+        },
         "./test_cases/main",
         NON_SECURE_PORT,
-        "issue-420",
-        None,
-        None,
+        "",
         None,
         None,
+        request_builder,
+        maybe_tls,
         (|resp| async {
-            let text = resp.unwrap().text().await.unwrap();
-
-            assert!(text.starts_with("file:///"));
-            assert!(text.ends_with(
-                "/node_modules/localhost/@imagemagick/magick-wasm/0.0.30/dist/index.js"
-            ));
+            assert_eq!(resp.unwrap().status().as_u16(), StatusCode::GATEWAY_TIMEOUT);
         }),
         TerminationToken::new()
     );
@@ -2353,913 +2732,2091 @@ async fn test_issue_420() {
 
 #[tokio::test]
 #[serial]
-async fn test_should_render_detailed_failed_to_create_graph_error() {
-    {
-        integration_test!(
-            "./test_cases/main",
-            NON_SECURE_PORT,
-            "graph-error-1",
-            None,
-            None,
-            None,
-            None,
-            (|resp| async {
-                let (payload, status) = ErrorResponsePayload::assert_error_response(resp).await;
+async fn test_request_idle_timeout_no_streamed_response_non_secure() {
+    test_request_idle_timeout_no_streamed_response(new_localhost_tls(false)).await;
+}
 
-                assert_eq!(status, 500);
-                assert!(payload.msg.starts_with(
-                    "InvalidWorkerCreation: worker boot error: failed to create the graph: \
-                    Relative import path \"oak\" not prefixed with"
-                ));
-            }),
-            TerminationToken::new()
-        );
-    }
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_no_streamed_response_secure() {
+    test_request_idle_timeout_no_streamed_response(new_localhost_tls(true)).await;
+}
 
-    {
-        integration_test!(
-            "./test_cases/main",
-            NON_SECURE_PORT,
-            "graph-error-2",
+async fn test_request_idle_timeout_streamed_response(maybe_tls: Option<Tls>) {
+    let client = maybe_tls.client();
+    let req = client
+        .request(
+            Method::GET,
+            format!(
+                "{}://localhost:{}/chunked-char-variable-delay-max-6000ms",
+                maybe_tls.schema(),
+                maybe_tls.port(),
+            ),
+        )
+        .build()
+        .unwrap();
+
+    let original = RequestBuilder::from_parts(client, req);
+    let request_builder = Some(original);
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            request_idle_timeout_ms: Some(2000),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        maybe_tls,
+        (|resp| async {
+            let resp = resp.unwrap();
+
+            assert_eq!(resp.status().as_u16(), StatusCode::OK);
+            assert!(resp.content_length().is_none());
+
+            let mut buf = Vec::<u8>::new();
+            let mut bytes_stream = resp.bytes_stream();
+
+            loop {
+                match bytes_stream.next().await {
+                    Some(Ok(v)) => {
+                        buf.extend(v);
+                    }
+
+                    Some(Err(_)) => {
+                        break;
+                    }
+
+                    None => {
+                        break;
+                    }
+                }
+            }
+
+            assert_eq!(&buf, b"meo");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_streamed_response_non_secure() {
+    test_request_idle_timeout_streamed_response(new_localhost_tls(false)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_streamed_response_secure() {
+    test_request_idle_timeout_streamed_response(new_localhost_tls(true)).await;
+}
+
+async fn test_request_idle_timeout_streamed_response_first_chunk_timeout(maybe_tls: Option<Tls>) {
+    let client = maybe_tls.client();
+    let req = client
+        .request(
+            Method::GET,
+            format!(
+                "{}://localhost:{}/chunked-char-first-6000ms",
+                maybe_tls.schema(),
+                maybe_tls.port(),
+            ),
+        )
+        .build()
+        .unwrap();
+
+    let original = RequestBuilder::from_parts(client, req);
+    let request_builder = Some(original);
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            request_idle_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        maybe_tls,
+        (|resp| async {
+            let resp = resp.unwrap();
+
+            assert_eq!(resp.status().as_u16(), StatusCode::OK);
+            assert!(resp.content_length().is_none());
+
+            let mut buf = Vec::<u8>::new();
+            let mut bytes_stream = resp.bytes_stream();
+
+            loop {
+                match bytes_stream.next().await {
+                    Some(Ok(v)) => {
+                        buf.extend(v);
+                    }
+
+                    Some(Err(_)) => {
+                        break;
+                    }
+
+                    None => {
+                        break;
+                    }
+                }
+            }
+
+            assert_eq!(&buf, b"");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_streamed_response_first_chunk_timeout_non_secure() {
+    test_request_idle_timeout_streamed_response_first_chunk_timeout(new_localhost_tls(false)).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_streamed_response_first_chunk_timeout_secure() {
+    test_request_idle_timeout_streamed_response_first_chunk_timeout(new_localhost_tls(true)).await;
+}
+
+async fn test_request_idle_timeout_websocket_deno(maybe_tls: Option<Tls>, use_node_ws: bool) {
+    let nonce = tungstenite::handshake::client::generate_key();
+    let client = maybe_tls.client();
+    let req = client
+        .request(
+            Method::GET,
+            format!(
+                "{}://localhost:{}/websocket-upgrade-no-send{}",
+                maybe_tls.schema(),
+                maybe_tls.port(),
+                if use_node_ws { "-node" } else { "" }
+            ),
+        )
+        .header(header::CONNECTION, "upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_KEY, &nonce)
+        .header(header::SEC_WEBSOCKET_VERSION, "13")
+        .build()
+        .unwrap();
+
+    let original = RequestBuilder::from_parts(client, req);
+    let request_builder = Some(original);
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            request_idle_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        request_builder,
+        maybe_tls,
+        (|resp| async {
+            let res = resp.unwrap();
+            let accepted = get_upgrade_type(res.headers());
+
+            assert!(res.status().as_u16() == 101);
+            assert!(accepted.is_some());
+            assert_eq!(accepted.as_ref().unwrap(), "websocket");
+
+            let upgraded = res.upgrade().await.unwrap();
+            let mut ws = WebSocketStream::from_raw_socket(
+                upgraded.compat(),
+                tungstenite::protocol::Role::Client,
+                None,
+            )
+            .await;
+
+            sleep(Duration::from_secs(3)).await;
+
+            ws.send(Message::Text("meow!!".into())).await.unwrap();
+
+            let err = ws.next().await.unwrap().unwrap_err();
+
+            use tungstenite::error::ProtocolError;
+            use tungstenite::Error;
+
+            assert!(matches!(
+                err,
+                Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)
+            ));
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_websocket_deno_non_secure() {
+    test_request_idle_timeout_websocket_deno(new_localhost_tls(false), false).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_websocket_deno_secure() {
+    test_request_idle_timeout_websocket_deno(new_localhost_tls(true), false).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_websocket_node_non_secure() {
+    test_request_idle_timeout_websocket_deno(new_localhost_tls(false), true).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_idle_timeout_websocket_node_secure() {
+    test_request_idle_timeout_websocket_deno(new_localhost_tls(true), true).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_request_read_timeout_after_http2_stream_reset() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            h2c: true,
+            request_read_timeout_ms: Some(1000),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let stream = TcpStream::connect(("127.0.0.1", NON_SECURE_PORT))
+                    .await
+                    .unwrap();
+                let (mut sender, conn) = hyper::client::conn::Builder::new()
+                    .http2_only(true)
+                    .handshake::<_, Body>(stream)
+                    .await
+                    .unwrap();
+                let conn_task = tokio::spawn(conn);
+
+                let req = Request::builder()
+                    .uri(format!("http://localhost:{}/sleep-5000ms", NON_SECURE_PORT))
+                    .body(Body::empty())
+                    .unwrap();
+
+                // dropping the response future before the response head has
+                // arrived resets the stream.
+                assert!(
+                    timeout(Duration::from_millis(500), sender.send_request(req))
+                        .await
+                        .is_err()
+                );
+
+                // the reset stream must not keep the timer of the connection
+                // from being restarted.
+                assert!(timeout(Duration::from_secs(4), conn_task).await.is_ok());
+
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_should_not_hang_when_forced_redirection_for_specifiers() {
+    let (tx, rx) = oneshot::channel::<()>();
+
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "concurrent-redirect",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            assert_eq!(resp.unwrap().status().as_u16(), 200);
+            tx.send(()).unwrap();
+        }),
+        TerminationToken::new()
+    );
+
+    if timeout(Duration::from_secs(10), rx).await.is_err() {
+        panic!("failed to check within 10 seconds");
+    }
+}
+
+async fn test_allow_net<F, R>(allow_net: Option<Vec<&str>>, url: &str, callback: F)
+where
+    F: FnOnce(Result<Response, reqwest::Error>) -> R,
+    R: Future<Output = ()>,
+{
+    let payload = serde_json::json!({
+        "allowNet": allow_net,
+        "url": url
+    });
+
+    let client = Client::new();
+    let req = client
+        .request(
+            Method::POST,
+            format!("http://localhost:{}/fetch", NON_SECURE_PORT),
+        )
+        .json(&payload)
+        .build()
+        .unwrap();
+
+    integration_test!(
+        "./test_cases/main_with_allow_net",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        Some(RequestBuilder::from_parts(client, req)),
+        None,
+        (|resp| async {
+            callback(resp).await;
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_allow_net_fetch_google_com() {
+    #[derive(Deserialize)]
+    struct FetchResponse {
+        status: u16,
+        body: String,
+    }
+
+    // 1. allow only specific hosts
+    test_allow_net(
+        // because google.com redirects to www.google.com
+        Some(vec!["google.com", "www.google.com"]),
+        "https://google.com",
+        |resp| async move {
+            let resp = resp.unwrap();
+
+            assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+            let payload = resp.json::<FetchResponse>().await.unwrap();
+
+            assert_eq!(payload.status, StatusCode::OK);
+            assert!(!payload.body.is_empty());
+        },
+    )
+    .await;
+
+    // 2. allow only specific host (but not considering the redirected host)
+    test_allow_net(
+        Some(vec!["google.com"]),
+        "https://google.com",
+        |resp| async move {
+            let resp = resp.unwrap();
+
+            assert_eq!(resp.status().as_u16(), StatusCode::INTERNAL_SERVER_ERROR);
+
+            let msg = resp.text().await.unwrap();
+
+            assert_eq!(
+                msg.as_str(),
+                // google.com redirects to www.google.com, but we didn't allow it
+                "PermissionDenied: Access to www.google.com is not allowed for user worker"
+            );
+        },
+    )
+    .await;
+
+    // 3. deny all hosts
+    test_allow_net(Some(vec![]), "https://google.com", |resp| async move {
+        let resp = resp.unwrap();
+
+        assert_eq!(resp.status().as_u16(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let msg = resp.text().await.unwrap();
+
+        assert_eq!(
+            msg.as_str(),
+            "PermissionDenied: Access to google.com is not allowed for user worker"
+        );
+    })
+    .await;
+
+    // 4. allow all hosts
+    test_allow_net(None, "https://google.com", |resp| async move {
+        let resp = resp.unwrap();
+
+        assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+        let payload = resp.json::<FetchResponse>().await.unwrap();
+
+        assert_eq!(payload.status, StatusCode::OK);
+        assert!(!payload.body.is_empty());
+    })
+    .await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_fastify_v4_package() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "fastify-v4",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_fastify_latest_package() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "fastify-latest",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_declarative_style_fetch_handler() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "serve-declarative-style",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            assert_eq!(resp.unwrap().text().await.unwrap(), "meow");
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_issue_420() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "issue-420",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let text = resp.unwrap().text().await.unwrap();
+
+            assert!(text.starts_with("file:///"));
+            assert!(text.ends_with(
+                "/node_modules/localhost/@imagemagick/magick-wasm/0.0.30/dist/index.js"
+            ));
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_should_render_detailed_failed_to_create_graph_error() {
+    {
+        integration_test!(
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            "graph-error-1",
+            None,
+            None,
+            None,
+            None,
+            (|resp| async {
+                let (payload, status) = ErrorResponsePayload::assert_error_response(resp).await;
+
+                assert_eq!(status, 500);
+                assert!(payload.msg.starts_with(
+                    "InvalidWorkerCreation: worker boot error: failed to create the graph: \
+                    Relative import path \"oak\" not prefixed with"
+                ));
+            }),
+            TerminationToken::new()
+        );
+    }
+
+    {
+        integration_test!(
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            "graph-error-2",
+            None,
+            None,
+            None,
+            None,
+            (|resp| async {
+                let (payload, status) = ErrorResponsePayload::assert_error_response(resp).await;
+
+                assert_eq!(status, 500);
+                assert!(payload.msg.starts_with(
+                    "InvalidWorkerCreation: worker boot error: failed to create the graph: \
+                    Module not found \"file://"
+                ));
+            }),
+            TerminationToken::new()
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_js_entrypoint() {
+    {
+        integration_test!(
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            "serve-js",
+            None,
+            None,
+            None,
+            None,
+            (|resp| async {
+                let resp = resp.unwrap();
+                assert_eq!(resp.status().as_u16(), 200);
+                let msg = resp.text().await.unwrap();
+                assert_eq!(msg, "meow");
+            }),
+            TerminationToken::new()
+        );
+    }
+
+    {
+        integration_test!(
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            "serve-declarative-style-js",
+            None,
+            None,
+            None,
+            None,
+            (|resp| async {
+                let resp = resp.unwrap();
+                assert_eq!(resp.status().as_u16(), 200);
+                let msg = resp.text().await.unwrap();
+                assert_eq!(msg, "meow");
+            }),
+            TerminationToken::new()
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_should_be_able_to_bundle_against_various_exts() {
+    let get_eszip_buf = |path: &str| {
+        let path = path.to_string();
+        let mut emitter_factory = EmitterFactory::new();
+
+        emitter_factory.set_jsx_import_source(JsxImportSourceConfig {
+            default_specifier: Some("https://esm.sh/preact".to_string()),
+            default_types_specifier: None,
+            module: "jsx-runtime".to_string(),
+            base_url: Url::from_file_path(std::env::current_dir().unwrap()).unwrap(),
+        });
+
+        async {
+            generate_binary_eszip(
+                PathBuf::from(path),
+                Arc::new(emitter_factory),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .into_bytes()
+        }
+    };
+
+    {
+        let buf = get_eszip_buf("./test_cases/eszip-various-ext/npm-supabase/index.js").await;
+
+        let client = Client::new();
+        let req = client
+            .request(
+                Method::POST,
+                format!("http://localhost:{}/meow", NON_SECURE_PORT),
+            )
+            .body(buf);
+
+        integration_test!(
+            "./test_cases/main_eszip",
+            NON_SECURE_PORT,
+            "",
+            None,
+            None,
+            Some(req),
+            None,
+            (|resp| async {
+                let resp = resp.unwrap();
+                assert_eq!(resp.status().as_u16(), 200);
+                let msg = resp.text().await.unwrap();
+                assert_eq!(msg, "function");
+            }),
+            TerminationToken::new()
+        );
+    }
+
+    let test_serve_simple_fn = |ext: &'static str, expected: &'static [u8]| {
+        let ext = ext.to_string();
+        let expected = expected.to_vec();
+
+        async move {
+            let buf = get_eszip_buf(&format!(
+                "./test_cases/eszip-various-ext/serve/index.{}",
+                ext
+            ))
+            .await;
+
+            let client = Client::new();
+            let req = client
+                .request(
+                    Method::POST,
+                    format!("http://localhost:{}/meow", NON_SECURE_PORT),
+                )
+                .body(buf);
+
+            integration_test!(
+                "./test_cases/main_eszip",
+                NON_SECURE_PORT,
+                "",
+                None,
+                None,
+                Some(req),
+                None,
+                (|resp| async move {
+                    let resp = resp.unwrap();
+                    assert_eq!(resp.status().as_u16(), 200);
+                    let msg = resp.bytes().await.unwrap();
+                    assert_eq!(msg, expected);
+                }),
+                TerminationToken::new()
+            );
+        }
+    };
+
+    test_serve_simple_fn("ts", b"meow").await;
+    test_serve_simple_fn("js", b"meow").await;
+    test_serve_simple_fn("mjs", b"meow").await;
+
+    static REACT_RESULT: &str = r#"{"type":"div","props":{"children":"meow"},"__k":null,"__":null,"__b":0,"__e":null,"__c":null,"__v":-1,"__i":-1,"__u":0}"#;
+
+    test_serve_simple_fn("jsx", REACT_RESULT.as_bytes()).await;
+    test_serve_simple_fn("tsx", REACT_RESULT.as_bytes()).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_private_npm_package_import() {
+    // Required because test_cases/main_with_registry/registry/registry-handler.ts:58
+    std::env::set_var("EDGE_RUNTIME_PORT", NON_SECURE_PORT.to_string());
+    let _guard = scopeguard::guard((), |_| {
+        std::env::remove_var("EDGE_RUNTIME_PORT");
+    });
+
+    let client = Client::new();
+    let run_server_fn = |main: &'static str, token| async move {
+        let (tx, mut rx) = mpsc::channel(1);
+        let handle = tokio::task::spawn({
+            async move {
+                Server::new(
+                    "127.0.0.1",
+                    NON_SECURE_PORT,
+                    None,
+                    main.to_string(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    Default::default(),
+                    Some(tx),
+                    Default::default(),
+                    Some(token),
+                    vec![],
+                    None,
+                    None,
+                    None,
+                )
+                .await
+                .unwrap()
+                .listen()
+                .await
+                .unwrap();
+            }
+        });
+
+        let _ev = loop {
+            match rx.recv().await {
+                Some(health) => break health.into_listening().unwrap(),
+                _ => continue,
+            }
+        };
+
+        handle
+    };
+
+    {
+        let token = TerminationToken::new();
+        let handle = run_server_fn("./test_cases/main_with_registry", token.clone()).await;
+
+        let resp = client
+            .request(
+                Method::GET,
+                format!(
+                    "http://localhost:{}/private-npm-package-import",
+                    NON_SECURE_PORT
+                ),
+            )
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let body = resp.json::<serde_json::Value>().await.unwrap();
+        let body = body.as_object().unwrap();
+
+        assert_eq!(body.len(), 2);
+        assert_eq!(body.get("meow"), Some(&json!("function")));
+        assert_eq!(body.get("odd"), Some(&json!(true)));
+
+        token.cancel();
+        handle.await.unwrap();
+    }
+
+    {
+        let token = TerminationToken::new();
+        let handle = run_server_fn("./test_cases/main_with_registry", token.clone()).await;
+
+        let resp = client
+            .request(
+                Method::GET,
+                format!("http://localhost:{}/meow", NON_SECURE_PORT),
+            )
+            .header("x-service-path", "private-npm-package-import-2/inner")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let body = resp.json::<serde_json::Value>().await.unwrap();
+        let body = body.as_object().unwrap();
+
+        assert_eq!(body.len(), 2);
+        assert_eq!(body.get("meow"), Some(&json!("function")));
+        assert_eq!(body.get("odd"), Some(&json!(true)));
+
+        token.cancel();
+        handle.await.unwrap();
+    }
+
+    {
+        let token = TerminationToken::new();
+        let handle = run_server_fn("./test_cases/main_eszip", token.clone()).await;
+
+        let buf = {
+            let mut emitter_factory = EmitterFactory::new();
+
+            emitter_factory.set_npmrc_path("./test_cases/private-npm-package-import/.npmrc");
+
+            generate_binary_eszip(
+                PathBuf::from("./test_cases/private-npm-package-import/index.js"),
+                Arc::new(emitter_factory),
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap()
+            .into_bytes()
+        };
+
+        let resp = client
+            .request(
+                Method::POST,
+                format!("http://localhost:{}/meow", NON_SECURE_PORT),
+            )
+            .body(buf)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let body = resp.json::<serde_json::Value>().await.unwrap();
+        let body = body.as_object().unwrap();
+
+        assert_eq!(body.len(), 2);
+        assert_eq!(body.get("meow"), Some(&json!("function")));
+        assert_eq!(body.get("odd"), Some(&json!(true)));
+
+        token.cancel();
+        handle.await.unwrap();
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_tmp_fs_usage() {
+    {
+        integration_test!(
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            "use-tmp-fs",
             None,
             None,
             None,
             None,
             (|resp| async {
-                let (payload, status) = ErrorResponsePayload::assert_error_response(resp).await;
+                let resp = resp.unwrap();
+
+                assert_eq!(resp.status().as_u16(), 200);
+
+                let body = resp.json::<serde_json::Value>().await.unwrap();
+                let body = body.as_object().unwrap();
+
+                assert_eq!(body.len(), 4);
+                assert_eq!(body.get("written"), Some(&json!(8)));
+                assert_eq!(body.get("content"), Some(&json!("meowmeow")));
+                assert_eq!(body.get("deleted"), Some(&json!(true)));
+
+                let steps = body.get("steps").unwrap().as_array().unwrap();
+
+                assert_eq!(&steps[0], &json!(true));
+                assert_eq!(&steps[1], &json!(false));
+            }),
+            TerminationToken::new()
+        );
+    }
+
+    {
+        integration_test!(
+            "./test_cases/main",
+            NON_SECURE_PORT,
+            "use-tmp-fs-2",
+            None,
+            None,
+            None,
+            None,
+            (|resp| async {
+                let resp = resp.unwrap();
+
+                assert_eq!(resp.status().as_u16(), 200);
+
+                let body = resp.json::<serde_json::Value>().await.unwrap();
+                let body = body.as_object().unwrap();
+
+                assert_eq!(body.len(), 2);
+                assert_eq!(body.get("hadExisted"), Some(&json!(true)));
+
+                let path = body.get("path").unwrap().as_str().unwrap();
+                let f = fs::read(path).await.unwrap();
+                let mut cursor = Cursor::new(&f);
+
+                let client = Client::new();
+                let resp2 = client
+                    .request(Method::GET, "https://httpbin.org/stream/20".to_string())
+                    .send()
+                    .await
+                    .unwrap();
+
+                assert_eq!(resp2.status().as_u16(), 200);
+
+                let body2 = resp2.bytes().await.unwrap();
+                let mut cursor2 = Cursor::new(&*body2);
+                let mut count = 0;
+
+                loop {
+                    use serde_json::*;
+
+                    let mut buf = String::new();
+
+                    cursor.read_line(&mut buf).unwrap();
+                    let mut msg = from_str::<Map<String, Value>>(&buf).unwrap();
+
+                    buf.clear();
+                    cursor2.read_line(&mut buf).unwrap();
+                    let mut msg2 = from_str::<Map<String, Value>>(&buf).unwrap();
+
+                    assert!(msg.remove("headers").is_some());
+                    assert!(msg2.remove("headers").is_some());
+                    assert_eq!(msg, msg2);
+
+                    count += 1;
+                    if count >= 20 {
+                        break;
+                    }
+                }
+            }),
+            TerminationToken::new()
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn test_tmp_fs_should_not_be_available_in_import_stmt() {
+    // The s3 fs and tmp fs are not currently attached to the module loader, so the import statement
+    // should not recognize their prefixes. (But, depending on the case, they may be attached in the
+    // future)
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "use-tmp-fs-in-import-stmt",
+        None,
+        None,
+        None,
+        None,
+        (|resp| async {
+            let (payload, status) = ErrorResponsePayload::assert_error_response(resp).await;
+
+            assert_eq!(status, 500);
+            dbg!(&payload.msg);
+            assert!(payload.msg.starts_with(
+                "InvalidWorkerResponse: event loop error while evaluating the module: \
+                TypeError: Module not found: file:///tmp/meowmeow.ts"
+            ));
+        }),
+        TerminationToken::new()
+    );
+}
+
+// -- sb_ai: ORT @huggingface/transformers
+async fn test_ort_transformers_js(script_path: &str) {
+    fn visit_json(value: &mut serde_json::Value) {
+        use serde_json::Number;
+        use serde_json::Value::*;
+
+        match value {
+            Array(vec) => {
+                for v in vec {
+                    visit_json(v);
+                }
+            }
+            Object(map) => {
+                for (_, v) in map {
+                    visit_json(v);
+                }
+            }
+            Number(number) => {
+                if let Some(f) = number.as_f64() {
+                    *number = Number::from_f64((f * 1_000_000.0).round() / 1_000_000.0).unwrap();
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    use std::env::consts;
+
+    let base_path = "./test_cases/ai-ort-rust-backend";
+    let main_path = format!("{}/main", base_path);
+    let script_path = format!("transformers-js/{}", script_path);
+    let snapshot_path = PathBuf::from(base_path)
+        .join(script_path.as_str())
+        .join(format!("__snapshot__/{}_{}.json", consts::OS, consts::ARCH));
+
+    let client = Client::new();
+    let body = {
+        if snapshot_path.exists() {
+            tokio::fs::read(&snapshot_path).await.unwrap()
+        } else {
+            b"null".to_vec()
+        }
+    };
+
+    let content_length = body.len();
+    let req = client
+        .request(
+            Method::POST,
+            format!(
+                "http://localhost:{}/{}",
+                NON_SECURE_PORT,
+                script_path.as_str(),
+            ),
+        )
+        .body(body)
+        .header("Content-Type", "application/json")
+        .header("Content-Length", content_length.to_string());
+
+    integration_test!(
+        main_path,
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        Some(req),
+        None,
+        (|resp| async {
+            let res = resp.unwrap();
+            let status_code = res.status();
+
+            assert!(matches!(status_code, StatusCode::OK | StatusCode::CREATED));
+
+            if status_code == StatusCode::OK {
+                return;
+            }
+
+            assert_eq!(std::env::var("CI").ok(), None);
+            assert!(!snapshot_path.exists());
+
+            tokio::fs::create_dir_all(snapshot_path.parent().unwrap())
+                .await
+                .unwrap();
+
+            let mut body = res.json::<serde_json::Value>().await.unwrap();
+            let mut file = fs::File::create(&snapshot_path).await.unwrap();
+
+            visit_json(&mut body);
+
+            let content = serde_json::to_vec(&body).unwrap();
+
+            file.write_all(&content).await.unwrap();
+        }),
+        TerminationToken::new()
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_feature_extraction() {
+    test_ort_transformers_js("feature-extraction").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_fill_mask() {
+    test_ort_transformers_js("fill-mask").await;
+}
 
-                assert_eq!(status, 500);
-                assert!(payload.msg.starts_with(
-                    "InvalidWorkerCreation: worker boot error: failed to create the graph: \
-                    Module not found \"file://"
-                ));
-            }),
-            TerminationToken::new()
-        );
-    }
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_question_answering() {
+    test_ort_transformers_js("question-answering").await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_js_entrypoint() {
-    {
-        integration_test!(
-            "./test_cases/main",
-            NON_SECURE_PORT,
-            "serve-js",
-            None,
-            None,
-            None,
-            None,
-            (|resp| async {
-                let resp = resp.unwrap();
-                assert_eq!(resp.status().as_u16(), 200);
-                let msg = resp.text().await.unwrap();
-                assert_eq!(msg, "meow");
-            }),
-            TerminationToken::new()
-        );
-    }
+async fn test_ort_nlp_summarization() {
+    test_ort_transformers_js("summarization").await;
+}
 
-    {
-        integration_test!(
-            "./test_cases/main",
-            NON_SECURE_PORT,
-            "serve-declarative-style-js",
-            None,
-            None,
-            None,
-            None,
-            (|resp| async {
-                let resp = resp.unwrap();
-                assert_eq!(resp.status().as_u16(), 200);
-                let msg = resp.text().await.unwrap();
-                assert_eq!(msg, "meow");
-            }),
-            TerminationToken::new()
-        );
-    }
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_text_classification() {
+    test_ort_transformers_js("text-classification").await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_should_be_able_to_bundle_against_various_exts() {
-    let get_eszip_buf = |path: &str| {
-        let path = path.to_string();
-        let mut emitter_factory = EmitterFactory::new();
+async fn test_ort_nlp_text_generation() {
+    test_ort_transformers_js("text-generation").await;
+}
 
-        emitter_factory.set_jsx_import_source(JsxImportSourceConfig {
-            default_specifier: Some("https://esm.sh/preact".to_string()),
-            default_types_specifier: None,
-            module: "jsx-runtime".to_string(),
-            base_url: Url::from_file_path(std::env::current_dir().unwrap()).unwrap(),
-        });
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_text2text_generation() {
+    test_ort_transformers_js("text2text-generation").await;
+}
 
-        async {
-            generate_binary_eszip(
-                PathBuf::from(path),
-                Arc::new(emitter_factory),
-                None,
-                None,
-                None,
-            )
-            .await
-            .unwrap()
-            .into_bytes()
-        }
-    };
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_token_classification() {
+    test_ort_transformers_js("token-classification").await;
+}
 
-    {
-        let buf = get_eszip_buf("./test_cases/eszip-various-ext/npm-supabase/index.js").await;
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_translation() {
+    test_ort_transformers_js("translation").await;
+}
 
-        let client = Client::new();
-        let req = client
-            .request(
-                Method::POST,
-                format!("http://localhost:{}/meow", NON_SECURE_PORT),
-            )
-            .body(buf);
+#[tokio::test]
+#[serial]
+async fn test_ort_nlp_zero_shot_classification() {
+    test_ort_transformers_js("zero-shot-classification").await;
+}
 
-        integration_test!(
-            "./test_cases/main_eszip",
-            NON_SECURE_PORT,
-            "",
-            None,
-            None,
-            Some(req),
-            None,
-            (|resp| async {
-                let resp = resp.unwrap();
-                assert_eq!(resp.status().as_u16(), 200);
-                let msg = resp.text().await.unwrap();
-                assert_eq!(msg, "function");
-            }),
-            TerminationToken::new()
-        );
-    }
+#[tokio::test]
+#[serial]
+async fn test_ort_vision_image_feature_extraction() {
+    test_ort_transformers_js("image-feature-extraction").await;
+}
 
-    let test_serve_simple_fn = |ext: &'static str, expected: &'static [u8]| {
-        let ext = ext.to_string();
-        let expected = expected.to_vec();
+#[tokio::test]
+#[serial]
+async fn test_ort_vision_image_classification() {
+    test_ort_transformers_js("image-classification").await;
+}
 
-        async move {
-            let buf = get_eszip_buf(&format!(
-                "./test_cases/eszip-various-ext/serve/index.{}",
-                ext
-            ))
-            .await;
+#[tokio::test]
+#[serial]
+async fn test_ort_vision_zero_shot_image_classification() {
+    test_ort_transformers_js("zero-shot-image-classification").await;
+}
 
-            let client = Client::new();
-            let req = client
-                .request(
-                    Method::POST,
-                    format!("http://localhost:{}/meow", NON_SECURE_PORT),
-                )
-                .body(buf);
+// -- sb_ai(cache): ORT @huggingface/transformers
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_feature_extraction() {
+    test_ort_transformers_js("feature-extraction-cache").await;
+}
 
-            integration_test!(
-                "./test_cases/main_eszip",
-                NON_SECURE_PORT,
-                "",
-                None,
-                None,
-                Some(req),
-                None,
-                (|resp| async move {
-                    let resp = resp.unwrap();
-                    assert_eq!(resp.status().as_u16(), 200);
-                    let msg = resp.bytes().await.unwrap();
-                    assert_eq!(msg, expected);
-                }),
-                TerminationToken::new()
-            );
-        }
-    };
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_fill_mask() {
+    test_ort_transformers_js("fill-mask-cache").await;
+}
 
-    test_serve_simple_fn("ts", b"meow").await;
-    test_serve_simple_fn("js", b"meow").await;
-    test_serve_simple_fn("mjs", b"meow").await;
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_question_answering() {
+    test_ort_transformers_js("question-answering-cache").await;
+}
 
-    static REACT_RESULT: &str = r#"{"type":"div","props":{"children":"meow"},"__k":null,"__":null,"__b":0,"__e":null,"__c":null,"__v":-1,"__i":-1,"__u":0}"#;
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_summarization() {
+    test_ort_transformers_js("summarization-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_text_classification() {
+    test_ort_transformers_js("text-classification-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_text_generation() {
+    test_ort_transformers_js("text-generation-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_text2text_generation() {
+    test_ort_transformers_js("text2text-generation-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_token_classification() {
+    test_ort_transformers_js("token-classification-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_translation() {
+    test_ort_transformers_js("translation-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_nlp_zero_shot_classification() {
+    test_ort_transformers_js("zero-shot-classification-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_vision_image_feature_extraction() {
+    test_ort_transformers_js("image-feature-extraction-cache").await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_vision_image_classification() {
+    test_ort_transformers_js("image-classification-cache").await;
+}
 
-    test_serve_simple_fn("jsx", REACT_RESULT.as_bytes()).await;
-    test_serve_simple_fn("tsx", REACT_RESULT.as_bytes()).await;
+#[tokio::test]
+#[serial]
+async fn test_ort_cache_vision_zero_shot_image_classification() {
+    test_ort_transformers_js("zero-shot-image-classification-cache").await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_private_npm_package_import() {
-    // Required because test_cases/main_with_registry/registry/registry-handler.ts:58
-    std::env::set_var("EDGE_RUNTIME_PORT", NON_SECURE_PORT.to_string());
-    let _guard = scopeguard::guard((), |_| {
-        std::env::remove_var("EDGE_RUNTIME_PORT");
-    });
+async fn test_worker_recycled_after_max_requests() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let tb = TestBedBuilder::new("./test_cases/main_with_options")
+        .with_per_worker_policy(None)
+        .with_worker_event_sender(Some(tx))
+        .build()
+        .await;
 
-    let client = Client::new();
-    let run_server_fn = |main: &'static str, token| async move {
-        let (tx, mut rx) = mpsc::channel(1);
-        let handle = tokio::task::spawn({
-            async move {
-                Server::new(
-                    "127.0.0.1",
-                    NON_SECURE_PORT,
-                    None,
-                    main.to_string(),
-                    None,
-                    None,
-                    None,
-                    None,
-                    Default::default(),
-                    Some(tx),
-                    Default::default(),
-                    Some(token),
-                    vec![],
-                    None,
-                    None,
-                    None,
-                )
-                .await
-                .unwrap()
-                .listen()
-                .await
-                .unwrap();
-            }
-        });
+    let resp = tb
+        .request(|b| {
+            b.uri("/echo-headers")
+                .method("GET")
+                .header("x-worker-options", r#"{"maxRequestsPerWorker":1}"#)
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
 
-        let _ev = loop {
-            match rx.recv().await {
-                Some(health) => break health.into_listening().unwrap(),
-                _ => continue,
+    assert_eq!(resp.status().as_u16(), StatusCode::OK);
+
+    // the worker has served as many requests as it may, so it must go away
+    // without being asked to.
+    let reason = timeout(Duration::from_secs(10), async {
+        while let Some(ev) = rx.recv().await {
+            if let WorkerEvents::Shutdown(ev) = ev.event {
+                return Some(ev.reason);
             }
-        };
+        }
 
-        handle
-    };
+        None
+    })
+    .await
+    .unwrap();
 
-    {
-        let token = TerminationToken::new();
-        let handle = run_server_fn("./test_cases/main_with_registry", token.clone()).await;
+    assert!(matches!(reason, Some(ShutdownReason::Recycled)));
 
-        let resp = client
-            .request(
-                Method::GET,
-                format!(
-                    "http://localhost:{}/private-npm-package-import",
-                    NON_SECURE_PORT
-                ),
-            )
-            .send()
-            .await
-            .unwrap();
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
 
-        assert_eq!(resp.status().as_u16(), 200);
+#[tokio::test]
+#[serial]
+async fn test_circuit_opens_after_repeated_boot_failures() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let tb = TestBedBuilder::new("./test_cases/main")
+        .with_worker_pool_policy(
+            WorkerPoolPolicy::new(SupervisorPolicy::PerWorker, 1, ServerFlags::default())
+                .with_circuit_breaker(CircuitBreakerPolicy {
+                    threshold: 2,
+                    backoff: Duration::from_secs(60),
+                    max_backoff: Duration::from_secs(60),
+                }),
+        )
+        .with_worker_event_sender(Some(tx))
+        .build()
+        .await;
 
-        let body = resp.json::<serde_json::Value>().await.unwrap();
-        let body = body.as_object().unwrap();
+    let req_body_fn = |b: http::request::Builder| {
+        b.uri("/boot_err_user_worker")
+            .method("GET")
+            .body(Body::empty())
+            .context("can't make request")
+    };
 
-        assert_eq!(body.len(), 2);
-        assert_eq!(body.get("meow"), Some(&json!("function")));
-        assert_eq!(body.get("odd"), Some(&json!(true)));
+    for _ in 0..2 {
+        let mut res = tb.request(req_body_fn).await.unwrap();
+        let buf = to_bytes(res.body_mut()).await.unwrap();
 
-        token.cancel();
-        handle.await.unwrap();
+        assert_eq!(res.status().as_u16(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(buf.starts_with(b"{\"msg\":\"InvalidWorkerCreation: worker boot error"));
     }
 
-    {
-        let token = TerminationToken::new();
-        let handle = run_server_fn("./test_cases/main_with_registry", token.clone()).await;
+    // the circuit is open now, so no worker is booted for the next request.
+    let mut res = tb.request(req_body_fn).await.unwrap();
+    let buf = to_bytes(res.body_mut()).await.unwrap();
 
-        let resp = client
-            .request(
-                Method::GET,
-                format!("http://localhost:{}/meow", NON_SECURE_PORT),
-            )
-            .header("x-service-path", "private-npm-package-import-2/inner")
-            .send()
-            .await
-            .unwrap();
+    assert_eq!(res.status().as_u16(), StatusCode::SERVICE_UNAVAILABLE);
 
-        assert_eq!(resp.status().as_u16(), 200);
+    let retry_after = res
+        .headers()
+        .get(http::header::RETRY_AFTER)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<u64>().ok())
+        .unwrap();
 
-        let body = resp.json::<serde_json::Value>().await.unwrap();
-        let body = body.as_object().unwrap();
+    assert!((1..=60).contains(&retry_after));
+    assert!(buf.starts_with(b"{\"msg\":\"WorkerCircuitOpen: the service keeps failing"));
 
-        assert_eq!(body.len(), 2);
-        assert_eq!(body.get("meow"), Some(&json!("function")));
-        assert_eq!(body.get("odd"), Some(&json!(true)));
+    let ev = timeout(Duration::from_secs(10), async {
+        while let Some(ev) = rx.recv().await {
+            if let WorkerEvents::CircuitStateChanged(ev) = ev.event {
+                return Some(ev);
+            }
+        }
 
-        token.cancel();
-        handle.await.unwrap();
-    }
+        None
+    })
+    .await
+    .unwrap()
+    .unwrap();
 
-    {
-        let token = TerminationToken::new();
-        let handle = run_server_fn("./test_cases/main_eszip", token.clone()).await;
+    assert_eq!(ev.state, CircuitState::Open);
+    assert_eq!(ev.failures, 2);
 
-        let buf = {
-            let mut emitter_factory = EmitterFactory::new();
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+}
 
-            emitter_factory.set_npmrc_path("./test_cases/private-npm-package-import/.npmrc");
+async fn test_runtime_beforeunload_event(kind: &'static str, pct: u8) {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let tb = TestBedBuilder::new("./test_cases/runtime-event")
+        .with_per_worker_policy(None)
+        .with_worker_event_sender(Some(tx))
+        .with_server_flags(ServerFlags {
+            beforeunload_wall_clock_pct: Some(pct),
+            beforeunload_cpu_pct: Some(pct),
+            beforeunload_memory_pct: Some(pct),
+            ..Default::default()
+        })
+        .build()
+        .await;
 
-            generate_binary_eszip(
-                PathBuf::from("./test_cases/private-npm-package-import/index.js"),
-                Arc::new(emitter_factory),
-                None,
-                None,
-                None,
-            )
-            .await
-            .unwrap()
-            .into_bytes()
-        };
+    let resp = tb
+        .request(|b| {
+            b.uri(format!("/{}", kind))
+                .method("GET")
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
 
-        let resp = client
-            .request(
-                Method::POST,
-                format!("http://localhost:{}/meow", NON_SECURE_PORT),
-            )
-            .body(buf)
-            .send()
-            .await
-            .unwrap();
+    assert_ne!(resp.status().as_u16(), StatusCode::OK);
 
-        assert_eq!(resp.status().as_u16(), 200);
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 
-        let body = resp.json::<serde_json::Value>().await.unwrap();
-        let body = body.as_object().unwrap();
+    while let Some(ev) = rx.recv().await {
+        let WorkerEvents::Log(ev) = ev.event else {
+            continue;
+        };
+        if ev.level != LogLevel::Info {
+            continue;
+        }
+        if ev
+            .msg
+            .contains(&format!("triggered {}", kind.replace('-', "_")))
+        {
+            return;
+        }
+    }
 
-        assert_eq!(body.len(), 2);
-        assert_eq!(body.get("meow"), Some(&json!("function")));
-        assert_eq!(body.get("odd"), Some(&json!(true)));
+    unreachable!("test failed");
+}
 
-        token.cancel();
-        handle.await.unwrap();
-    }
+#[tokio::test]
+#[serial]
+async fn test_runtime_event_beforeunload_cpu() {
+    test_runtime_beforeunload_event("cpu", 50).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_runtime_event_beforeunload_wall_clock() {
+    test_runtime_beforeunload_event("wall-clock", 50).await;
+}
+
+#[tokio::test]
+#[serial]
+async fn test_runtime_event_beforeunload_mem() {
+    test_runtime_beforeunload_event("mem", 50).await;
 }
 
+// NOTE(Nyannyacha): We cannot enable this test unless we clarify the trigger point of the unload
+// event.
+//
+// #[tokio::test]
+// #[serial]
+// async fn test_runtime_event_unload() {
+//     let (tx, mut rx) = mpsc::unbounded_channel();
+//     let tb = TestBedBuilder::new("./test_cases/runtime-event")
+//         .with_per_worker_policy(None)
+//         .with_worker_event_sender(Some(tx))
+//         .build()
+//         .await;
+//
+//     let resp = tb
+//         .request(|b| {
+//             b.uri("/unload")
+//                 .method("GET")
+//                 .body(Body::empty())
+//                 .context("can't make request")
+//         })
+//         .await
+//         .unwrap();
+//
+//     assert_eq!(resp.status().as_u16(), StatusCode::OK);
+//
+//     sleep(Duration::from_secs(8)).await;
+//     tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+//
+//     while let Some(ev) = rx.recv().await {
+//         let WorkerEvents::Log(ev) = ev.event else {
+//             continue;
+//         };
+//         if ev.level != LogLevel::Info {
+//             continue;
+//         }
+//         if ev.msg.contains("triggered unload") {
+//             break;
+//         }
+//     }
+//
+//     unreachable!("test failed");
+// }
+
 #[tokio::test]
 #[serial]
-async fn test_tmp_fs_usage() {
-    {
-        integration_test!(
-            "./test_cases/main",
-            NON_SECURE_PORT,
-            "use-tmp-fs",
-            None,
-            None,
-            None,
-            None,
-            (|resp| async {
-                let resp = resp.unwrap();
-
-                assert_eq!(resp.status().as_u16(), 200);
+async fn test_should_wait_for_background_tests() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let tb = TestBedBuilder::new("./test_cases/main")
+        // only the `per_worker` policy allows waiting for background tasks.
+        .with_per_worker_policy(None)
+        .with_worker_event_sender(Some(tx))
+        .build()
+        .await;
 
-                let body = resp.json::<serde_json::Value>().await.unwrap();
-                let body = body.as_object().unwrap();
+    let resp = tb
+        .request(|b| {
+            b.uri("/mark-background-task")
+                .header("x-cpu-time-soft-limit-ms", HeaderValue::from_static("100"))
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
 
-                assert_eq!(body.len(), 4);
-                assert_eq!(body.get("written"), Some(&json!(8)));
-                assert_eq!(body.get("content"), Some(&json!("meowmeow")));
-                assert_eq!(body.get("deleted"), Some(&json!(true)));
+    assert_eq!(resp.status().as_u16(), StatusCode::OK);
 
-                let steps = body.get("steps").unwrap().as_array().unwrap();
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 
-                assert_eq!(&steps[0], &json!(true));
-                assert_eq!(&steps[1], &json!(false));
-            }),
-            TerminationToken::new()
-        );
+    while let Some(ev) = rx.recv().await {
+        let WorkerEvents::Log(ev) = ev.event else {
+            continue;
+        };
+        if ev.level != LogLevel::Info {
+            continue;
+        }
+        if ev.msg.contains("meow") {
+            return;
+        }
     }
 
-    {
-        integration_test!(
-            "./test_cases/main",
-            NON_SECURE_PORT,
-            "use-tmp-fs-2",
-            None,
-            None,
-            None,
-            None,
-            (|resp| async {
-                let resp = resp.unwrap();
-
-                assert_eq!(resp.status().as_u16(), 200);
-
-                let body = resp.json::<serde_json::Value>().await.unwrap();
-                let body = body.as_object().unwrap();
-
-                assert_eq!(body.len(), 2);
-                assert_eq!(body.get("hadExisted"), Some(&json!(true)));
-
-                let path = body.get("path").unwrap().as_str().unwrap();
-                let f = fs::read(path).await.unwrap();
-                let mut cursor = Cursor::new(&f);
-
-                let client = Client::new();
-                let resp2 = client
-                    .request(Method::GET, "https://httpbin.org/stream/20".to_string())
-                    .send()
-                    .await
-                    .unwrap();
-
-                assert_eq!(resp2.status().as_u16(), 200);
-
-                let body2 = resp2.bytes().await.unwrap();
-                let mut cursor2 = Cursor::new(&*body2);
-                let mut count = 0;
-
-                loop {
-                    use serde_json::*;
+    unreachable!("test failed");
+}
 
-                    let mut buf = String::new();
+#[tokio::test]
+#[serial]
+async fn test_should_not_wait_for_background_tests() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let tb = TestBedBuilder::new("./test_cases/main")
+        // only the `per_worker` policy allows waiting for background tasks.
+        .with_per_worker_policy(None)
+        .with_worker_event_sender(Some(tx))
+        .build()
+        .await;
 
-                    cursor.read_line(&mut buf).unwrap();
-                    let mut msg = from_str::<Map<String, Value>>(&buf).unwrap();
+    let resp = tb
+        .request(|b| {
+            b.uri("/mark-background-task-2")
+                .header("x-cpu-time-soft-limit-ms", HeaderValue::from_static("100"))
+                .body(Body::empty())
+                .context("can't make request")
+        })
+        .await
+        .unwrap();
 
-                    buf.clear();
-                    cursor2.read_line(&mut buf).unwrap();
-                    let mut msg2 = from_str::<Map<String, Value>>(&buf).unwrap();
+    assert_eq!(resp.status().as_u16(), StatusCode::OK);
 
-                    assert!(msg.remove("headers").is_some());
-                    assert!(msg2.remove("headers").is_some());
-                    assert_eq!(msg, msg2);
+    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
 
-                    count += 1;
-                    if count >= 20 {
-                        break;
-                    }
-                }
-            }),
-            TerminationToken::new()
-        );
+    while let Some(ev) = rx.recv().await {
+        let WorkerEvents::Log(ev) = ev.event else {
+            continue;
+        };
+        if ev.level != LogLevel::Info {
+            continue;
+        }
+        if ev.msg.contains("meow") {
+            unreachable!("test failed");
+        }
     }
 }
 
 #[tokio::test]
 #[serial]
-async fn test_tmp_fs_should_not_be_available_in_import_stmt() {
-    // The s3 fs and tmp fs are not currently attached to the module loader, so the import statement
-    // should not recognize their prefixes. (But, depending on the case, they may be attached in the
-    // future)
-    integration_test!(
-        "./test_cases/main",
+async fn test_main_worker_dual_stack_listen() {
+    let token = TerminationToken::new();
+    let (tx, mut rx) = mpsc::channel::<ServerHealth>(1);
+    let mut listen_fut = start_server(
+        "::",
         NON_SECURE_PORT,
-        "use-tmp-fs-in-import-stmt",
         None,
+        String::from("./test_cases/main"),
         None,
         None,
         None,
-        (|resp| async {
-            let (payload, status) = ErrorResponsePayload::assert_error_response(resp).await;
-
-            assert_eq!(status, 500);
-            dbg!(&payload.msg);
-            assert!(payload.msg.starts_with(
-                "InvalidWorkerResponse: event loop error while evaluating the module: \
-                TypeError: Module not found: file:///tmp/meowmeow.ts"
-            ));
-        }),
-        TerminationToken::new()
-    );
-}
-
-// -- sb_ai: ORT @huggingface/transformers
-async fn test_ort_transformers_js(script_path: &str) {
-    fn visit_json(value: &mut serde_json::Value) {
-        use serde_json::Number;
-        use serde_json::Value::*;
-
-        match value {
-            Array(vec) => {
-                for v in vec {
-                    visit_json(v);
-                }
-            }
-            Object(map) => {
-                for (_, v) in map {
-                    visit_json(v);
-                }
-            }
-            Number(number) => {
-                if let Some(f) = number.as_f64() {
-                    *number = Number::from_f64((f * 1_000_000.0).round() / 1_000_000.0).unwrap();
-                }
-            }
-
-            _ => {}
-        }
-    }
-
-    use std::env::consts;
-
-    let base_path = "./test_cases/ai-ort-rust-backend";
-    let main_path = format!("{}/main", base_path);
-    let script_path = format!("transformers-js/{}", script_path);
-    let snapshot_path = PathBuf::from(base_path)
-        .join(script_path.as_str())
-        .join(format!("__snapshot__/{}_{}.json", consts::OS, consts::ARCH));
-
-    let client = Client::new();
-    let body = {
-        if snapshot_path.exists() {
-            tokio::fs::read(&snapshot_path).await.unwrap()
-        } else {
-            b"null".to_vec()
-        }
-    };
-
-    let content_length = body.len();
-    let req = client
-        .request(
-            Method::POST,
-            format!(
-                "http://localhost:{}/{}",
-                NON_SECURE_PORT,
-                script_path.as_str(),
-            ),
-        )
-        .body(body)
-        .header("Content-Type", "application/json")
-        .header("Content-Length", content_length.to_string());
-
-    integration_test!(
-        main_path,
-        NON_SECURE_PORT,
-        "",
         None,
+        ServerFlags::default(),
+        Some(tx),
+        WorkerEntrypoints {
+            main: None,
+            events: None,
+        },
+        Some(token.clone()),
+        vec![],
         None,
-        Some(req),
         None,
-        (|resp| async {
-            let res = resp.unwrap();
-            let status_code = res.status();
-
-            assert!(matches!(status_code, StatusCode::OK | StatusCode::CREATED));
-
-            if status_code == StatusCode::OK {
-                return;
-            }
+        None,
+    )
+    .boxed();
 
-            assert_eq!(std::env::var("CI").ok(), None);
-            assert!(!snapshot_path.exists());
+    let req_fut = async move {
+        assert!(matches!(rx.recv().await, Some(ServerHealth::Listening(..))));
 
-            tokio::fs::create_dir_all(snapshot_path.parent().unwrap())
+        // binding to `::` should accept connections from both families.
+        for host in ["127.0.0.1", "[::1]"] {
+            let resp = reqwest::get(format!("http://{}:{}/", host, NON_SECURE_PORT))
                 .await
                 .unwrap();
 
-            let mut body = res.json::<serde_json::Value>().await.unwrap();
-            let mut file = fs::File::create(&snapshot_path).await.unwrap();
+            assert_eq!(resp.status().as_u16(), StatusCode::BAD_REQUEST);
+        }
+    };
 
-            visit_json(&mut body);
+    tokio::select! {
+        _ = req_fut => {}
+        _ = &mut listen_fut => {
+            panic!("This one should not end first");
+        }
+    }
 
-            let content = serde_json::to_vec(&body).unwrap();
+    let join_fut = tokio::spawn(async move {
+        let _ = listen_fut.await;
+    });
 
-            file.write_all(&content).await.unwrap();
-        }),
-        TerminationToken::new()
-    );
+    if timeout(Duration::from_secs(30), async move {
+        let (_, ret) = join!(token.cancel_and_wait(), join_fut);
+        ret.unwrap();
+    })
+    .await
+    .is_err()
+    {
+        panic!("failed to terminate server within 30 seconds");
+    }
 }
 
+#[cfg(unix)]
 #[tokio::test]
 #[serial]
-async fn test_ort_nlp_feature_extraction() {
-    test_ort_transformers_js("feature-extraction").await;
-}
+async fn test_main_worker_unix_socket_listen() {
+    let dir = tempfile::tempdir().unwrap();
+    let socket_path = dir.path().join("edge-runtime.sock");
+    let socket_path_inner = socket_path.clone();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_fill_mask() {
-    test_ort_transformers_js("fill-mask").await;
-}
+    integration_test_with_server_flag!(
+        ServerFlags {
+            unix_socket: Some(socket_path.clone()),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let mut stream = tokio::net::UnixStream::connect(&socket_path_inner)
+                    .await
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_question_answering() {
-    test_ort_transformers_js("question-answering").await;
-}
+                stream
+                    .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_summarization() {
-    test_ort_transformers_js("summarization").await;
-}
+                let mut buf = vec![];
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_text_classification() {
-    test_ort_transformers_js("text-classification").await;
-}
+                stream.read_to_end(&mut buf).await.unwrap();
+                assert!(buf.starts_with(b"HTTP/1.1 400"));
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_text_generation() {
-    test_ort_transformers_js("text-generation").await;
-}
+                None
+            },
+            |resp| async {
+                // the TCP listener should keep working alongside the socket.
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_text2text_generation() {
-    test_ort_transformers_js("text2text-generation").await;
+    assert!(!socket_path.exists());
 }
 
 #[tokio::test]
 #[serial]
-async fn test_ort_nlp_token_classification() {
-    test_ort_transformers_js("token-classification").await;
-}
+async fn test_main_worker_proxy_protocol() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            proxy_protocol: true,
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let v2_header = {
+                    let mut buf = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+
+                    buf.extend_from_slice(&[203, 0, 113, 7, 127, 0, 0, 1]);
+                    buf.extend_from_slice(&56324u16.to_be_bytes());
+                    buf.extend_from_slice(&NON_SECURE_PORT.to_be_bytes());
+                    buf
+                };
+
+                for header in [
+                    format!(
+                        "PROXY TCP4 203.0.113.7 127.0.0.1 56324 {}\r\n",
+                        NON_SECURE_PORT
+                    )
+                    .into_bytes(),
+                    v2_header,
+                ] {
+                    let mut stream = TcpStream::connect(("127.0.0.1", NON_SECURE_PORT))
+                        .await
+                        .unwrap();
+
+                    stream.write_all(&header).await.unwrap();
+
+                    let remote_addr = get_remote_addr_from_conn_info(stream).await;
+
+                    assert_eq!(remote_addr["hostname"], "203.0.113.7");
+                    assert_eq!(remote_addr["port"], 56324);
+                }
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_translation() {
-    test_ort_transformers_js("translation").await;
+                None
+            },
+            |resp| async {
+                // connections without a PROXY header should be dropped.
+                assert!(resp.is_err());
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
-#[tokio::test]
-#[serial]
-async fn test_ort_nlp_zero_shot_classification() {
-    test_ort_transformers_js("zero-shot-classification").await;
-}
+async fn get_remote_addr_from_conn_info(stream: TcpStream) -> serde_json::Value {
+    let (mut sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_vision_image_feature_extraction() {
-    test_ort_transformers_js("image-feature-extraction").await;
-}
+    tokio::spawn(conn);
 
-#[tokio::test]
-#[serial]
-async fn test_ort_vision_image_classification() {
-    test_ort_transformers_js("image-classification").await;
-}
+    let req = Request::builder()
+        .uri("/conn-info")
+        .header("host", "localhost")
+        .body(Body::empty())
+        .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_vision_zero_shot_image_classification() {
-    test_ort_transformers_js("zero-shot-image-classification").await;
-}
+    let res = sender.send_request(req).await.unwrap();
 
-// -- sb_ai(cache): ORT @huggingface/transformers
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_feature_extraction() {
-    test_ort_transformers_js("feature-extraction-cache").await;
-}
+    assert_eq!(res.status().as_u16(), StatusCode::OK);
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_fill_mask() {
-    test_ort_transformers_js("fill-mask-cache").await;
-}
+    let body = to_bytes(res.into_body()).await.unwrap();
+    let mut payload = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_question_answering() {
-    test_ort_transformers_js("question-answering-cache").await;
+    payload["remoteAddr"].take()
 }
 
 #[tokio::test]
 #[serial]
-async fn test_ort_cache_nlp_summarization() {
-    test_ort_transformers_js("summarization-cache").await;
-}
+async fn test_user_worker_remote_addr() {
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let stream = TcpStream::connect(("127.0.0.1", NON_SECURE_PORT))
+                    .await
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_text_classification() {
-    test_ort_transformers_js("text-classification-cache").await;
-}
+                let local_addr = stream.local_addr().unwrap();
+                let remote_addr = get_remote_addr_from_conn_info(stream).await;
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_text_generation() {
-    test_ort_transformers_js("text-generation-cache").await;
-}
+                // the user worker should see the address of the client rather
+                // than a placeholder.
+                assert_eq!(remote_addr["transport"], "tcp");
+                assert_eq!(remote_addr["hostname"], local_addr.ip().to_string());
+                assert_eq!(remote_addr["port"], local_addr.port());
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_text2text_generation() {
-    test_ort_transformers_js("text2text-generation-cache").await;
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_ort_cache_nlp_token_classification() {
-    test_ort_transformers_js("token-classification-cache").await;
-}
+async fn test_metrics_endpoint() {
+    integration_test_with_server_flag!(
+        ServerFlags {
+            metrics_addr: Some(SocketAddr::from(([127, 0, 0, 1], METRICS_PORT))),
+            ..Default::default()
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        None,
+        (
+            |_| async move {
+                let resp = reqwest::get(format!("http://localhost:{}/conn-info", NON_SECURE_PORT))
+                    .await
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_translation() {
-    test_ort_transformers_js("translation-cache").await;
-}
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_nlp_zero_shot_classification() {
-    test_ort_transformers_js("zero-shot-classification-cache").await;
-}
+                let resp = reqwest::get(format!("http://localhost:{}/metrics", METRICS_PORT))
+                    .await
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_vision_image_feature_extraction() {
-    test_ort_transformers_js("image-feature-extraction-cache").await;
-}
+                assert_eq!(resp.status().as_u16(), StatusCode::OK);
+                assert!(resp
+                    .headers()
+                    .get("content-type")
+                    .and_then(|it| it.to_str().ok())
+                    .is_some_and(|it| it.starts_with("text/plain")));
+
+                let body = resp.text().await.unwrap();
+
+                assert!(body.contains("\nedge_runtime_received_requests_total 1\n"));
+                assert!(body.contains("\nedge_runtime_active_user_workers 1\n"));
+                assert!(body.contains("\nedge_runtime_heap_used_bytes{worker=\"main\"} "));
+                assert!(body.lines().any(|it| {
+                    it.starts_with("edge_runtime_service_user_workers{")
+                        && it.contains("conn-info")
+                        && it.ends_with("state=\"active\"} 1")
+                }));
+
+                let resp = reqwest::get(format!("http://localhost:{}/", METRICS_PORT))
+                    .await
+                    .unwrap();
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_vision_image_classification() {
-    test_ort_transformers_js("image-classification-cache").await;
-}
+                assert_eq!(resp.status().as_u16(), StatusCode::NOT_FOUND);
 
-#[tokio::test]
-#[serial]
-async fn test_ort_cache_vision_zero_shot_image_classification() {
-    test_ort_transformers_js("zero-shot-image-classification-cache").await;
+                None
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
-async fn test_runtime_beforeunload_event(kind: &'static str, pct: u8) {
-    let (tx, mut rx) = mpsc::unbounded_channel();
-    let tb = TestBedBuilder::new("./test_cases/runtime-event")
-        .with_per_worker_policy(None)
-        .with_worker_event_sender(Some(tx))
-        .with_server_flags(ServerFlags {
-            beforeunload_wall_clock_pct: Some(pct),
-            beforeunload_cpu_pct: Some(pct),
-            beforeunload_memory_pct: Some(pct),
+async fn test_main_worker_http2(maybe_tls: Option<Tls>) {
+    let schema = maybe_tls.schema();
+    let port = maybe_tls.port();
+    let stream_fut = maybe_tls.stream_with_alpn(vec![b"h2".to_vec()]);
+
+    integration_test_with_server_flag!(
+        ServerFlags {
+            h2c: true,
             ..Default::default()
-        })
-        .build()
-        .await;
+        },
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        maybe_tls,
+        (
+            |_| async move {
+                let (mut sender, conn) = hyper::client::conn::Builder::new()
+                    .http2_only(true)
+                    .handshake::<_, Body>(stream_fut.await)
+                    .await
+                    .unwrap();
 
-    let resp = tb
-        .request(|b| {
-            b.uri(format!("/{}", kind))
-                .method("GET")
-                .body(Body::empty())
-                .context("can't make request")
-        })
-        .await
-        .unwrap();
+                tokio::spawn(conn);
 
-    assert_ne!(resp.status().as_u16(), StatusCode::OK);
+                let req = Request::builder()
+                    .uri(format!("{}://localhost:{}/", schema, port))
+                    .body(Body::empty())
+                    .unwrap();
 
-    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+                let res = sender.send_request(req).await.unwrap();
 
-    while let Some(ev) = rx.recv().await {
-        let WorkerEvents::Log(ev) = ev.event else {
-            continue;
-        };
-        if ev.level != LogLevel::Info {
-            continue;
-        }
-        if ev
-            .msg
-            .contains(&format!("triggered {}", kind.replace('-', "_")))
-        {
-            return;
-        }
-    }
+                assert_eq!(res.version(), http::Version::HTTP_2);
+                assert_eq!(res.status(), StatusCode::BAD_REQUEST);
 
-    unreachable!("test failed");
+                None
+            },
+            |resp| async {
+                // HTTP/1.1 clients should keep working on the same listener.
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_runtime_event_beforeunload_cpu() {
-    test_runtime_beforeunload_event("cpu", 50).await;
+async fn test_main_worker_http2_non_secure() {
+    test_main_worker_http2(new_localhost_tls(false)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_runtime_event_beforeunload_wall_clock() {
-    test_runtime_beforeunload_event("wall-clock", 50).await;
+async fn test_main_worker_http2_secure() {
+    test_main_worker_http2(new_localhost_tls(true)).await;
 }
 
 #[tokio::test]
 #[serial]
-async fn test_runtime_event_beforeunload_mem() {
-    test_runtime_beforeunload_event("mem", 50).await;
-}
+async fn test_tls_keep_previous_cert_on_failed_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_path = dir.path().join("key.pem");
+    let cert_path = dir.path().join("cert.pem");
 
-// NOTE(Nyannyacha): We cannot enable this test unless we clarify the trigger point of the unload
-// event.
-//
-// #[tokio::test]
-// #[serial]
-// async fn test_runtime_event_unload() {
-//     let (tx, mut rx) = mpsc::unbounded_channel();
-//     let tb = TestBedBuilder::new("./test_cases/runtime-event")
-//         .with_per_worker_policy(None)
-//         .with_worker_event_sender(Some(tx))
-//         .build()
-//         .await;
-//
-//     let resp = tb
-//         .request(|b| {
-//             b.uri("/unload")
-//                 .method("GET")
-//                 .body(Body::empty())
-//                 .context("can't make request")
-//         })
-//         .await
-//         .unwrap();
-//
-//     assert_eq!(resp.status().as_u16(), StatusCode::OK);
-//
-//     sleep(Duration::from_secs(8)).await;
-//     tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
-//
-//     while let Some(ev) = rx.recv().await {
-//         let WorkerEvents::Log(ev) = ev.event else {
-//             continue;
-//         };
-//         if ev.level != LogLevel::Info {
-//             continue;
-//         }
-//         if ev.msg.contains("triggered unload") {
-//             break;
-//         }
-//     }
-//
-//     unreachable!("test failed");
-// }
+    std::fs::write(&key_path, TLS_LOCALHOST_KEY).unwrap();
+    std::fs::write(&cert_path, TLS_LOCALHOST_CERT).unwrap();
+
+    let tls = Some(Tls::from_files(SECURE_PORT, &key_path, &cert_path).unwrap());
+    let client = tls.client();
+
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        tls,
+        (
+            |_| async move {
+                // the reload triggered by this change must fail, so the server
+                // should keep serving with the previous certificate.
+                std::fs::write(&cert_path, b"invalid cert").unwrap();
+                sleep(Duration::from_secs(2)).await;
+
+                Some(
+                    client
+                        .get(format!("https://localhost:{}/", SECURE_PORT))
+                        .send()
+                        .await,
+                )
+            },
+            |resp| async {
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
+}
 
 #[tokio::test]
 #[serial]
-async fn test_should_wait_for_background_tests() {
-    let (tx, mut rx) = mpsc::unbounded_channel();
-    let tb = TestBedBuilder::new("./test_cases/main")
-        // only the `per_worker` policy allows waiting for background tasks.
-        .with_per_worker_policy(None)
-        .with_worker_event_sender(Some(tx))
-        .build()
-        .await;
+async fn test_tls_select_cert_by_sni() {
+    let tls =
+        new_localhost_tls(true).map(|it| it.with_sni_dir(Path::new("./tests/fixture/tls/sni")));
 
-    let resp = tb
-        .request(|b| {
-            b.uri("/mark-background-task")
-                .header("x-cpu-time-soft-limit-ms", HeaderValue::from_static("100"))
-                .body(Body::empty())
-                .context("can't make request")
-        })
-        .await
-        .unwrap();
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        tls,
+        (
+            |_| async move {
+                let mut root_cert_store = RootCertStore::empty();
+                let certs = rustls_pemfile::certs(&mut Cursor::new(TLS_SNI_CERT))
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
 
-    assert_eq!(resp.status().as_u16(), StatusCode::OK);
+                let _ = root_cert_store.add_parsable_certificates(certs);
 
-    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+                let config = ClientConfig::builder()
+                    .with_root_certificates(root_cert_store)
+                    .with_no_client_auth();
 
-    while let Some(ev) = rx.recv().await {
-        let WorkerEvents::Log(ev) = ev.event else {
-            continue;
-        };
-        if ev.level != LogLevel::Info {
-            continue;
-        }
-        if ev.msg.contains("meow") {
-            return;
-        }
-    }
+                // the handshake only succeeds if the server picked the
+                // certificate for the requested server name.
+                let stream = TcpStream::connect(("127.0.0.1", SECURE_PORT))
+                    .await
+                    .unwrap();
+                let stream = TlsConnector::from(Arc::new(config))
+                    .connect(ServerName::try_from("edge-runtime.test").unwrap(), stream)
+                    .await
+                    .unwrap();
 
-    unreachable!("test failed");
+                let (mut sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+
+                tokio::spawn(conn);
+
+                let req = Request::builder()
+                    .uri("/echo-headers")
+                    .header(http::header::HOST, "edge-runtime.test")
+                    .header(TLS_SERVER_NAME_HEADER, "spoofed.test")
+                    .body(Body::empty())
+                    .unwrap();
+
+                let res = sender.send_request(req).await.unwrap();
+
+                assert_eq!(res.status(), StatusCode::OK);
+
+                let body = to_bytes(res.into_body()).await.unwrap();
+                let headers = serde_json::from_slice::<HashMap<String, String>>(&body).unwrap();
+
+                assert_eq!(
+                    headers.get(TLS_SERVER_NAME_HEADER).map(String::as_str),
+                    Some("edge-runtime.test")
+                );
+
+                None
+            },
+            |resp| async {
+                // clients asking for an unknown name get the default certificate.
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[tokio::test]
 #[serial]
-async fn test_should_not_wait_for_background_tests() {
-    let (tx, mut rx) = mpsc::unbounded_channel();
-    let tb = TestBedBuilder::new("./test_cases/main")
-        // only the `per_worker` policy allows waiting for background tasks.
-        .with_per_worker_policy(None)
-        .with_worker_event_sender(Some(tx))
-        .build()
-        .await;
+async fn test_tls_forward_client_cert_identity() {
+    let tls = new_localhost_tls(true).map(|it| {
+        it.with_client_auth(TLS_CLIENT_CA, ClientAuthMode::Optional)
+            .unwrap()
+    });
 
-    let resp = tb
-        .request(|b| {
-            b.uri("/mark-background-task-2")
-                .header("x-cpu-time-soft-limit-ms", HeaderValue::from_static("100"))
-                .body(Body::empty())
-                .context("can't make request")
-        })
-        .await
-        .unwrap();
+    integration_test!(
+        "./test_cases/main",
+        NON_SECURE_PORT,
+        "",
+        None,
+        None,
+        None,
+        tls,
+        (
+            |_| async move {
+                let mut root_cert_store = RootCertStore::empty();
+                let certs = rustls_pemfile::certs(&mut Cursor::new(TLS_LOCALHOST_ROOT_CA))
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
 
-    assert_eq!(resp.status().as_u16(), StatusCode::OK);
+                let _ = root_cert_store.add_parsable_certificates(certs);
 
-    tb.exit(Duration::from_secs(TESTBED_DEADLINE_SEC)).await;
+                let cert_chain = rustls_pemfile::certs(&mut Cursor::new(TLS_CLIENT_CERT))
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
 
-    while let Some(ev) = rx.recv().await {
-        let WorkerEvents::Log(ev) = ev.event else {
-            continue;
-        };
-        if ev.level != LogLevel::Info {
-            continue;
-        }
-        if ev.msg.contains("meow") {
-            unreachable!("test failed");
-        }
-    }
+                let key = rustls_pemfile::private_key(&mut Cursor::new(TLS_CLIENT_KEY))
+                    .unwrap()
+                    .unwrap();
+
+                let config = ClientConfig::builder()
+                    .with_root_certificates(root_cert_store)
+                    .with_client_auth_cert(cert_chain, key)
+                    .unwrap();
+
+                let stream = TcpStream::connect(("127.0.0.1", SECURE_PORT))
+                    .await
+                    .unwrap();
+                let stream = TlsConnector::from(Arc::new(config))
+                    .connect(ServerName::try_from("localhost").unwrap(), stream)
+                    .await
+                    .unwrap();
+
+                let (mut sender, conn) = hyper::client::conn::handshake(stream).await.unwrap();
+
+                tokio::spawn(conn);
+
+                let req = Request::builder()
+                    .uri("/echo-headers")
+                    .header(http::header::HOST, "localhost")
+                    .header(CLIENT_CERT_SUBJECT_HEADER, "CN=spoofed")
+                    .body(Body::empty())
+                    .unwrap();
+
+                let res = sender.send_request(req).await.unwrap();
+
+                assert_eq!(res.status(), StatusCode::OK);
+
+                let body = to_bytes(res.into_body()).await.unwrap();
+                let headers = serde_json::from_slice::<HashMap<String, String>>(&body).unwrap();
+
+                assert_eq!(
+                    headers.get(CLIENT_CERT_SUBJECT_HEADER).map(String::as_str),
+                    Some("O=edge-runtime test, CN=client.edge-runtime.test")
+                );
+                assert_eq!(
+                    headers.get(CLIENT_CERT_SAN_HEADER).map(String::as_str),
+                    Some("DNS:client.edge-runtime.test, URI:spiffe://edge-runtime.test/client")
+                );
+                assert_eq!(
+                    headers
+                        .get(CLIENT_CERT_FINGERPRINT_HEADER)
+                        .map(String::as_str),
+                    Some("34bdae7e10475291005a66d3c4296fb04c0264bb6faadd508bdb11cbd3281f7a")
+                );
+
+                None
+            },
+            |resp| async {
+                // client certificates are optional, so clients without one
+                // should still be served.
+                assert_eq!(resp.unwrap().status().as_u16(), StatusCode::BAD_REQUEST);
+            }
+        ),
+        TerminationToken::new()
+    );
 }
 
 #[derive(Deserialize)]
@@ -3289,6 +4846,10 @@ trait TlsExt {
     fn sock_addr(&self) -> SocketAddr;
     fn port(&self) -> u16;
     fn stream(&self) -> BoxFuture<'static, Box<dyn AsyncReadWrite>>;
+    fn stream_with_alpn(
+        &self,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> BoxFuture<'static, Box<dyn AsyncReadWrite>>;
 }
 
 impl TlsExt for Option<Tls> {
@@ -3334,6 +4895,13 @@ impl TlsExt for Option<Tls> {
     }
 
     fn stream(&self) -> BoxFuture<'static, Box<dyn AsyncReadWrite>> {
+        self.stream_with_alpn(vec![])
+    }
+
+    fn stream_with_alpn(
+        &self,
+        alpn_protocols: Vec<Vec<u8>>,
+    ) -> BoxFuture<'static, Box<dyn AsyncReadWrite>> {
         let use_tls = self.is_some();
         let sock_addr = self.sock_addr();
 
@@ -3347,10 +4915,12 @@ impl TlsExt for Option<Tls> {
                 let mut root_cert_store = RootCertStore::empty();
                 let _ = root_cert_store.add_parsable_certificates(certs);
 
-                let config = ClientConfig::builder()
+                let mut config = ClientConfig::builder()
                     .with_root_certificates(root_cert_store)
                     .with_no_client_auth();
 
+                config.alpn_protocols = alpn_protocols;
+
                 let connector = TlsConnector::from(Arc::new(config));
                 let dnsname = ServerName::try_from("localhost").unwrap();
 
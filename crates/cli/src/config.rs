@@ -0,0 +1,437 @@
+// Fills in the options of `edge-runtime start` from a TOML or JSON file given
+// with `--config`. Each key of the file is the long name of a flag, e.g.
+//
+//   main-service = "./main"
+//   port = "${PORT:-9000}"
+//   proxy-protocol-trusted = ["10.0.0.0/8"]
+//
+// An option set on the command line or through its environment variable takes
+// precedence over the file, which in turn takes precedence over the defaults.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Error};
+use clap::error::{ContextKind, ContextValue};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::flags::get_cli;
+
+const START_COMMAND: &str = "start";
+const CONFIG_ARG: &str = "config";
+
+/// Parses the command line of this process, along with the config file if
+/// one is given.
+pub(super) fn get_matches() -> Result<ArgMatches, Error> {
+    get_matches_from(std::env::args_os().collect())
+}
+
+fn get_matches_from(mut args: Vec<OsString>) -> Result<ArgMatches, Error> {
+    // NOTE: The command line is parsed leniently first, only to find out
+    // where the config file is and which options it may still set. Any error
+    // is reported by the second parse.
+    let Ok(matches) = get_cli().ignore_errors(true).try_get_matches_from(&args) else {
+        return Ok(get_cli().get_matches_from(args));
+    };
+
+    let Some(sub_matches) = matches.subcommand_matches(START_COMMAND) else {
+        return Ok(get_cli().get_matches_from(args));
+    };
+
+    let Some(path) = sub_matches.get_one::<PathBuf>(CONFIG_ARG).cloned() else {
+        return Ok(get_cli().get_matches_from(args));
+    };
+
+    let table = read_config_file(&path)?;
+    let cli = get_cli();
+    let start = cli.find_subcommand(START_COMMAND).unwrap();
+    let mut applied = vec![];
+
+    for (key, value) in table.iter() {
+        let Some(arg) = find_arg(&cli, start, key) else {
+            bail!(
+                "{}: `{}` is not an option of `edge-runtime {}`",
+                path.display(),
+                key,
+                START_COMMAND
+            );
+        };
+
+        if key == CONFIG_ARG {
+            bail!(
+                "{}: `{}` can't be set in a config file",
+                path.display(),
+                key
+            );
+        }
+
+        let is_overridden = matches!(
+            sub_matches.value_source(arg.get_id().as_str()),
+            Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+        );
+
+        if is_overridden {
+            continue;
+        }
+
+        let values =
+            to_values(arg, value).with_context(|| format!("{}: `{}`", path.display(), key))?;
+
+        for value in values.iter() {
+            args.push(match value {
+                Some(value) => format!("--{}={}", key, value).into(),
+                None => format!("--{}", key).into(),
+            });
+        }
+
+        applied.push((key.as_str(), values));
+    }
+
+    match get_cli().try_get_matches_from(&args) {
+        Ok(matches) => Ok(matches),
+        Err(err) => match find_invalid_key(&err, &applied) {
+            Some(key) => Err(anyhow!(
+                "{}: `{}`: {}",
+                path.display(),
+                key,
+                match std::error::Error::source(&err) {
+                    Some(source) => source.to_string(),
+                    None => err.kind().to_string(),
+                }
+            )),
+
+            None => err.exit(),
+        },
+    }
+}
+
+fn read_config_file(path: &Path) -> Result<toml::Table, Error> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("can't read the config file: {}", path.display()))?;
+
+    let is_json = path
+        .extension()
+        .is_some_and(|it| it.eq_ignore_ascii_case("json"));
+
+    if is_json {
+        serde_json::from_str::<toml::Table>(&content)
+            .with_context(|| format!("invalid config file: {}", path.display()))
+    } else {
+        toml::from_str::<toml::Table>(&content)
+            .with_context(|| format!("invalid config file: {}", path.display()))
+    }
+}
+
+/// Looks up the argument named `key` among the ones of `start`, and the
+/// global ones of `cli`.
+fn find_arg<'a>(cli: &'a Command, start: &'a Command, key: &str) -> Option<&'a Arg> {
+    start
+        .get_arguments()
+        .chain(cli.get_arguments().filter(|it| it.is_global_set()))
+        .find(|it| it.get_long() == Some(key))
+}
+
+/// Turns the value of a key into the values of the arguments it stands for.
+/// `None` stands for an argument given without a value.
+fn to_values(arg: &Arg, value: &toml::Value) -> Result<Vec<Option<String>>, Error> {
+    if !arg.get_action().takes_values() {
+        return match value {
+            toml::Value::Boolean(true) => Ok(vec![None]),
+            toml::Value::Boolean(false) => Ok(vec![]),
+            _ => bail!("expected a boolean"),
+        };
+    }
+
+    // `true` turns on a flag whose value may be omitted, e.g. `--tls`.
+    let is_value_optional = arg.get_num_args().is_some_and(|it| it.min_values() == 0);
+
+    if is_value_optional && matches!(value, toml::Value::Boolean(true)) {
+        return Ok(vec![None]);
+    }
+
+    let values = match value {
+        toml::Value::Array(values) => {
+            if !matches!(arg.get_action(), ArgAction::Append) {
+                bail!("expected a single value, got an array");
+            }
+
+            values.iter().collect::<Vec<_>>()
+        }
+
+        it => vec![it],
+    };
+
+    values
+        .into_iter()
+        .map(|it| to_arg_value(it).map(Some))
+        .collect()
+}
+
+fn to_arg_value(value: &toml::Value) -> Result<String, Error> {
+    match value {
+        toml::Value::String(it) => interpolate_env(it),
+        toml::Value::Integer(it) => Ok(it.to_string()),
+        toml::Value::Float(it) => Ok(it.to_string()),
+        toml::Value::Boolean(it) => Ok(it.to_string()),
+        toml::Value::Datetime(_) | toml::Value::Array(_) | toml::Value::Table(_) => {
+            bail!("expected a string, a number or a boolean")
+        }
+    }
+}
+
+/// Replaces `${NAME}` with the value of the environment variable `NAME`, or
+/// with `default` in `${NAME:-default}` if it is unset or empty. `$$` stands
+/// for a literal `$`.
+fn interpolate_env(s: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if let Some(it) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = it;
+            continue;
+        }
+
+        let Some(it) = rest.strip_prefix("${") else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let Some(end) = it.find('}') else {
+            bail!("unterminated `${{` in {:?}", s);
+        };
+
+        let (name, maybe_default) = match it[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&it[..end], None),
+        };
+
+        match (std::env::var(name), maybe_default) {
+            (Ok(value), Some(default)) if value.is_empty() => out.push_str(default),
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), Some(default)) => out.push_str(default),
+            (Err(_), None) => bail!("the environment variable `{}` is not set", name),
+        }
+
+        rest = &it[end + 1..];
+    }
+
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Finds which of the keys taken from the config file the failed parse is
+/// about, if any.
+fn find_invalid_key<'a>(
+    err: &clap::Error,
+    applied: &[(&'a str, Vec<Option<String>>)],
+) -> Option<&'a str> {
+    let Some(ContextValue::String(invalid_arg)) = err.get(ContextKind::InvalidArg) else {
+        return None;
+    };
+
+    // the argument is rendered as in `--port <PORT>`.
+    let name = invalid_arg
+        .trim_start_matches('-')
+        .split([' ', '='])
+        .next()
+        .unwrap_or_default();
+
+    let (key, values) = applied.iter().find(|(key, _)| *key == name)?;
+
+    // the same argument may have been given on the command line as well.
+    match err.get(ContextKind::InvalidValue) {
+        Some(ContextValue::String(value)) if !values.contains(&Some(value.clone())) => None,
+        _ => Some(*key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::*;
+
+    fn get_start_arg(key: &str) -> Arg {
+        let cli = get_cli();
+        let start = cli.find_subcommand(START_COMMAND).unwrap();
+
+        find_arg(&cli, start, key).unwrap().clone()
+    }
+
+    #[test]
+    fn test_interpolate_env() {
+        std::env::set_var("EDGE_RUNTIME_TEST_CONFIG_SET", "8000");
+        std::env::set_var("EDGE_RUNTIME_TEST_CONFIG_EMPTY", "");
+
+        assert_eq!(interpolate_env("a$$b").unwrap(), "a$b");
+        assert_eq!(interpolate_env("$${X}").unwrap(), "${X}");
+        assert_eq!(interpolate_env("cost: $5").unwrap(), "cost: $5");
+        assert_eq!(
+            interpolate_env(":${EDGE_RUNTIME_TEST_CONFIG_SET}").unwrap(),
+            ":8000"
+        );
+        assert_eq!(
+            interpolate_env("${EDGE_RUNTIME_TEST_CONFIG_SET:-9000}").unwrap(),
+            "8000"
+        );
+        assert_eq!(
+            interpolate_env("${EDGE_RUNTIME_TEST_CONFIG_EMPTY:-9000}").unwrap(),
+            "9000"
+        );
+        assert_eq!(
+            interpolate_env("${EDGE_RUNTIME_TEST_CONFIG_UNSET:-9000}").unwrap(),
+            "9000"
+        );
+        assert_eq!(
+            interpolate_env("${EDGE_RUNTIME_TEST_CONFIG_UNSET:-}").unwrap(),
+            ""
+        );
+
+        let err = interpolate_env("${EDGE_RUNTIME_TEST_CONFIG_SET").unwrap_err();
+
+        assert!(err.to_string().contains("unterminated"));
+
+        let err = interpolate_env("${EDGE_RUNTIME_TEST_CONFIG_UNSET}").unwrap_err();
+
+        assert!(err.to_string().contains("is not set"));
+    }
+
+    #[test]
+    fn test_to_values_of_bool_flag() {
+        let arg = get_start_arg("h2c");
+
+        assert_eq!(
+            to_values(&arg, &toml::Value::Boolean(true)).unwrap(),
+            [None]
+        );
+        assert!(to_values(&arg, &toml::Value::Boolean(false))
+            .unwrap()
+            .is_empty());
+        assert!(to_values(&arg, &toml::Value::String("true".into())).is_err());
+    }
+
+    #[test]
+    fn test_to_values_of_optional_value_flag() {
+        let arg = get_start_arg("tls");
+
+        assert_eq!(
+            to_values(&arg, &toml::Value::Boolean(true)).unwrap(),
+            [None]
+        );
+        assert_eq!(
+            to_values(&arg, &toml::Value::Integer(8443)).unwrap(),
+            [Some("8443".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_to_values_of_array() {
+        let values = toml::Value::Array(vec![
+            toml::Value::String("10.0.0.0/8".into()),
+            toml::Value::String("192.168.0.0/16".into()),
+        ]);
+
+        assert_eq!(
+            to_values(&get_start_arg("proxy-protocol-trusted"), &values).unwrap(),
+            [
+                Some("10.0.0.0/8".to_string()),
+                Some("192.168.0.0/16".to_string())
+            ]
+        );
+
+        let err = to_values(&get_start_arg("port"), &values).unwrap_err();
+
+        assert!(err.to_string().contains("expected a single value"));
+    }
+
+    #[test]
+    fn test_find_invalid_key() {
+        let err = get_cli()
+            .try_get_matches_from(["edge-runtime", "start", "--port=abc"])
+            .unwrap_err();
+
+        assert_eq!(
+            find_invalid_key(&err, &[("port", vec![Some("abc".to_string())])]),
+            Some("port")
+        );
+
+        // not taken from the config file.
+        assert_eq!(find_invalid_key(&err, &[("h2c", vec![None])]), None);
+
+        let err = get_cli()
+            .try_get_matches_from([
+                "edge-runtime",
+                "start",
+                "--proxy-protocol",
+                "--proxy-protocol-trusted=10.0.0.0/8",
+                "--proxy-protocol-trusted=bad",
+            ])
+            .unwrap_err();
+
+        // the invalid value was given on the command line, not in the file.
+        assert_eq!(
+            find_invalid_key(
+                &err,
+                &[(
+                    "proxy-protocol-trusted",
+                    vec![Some("10.0.0.0/8".to_string())]
+                )]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_command_line_and_env_override_config_file() {
+        let path = std::env::temp_dir().join(format!(
+            "edge-runtime-test-config-{}.toml",
+            std::process::id()
+        ));
+
+        std::fs::write(
+            &path,
+            concat!(
+                "port = 9000\n",
+                "metrics-addr = \"127.0.0.1:9100\"\n",
+                "main-service = \"./from-file\"\n",
+            ),
+        )
+        .unwrap();
+
+        std::env::set_var("EDGE_RUNTIME_METRICS_ADDR", "127.0.0.1:9200");
+
+        let result = get_matches_from(vec![
+            "edge-runtime".into(),
+            "start".into(),
+            "--config".into(),
+            path.clone().into(),
+            "--port=8000".into(),
+        ]);
+
+        std::env::remove_var("EDGE_RUNTIME_METRICS_ADDR");
+        std::fs::remove_file(&path).unwrap();
+
+        let matches = result.unwrap();
+        let sub_matches = matches.subcommand_matches(START_COMMAND).unwrap();
+
+        assert_eq!(sub_matches.get_one::<u16>("port"), Some(&8000));
+        assert_eq!(
+            sub_matches.get_one::<SocketAddr>("metrics-addr"),
+            Some(&"127.0.0.1:9200".parse().unwrap())
+        );
+        assert_eq!(
+            sub_matches
+                .get_one::<String>("main-service")
+                .map(String::as_str),
+            Some("./from-file")
+        );
+    }
+}
@@ -1,10 +1,14 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+};
 
 use clap::{
     arg,
     builder::{BoolishValueParser, FalseyValueParser, TypedValueParser},
     crate_version, value_parser, ArgAction, ArgGroup, Command, ValueEnum,
 };
+use ipnetwork::IpNetwork;
 use sb_graph::Checksum;
 
 #[derive(ValueEnum, Default, Clone, Copy)]
@@ -59,7 +63,32 @@ pub(super) fn get_cli() -> Command {
 fn get_start_command() -> Command {
     Command::new("start")
         .about("Start the server")
-        .arg(arg!(-i --ip <HOST>).help("Host IP address to listen on").default_value("0.0.0.0"))
+        .after_help(concat!(
+            "Signals:\n",
+            "  SIGHUP   Reload the TLS certificate and key (terminates the process without TLS)\n",
+            "  SIGUSR1  Reload the main worker, draining the previous one (or POST /reload on --admin-addr)\n",
+            "  SIGUSR2  Hand the listening sockets over to a new process (see --listener-handover)\n",
+            "  SIGTERM  Shut down gracefully",
+        ))
+        .arg(
+            arg!(--config <PATH>)
+                .help(concat!(
+                    "Path to a TOML or JSON file to read options from, keyed by their long names. ",
+                    "Options given on the command line or through the environment take precedence, ",
+                    "and `${VAR}` or `${VAR:-default}` in strings is replaced with the environment variable"
+                ))
+                .env("EDGE_RUNTIME_CONFIG")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(-i --ip <HOST>)
+                .help(concat!(
+                    "Host IP address to listen on. ",
+                    "Both IPv4 and IPv6 addresses are accepted, and `::` listens on both families"
+                ))
+                .default_value("0.0.0.0")
+                .value_parser(value_parser!(IpAddr)),
+        )
         .arg(
             arg!(-p --port <PORT>)
                 .help("Port to listen on")
@@ -67,6 +96,18 @@ fn get_start_command() -> Command {
                 .default_value("9000")
                 .value_parser(value_parser!(u16)),
         )
+        .arg(
+            arg!(--"unix-socket" <Path>)
+                .help("Path to a Unix domain socket to listen on, in addition to the TCP port")
+                .env("EDGE_RUNTIME_UNIX_SOCKET")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"unix-socket-only")
+                .help("Do not listen on the TCP port, only on the Unix domain socket")
+                .requires("unix-socket")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--tls [PORT])
                 .env("EDGE_RUNTIME_TLS")
@@ -88,6 +129,114 @@ fn get_start_command() -> Command {
                 .env("EDGE_RUNTIME_TLS_CERT_PATH")
                 .value_parser(value_parser!(PathBuf)),
         )
+        .arg(
+            arg!(--"tls-sni" <SNI>)
+                .help(concat!(
+                    "Serve an additional certificate to clients that ask for a server name through ",
+                    "SNI, in the form of `<SERVER_NAME>=<CERT_PATH>,<KEY_PATH>`. ",
+                    "The server name may start with `*.` to match any single label"
+                ))
+                .requires("tls")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"tls-sni-dir" <DIR>)
+                .help(concat!(
+                    "Path to a directory of certificates served by SNI. Each certificate is named ",
+                    "after its server name, as `<NAME>.pem` with `<NAME>-key.pem` or as `<NAME>.crt` ",
+                    "with `<NAME>.key`"
+                ))
+                .env("EDGE_RUNTIME_TLS_SNI_DIR")
+                .requires("tls")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"tls-client-ca" <Path>)
+                .help(concat!(
+                    "Path to PEM-encoded CA bundle to verify client certificates against (mutual TLS). ",
+                    "The identity of a verified certificate is forwarded to the main worker through ",
+                    "the `x-edge-runtime-client-cert-*` headers"
+                ))
+                .env("EDGE_RUNTIME_TLS_CLIENT_CA_PATH")
+                .requires("tls")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"tls-client-auth" <MODE>)
+                .help("Whether clients must present a certificate when `--tls-client-ca` is specified")
+                .requires("tls-client-ca")
+                .default_value("required")
+                .value_parser(["required", "optional"]),
+        )
+        .arg(
+            arg!(--h2c)
+                .help(concat!(
+                    "Accept HTTP/2 with prior knowledge (h2c) on the non-secure listeners. ",
+                    "HTTP/2 over TLS is always available through ALPN"
+                ))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"proxy-protocol")
+                .help(concat!(
+                    "Expect every TCP connection to start with a PROXY protocol (v1 or v2) header, ",
+                    "and use the client address it carries as the remote address"
+                ))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"proxy-protocol-trusted" <CIDR>)
+                .help(concat!(
+                    "Only accept connections from proxies within this address range. ",
+                    "Can be specified multiple times. If omitted, every peer is trusted"
+                ))
+                .requires("proxy-protocol")
+                .value_parser(value_parser!(IpNetwork))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"metrics-addr" <ADDR>)
+                .help(concat!(
+                    "Serve runtime metrics in the Prometheus text format at `/metrics` on this address. ",
+                    "The endpoint is served independently of the main worker"
+                ))
+                .env("EDGE_RUNTIME_METRICS_ADDR")
+                .value_parser(value_parser!(SocketAddr)),
+        )
+        .arg(
+            arg!(--"admin-addr" <ADDR>)
+                .help(concat!(
+                    "Serve the `/livez` and `/readyz` health checks on this address, ",
+                    "and `POST /reload` to reload the main worker like SIGUSR1 does"
+                ))
+                .env("EDGE_RUNTIME_ADMIN_ADDR")
+                .value_parser(value_parser!(SocketAddr)),
+        )
+        .arg(
+            arg!(--"health-on-main-port")
+                .help(concat!(
+                    "Also answer the `/livez` and `/readyz` health checks on the main port, ",
+                    "instead of passing them to the main worker"
+                ))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"trust-request-id")
+                .help(concat!(
+                    "Keep the X-Request-Id header sent by the client instead of generating a new ID. ",
+                    "Only enable this behind a proxy that sets the header itself"
+                ))
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            arg!(--"listener-handover")
+                .help(concat!(
+                    "On SIGUSR2, start a new process with the same arguments that takes over the ",
+                    "listening sockets. Once it is listening, drain the connections of this one and ",
+                    "exit. If it fails to start, keep serving"
+                ))
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--"main-service" <DIR>)
                 .help("Path to main service directory or eszip")
@@ -109,6 +258,15 @@ fn get_start_command() -> Command {
                 .default_value("per_worker")
                 .value_parser(["per_worker", "per_request", "oneshot"]),
         )
+        .arg(
+            arg!(--"routing" <STRATEGY>)
+                .help(concat!(
+                    "Strategy to route requests among the workers of a service. ",
+                    "`p2c` picks the less busy of two workers taken at random"
+                ))
+                .default_value("round_robin")
+                .value_parser(["round_robin", "least_outstanding", "p2c"]),
+        )
         .arg(
             arg!(--"decorator" <TYPE>)
                 .help(concat!(
@@ -166,6 +324,38 @@ fn get_start_command() -> Command {
                 .default_value("10000")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"max-queued-requests" <COUNT>)
+                .help(concat!(
+                    "Maximum count of requests that can wait for the workers of a service. ",
+                    "Requests beyond it are turned away right away, lower priorities first"
+                ))
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            arg!(--"circuit-breaker-threshold" <COUNT>)
+                .help(concat!(
+                    "Count of failures in a row after which no worker is created for a service ",
+                    "for a while, i.e. boot failures and deaths from uncaught exceptions ",
+                    "(disabled by default)"
+                ))
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"circuit-breaker-backoff" <MILLISECONDS>)
+                .help(concat!(
+                    "Time in milliseconds for which no worker is created for a failing service. ",
+                    "It doubles every time the service fails again"
+                ))
+                .default_value("1000")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"circuit-breaker-max-backoff" <MILLISECONDS>)
+                .help("Maximum time in milliseconds for which no worker is created for a failing service")
+                .default_value("60000")
+                .value_parser(value_parser!(u64)),
+        )
         .arg(
             arg!(--"request-idle-timeout" <MILLISECONDS>)
                 .help("Maximum time in milliseconds that can be waited from when a worker takes over the request (disabled by default)")
@@ -176,6 +366,85 @@ fn get_start_command() -> Command {
                 .help("Maximum time in milliseconds that can be waited from when the connection is accepted until the request body is fully read (disabled by default)")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"request-hard-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that can be waited for the main worker to respond to a request before responding with 504 (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"keepalive-idle-timeout" <MILLISECONDS>)
+                .help("Maximum time in milliseconds that a keep-alive connection can stay idle between requests before it is closed (disabled by default)")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"max-connections" <COUNT>)
+                .help("Maximum count of connections that can be open simultaneously, including those still reading their PROXY header or doing their TLS handshake. Further connections wait in the listen backlog until one is closed (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"max-connections-per-ip" <COUNT>)
+                .help("Maximum count of connections that a single client address can have open simultaneously. Further connections are refused (unlimited by default)")
+                .value_parser(value_parser!(u32).range(1..).map(|it| -> usize { it as usize })),
+        )
+        .arg(
+            arg!(--"rate-limit" <REQUESTS_PER_SECOND>)
+                .help("Maximum rate of requests that are passed to the main worker for each rate limit key. Requests beyond it are answered with 429 (disabled by default)")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            arg!(--"rate-limit-burst" <COUNT>)
+                .help("Maximum count of requests that can be passed at once for each rate limit key (defaults to the rate)")
+                .requires("rate-limit")
+                .value_parser(value_parser!(u32).range(1..)),
+        )
+        .arg(
+            arg!(--"rate-limit-key" <KEY>)
+                .help("What requests are grouped by for rate limiting: `ip`, `path` (first path segment) or `header:<NAME>`")
+                .requires("rate-limit")
+                .default_value("ip"),
+        )
+        .arg(
+            arg!(--"max-request-body-bytes" <BYTES>)
+                .help("Maximum size in bytes of a request body. Larger requests are answered with 413 (unlimited by default)")
+                .value_parser(value_parser!(u64).range(1..)),
+        )
+        .arg(
+            arg!(--"compress" <CODING>)
+                .help("Compress responses with this content coding when the client accepts it. Can be given more than once, the preferred coding first (disabled by default)")
+                .value_parser(["br", "zstd", "gzip"])
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"compress-min-size" <BYTES>)
+                .help("Minimum size in bytes of a response to be compressed. Responses of unknown size are always compressed")
+                .requires("compress")
+                .default_value("1024")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            arg!(--"compress-content-type" <TYPE>)
+                .help("Content type of the responses to be compressed, such as `application/json` or `text/*`. Can be given more than once (defaults to common textual types)")
+                .requires("compress")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            arg!(--"access-log" <PATH>)
+                .help("Write an access log line for every request to this file, or to stdout if `-` is given (disabled by default)")
+                .value_parser(value_parser!(PathBuf)),
+        )
+        .arg(
+            arg!(--"access-log-format" <FORMAT>)
+                .help("Format of the access log lines")
+                .requires("access-log")
+                .default_value("json")
+                .value_parser(["json", "combined"]),
+        )
+        .arg(
+            arg!(--"access-log-query")
+                .help("Include the query string of the request in the access log lines")
+                .requires("access-log")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             arg!(--"inspect" [HOST_AND_PORT])
                 .help("Activate inspector on host:port")
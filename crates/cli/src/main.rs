@@ -1,35 +1,46 @@
+mod config;
 mod env;
 mod flags;
 
 #[cfg(not(feature = "tracing"))]
 mod logger;
 
-use anyhow::{anyhow, bail, Error};
+use anyhow::{anyhow, bail, Context, Error};
 use base::commands::start_server;
 
-use base::rt_worker::worker_pool::{SupervisorPolicy, WorkerPoolPolicy};
-use base::server::{ServerFlags, Tls, WorkerEntrypoints};
+use base::rt_worker::worker_pool::{
+    CircuitBreakerPolicy, RoutingStrategy, SupervisorPolicy, WorkerPoolPolicy,
+};
+use base::server::{
+    AccessLogConfig, AccessLogFormat, AccessLogTarget, ClientAuthMode, CompressionConfig,
+    ContentCoding, ListenFds, RateLimitConfig, RateLimitKey, ServerFlags, Tls, WorkerEntrypoints,
+};
 use base::utils::path::find_up;
 use base::utils::units::percentage_value;
 use base::{CacheSetting, DecoratorType, InspectorOption};
 use clap::ArgMatches;
 use deno_core::url::Url;
 use env::resolve_deno_runtime_env;
-use flags::{get_cli, EszipV2ChecksumKind};
+use flags::EszipV2ChecksumKind;
+use ipnetwork::IpNetwork;
 use log::warn;
 use sb_graph::emitter::EmitterFactory;
 use sb_graph::import_map::load_import_map;
 use sb_graph::{extract_from_file, generate_binary_eszip, include_glob_patterns_in_eszip};
 use std::fs::File;
 use std::io::Write;
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::Duration;
 
 fn main() -> Result<ExitCode, anyhow::Error> {
     resolve_deno_runtime_env();
 
+    // SAFETY: No other thread has been started yet.
+    let listen_fds = unsafe { ListenFds::take_from_env() }?;
+
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .thread_name("sb-main")
@@ -39,7 +50,7 @@ fn main() -> Result<ExitCode, anyhow::Error> {
     // TODO: Tokio runtime shouldn't be needed here (Address later)
     let local = tokio::task::LocalSet::new();
     let res: Result<ExitCode, Error> = local.block_on(&runtime, async {
-        let matches = get_cli().get_matches();
+        let matches = config::get_matches()?;
         let verbose = matches.get_flag("verbose");
 
         if !matches.get_flag("quiet") {
@@ -70,27 +81,74 @@ fn main() -> Result<ExitCode, anyhow::Error> {
         #[allow(clippy::arc_with_non_send_sync)]
         let exit_code = match matches.subcommand() {
             Some(("start", sub_matches)) => {
-                let ip = sub_matches.get_one::<String>("ip").cloned().unwrap();
+                let ip = sub_matches.get_one::<IpAddr>("ip").cloned().unwrap();
                 let port = sub_matches.get_one::<u16>("port").copied().unwrap();
 
                 let maybe_tls = if let Some(port) = sub_matches.get_one::<u16>("tls").copied() {
-                    let Some((key_slice, cert_slice)) = sub_matches
+                    let Some((key_path, cert_path)) = sub_matches
                         .get_one::<PathBuf>("key")
-                        .and_then(|it| std::fs::read(it).ok())
-                        .zip(
-                            sub_matches
-                                .get_one::<PathBuf>("cert")
-                                .and_then(|it| std::fs::read(it).ok()),
-                        )
+                        .zip(sub_matches.get_one::<PathBuf>("cert"))
                     else {
                         bail!("unable to load the key file or cert file");
                     };
 
-                    Some(Tls::new(port, &key_slice, &cert_slice)?)
+                    let mut tls = Tls::from_files(port, key_path, cert_path)
+                        .context("unable to load the key file or cert file")?;
+
+                    for sni in sub_matches
+                        .get_many::<String>("tls-sni")
+                        .into_iter()
+                        .flatten()
+                    {
+                        let Some((server_name, (cert_path, key_path))) = sni
+                            .split_once('=')
+                            .and_then(|(name, paths)| Some(name).zip(paths.split_once(',')))
+                        else {
+                            bail!("invalid --tls-sni value: {}", sni);
+                        };
+
+                        tls = tls
+                            .with_sni_files(server_name, Path::new(key_path), Path::new(cert_path))
+                            .with_context(|| {
+                                format!("unable to load the certificate for {}", server_name)
+                            })?;
+                    }
+
+                    if let Some(dir) = sub_matches.get_one::<PathBuf>("tls-sni-dir") {
+                        tls = tls.with_sni_dir(dir);
+                    }
+
+                    if let Some(ca_path) = sub_matches.get_one::<PathBuf>("tls-client-ca") {
+                        let ca_bundle = std::fs::read(ca_path)
+                            .context("unable to load the client CA bundle file")?;
+                        let mode = sub_matches
+                            .get_one::<String>("tls-client-auth")
+                            .unwrap()
+                            .parse::<ClientAuthMode>()?;
+
+                        tls = tls.with_client_auth(&ca_bundle, mode)?;
+                    }
+
+                    Some(tls)
                 } else {
                     None
                 };
 
+                let maybe_unix_socket = sub_matches.get_one::<PathBuf>("unix-socket").cloned();
+                let unix_socket_only = sub_matches.get_flag("unix-socket-only");
+                let h2c = sub_matches.get_flag("h2c");
+                let maybe_metrics_addr = sub_matches.get_one::<SocketAddr>("metrics-addr").copied();
+                let maybe_admin_addr = sub_matches.get_one::<SocketAddr>("admin-addr").copied();
+                let health_on_main_port = sub_matches.get_flag("health-on-main-port");
+                let trust_request_id = sub_matches.get_flag("trust-request-id");
+                let listener_handover = sub_matches.get_flag("listener-handover");
+                let proxy_protocol = sub_matches.get_flag("proxy-protocol");
+                let proxy_protocol_trusted_ranges = sub_matches
+                    .get_many::<IpNetwork>("proxy-protocol-trusted")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect::<Vec<_>>();
+
                 let main_service_path = sub_matches
                     .get_one::<String>("main-service")
                     .cloned()
@@ -118,6 +176,30 @@ fn main() -> Result<ExitCode, anyhow::Error> {
                     .get_one::<String>("policy")
                     .map(|it| it.parse::<SupervisorPolicy>().unwrap());
 
+                let routing = sub_matches
+                    .get_one::<String>("routing")
+                    .map(|it| it.parse::<RoutingStrategy>().unwrap())
+                    .unwrap_or_default();
+
+                let maybe_circuit_breaker = sub_matches
+                    .get_one::<usize>("circuit-breaker-threshold")
+                    .cloned()
+                    .map(|threshold| CircuitBreakerPolicy {
+                        threshold,
+                        backoff: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("circuit-breaker-backoff")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                        max_backoff: Duration::from_millis(
+                            sub_matches
+                                .get_one::<u64>("circuit-breaker-max-backoff")
+                                .cloned()
+                                .unwrap(),
+                        ),
+                    });
+
                 let graceful_exit_deadline_sec = sub_matches
                     .get_one::<u64>("graceful-exit-timeout")
                     .cloned()
@@ -143,10 +225,75 @@ fn main() -> Result<ExitCode, anyhow::Error> {
                     sub_matches.get_one::<usize>("max-parallelism").cloned();
                 let maybe_request_wait_timeout =
                     sub_matches.get_one::<u64>("request-wait-timeout").cloned();
+                let maybe_max_queued_requests =
+                    sub_matches.get_one::<usize>("max-queued-requests").cloned();
                 let maybe_request_idle_timeout =
                     sub_matches.get_one::<u64>("request-idle-timeout").cloned();
                 let maybe_request_read_timeout =
                     sub_matches.get_one::<u64>("request-read-timeout").cloned();
+                let maybe_request_hard_timeout =
+                    sub_matches.get_one::<u64>("request-hard-timeout").cloned();
+                let maybe_keepalive_idle_timeout = sub_matches
+                    .get_one::<u64>("keepalive-idle-timeout")
+                    .cloned();
+                let maybe_max_connections =
+                    sub_matches.get_one::<usize>("max-connections").cloned();
+                let maybe_max_connections_per_ip = sub_matches
+                    .get_one::<usize>("max-connections-per-ip")
+                    .cloned();
+                let maybe_rate_limit = match sub_matches.get_one::<u32>("rate-limit").cloned() {
+                    Some(rate) => Some(RateLimitConfig {
+                        key: sub_matches
+                            .get_one::<String>("rate-limit-key")
+                            .unwrap()
+                            .parse::<RateLimitKey>()
+                            .context("invalid rate limit key")?,
+                        rate,
+                        burst: sub_matches
+                            .get_one::<u32>("rate-limit-burst")
+                            .cloned()
+                            .unwrap_or(rate),
+                    }),
+
+                    None => None,
+                };
+                let maybe_max_request_body_bytes = sub_matches
+                    .get_one::<u64>("max-request-body-bytes")
+                    .cloned();
+                let maybe_compression = match sub_matches.get_many::<String>("compress") {
+                    Some(codings) => Some(CompressionConfig {
+                        codings: codings
+                            .map(|it| it.parse::<ContentCoding>())
+                            .collect::<Result<Vec<_>, _>>()?,
+                        min_size: sub_matches
+                            .get_one::<u64>("compress-min-size")
+                            .cloned()
+                            .unwrap(),
+                        content_types: match sub_matches.get_many::<String>("compress-content-type")
+                        {
+                            Some(types) => types.cloned().collect(),
+                            None => CompressionConfig::default().content_types,
+                        },
+                    }),
+
+                    None => None,
+                };
+                let maybe_access_log = match sub_matches.get_one::<PathBuf>("access-log") {
+                    Some(path) => Some(AccessLogConfig {
+                        format: sub_matches
+                            .get_one::<String>("access-log-format")
+                            .unwrap()
+                            .parse::<AccessLogFormat>()?,
+                        target: if path.as_os_str() == "-" {
+                            AccessLogTarget::Stdout
+                        } else {
+                            AccessLogTarget::File(path.clone())
+                        },
+                        include_query: sub_matches.get_flag("access-log-query"),
+                    }),
+
+                    None => None,
+                };
 
                 let maybe_beforeunload_wall_clock_pct = sub_matches
                     .get_one::<u8>("dispatch-beforeunload-wall-clock-ratio")
@@ -195,12 +342,33 @@ fn main() -> Result<ExitCode, anyhow::Error> {
                     allow_main_inspector,
                     tcp_nodelay,
 
+                    unix_socket: maybe_unix_socket,
+                    unix_socket_only,
+                    h2c,
+                    proxy_protocol,
+                    proxy_protocol_trusted_ranges,
+                    metrics_addr: maybe_metrics_addr,
+                    admin_addr: maybe_admin_addr,
+                    health_on_main_port,
+                    trust_request_id,
+                    max_connections: maybe_max_connections,
+                    max_connections_per_ip: maybe_max_connections_per_ip,
+                    rate_limit: maybe_rate_limit,
+                    access_log: maybe_access_log,
+                    max_request_body_bytes: maybe_max_request_body_bytes,
+                    compression: maybe_compression,
+                    listener_handover,
+                    listen_fds: listen_fds.clone(),
+
                     graceful_exit_deadline_sec,
                     graceful_exit_keepalive_deadline_ms,
                     event_worker_exit_deadline_sec,
                     request_wait_timeout_ms: maybe_request_wait_timeout,
+                    max_queued_requests: maybe_max_queued_requests,
                     request_idle_timeout_ms: maybe_request_idle_timeout,
                     request_read_timeout_ms: maybe_request_read_timeout,
+                    request_hard_timeout_ms: maybe_request_hard_timeout,
+                    keepalive_idle_timeout_ms: maybe_keepalive_idle_timeout,
                     request_buffer_size: Some(request_buffer_size),
 
                     beforeunload_wall_clock_pct: maybe_beforeunload_wall_clock_pct,
@@ -209,36 +377,40 @@ fn main() -> Result<ExitCode, anyhow::Error> {
                 };
 
                 let maybe_received_signum = start_server(
-                    ip.as_str(),
+                    ip.to_string().as_str(),
                     port,
                     maybe_tls,
                     main_service_path,
                     event_service_manager_path,
                     get_decorator_option(sub_matches),
-                    Some(WorkerPoolPolicy::new(
-                        maybe_supervisor_policy,
-                        if let Some(true) = maybe_supervisor_policy
-                            .as_ref()
-                            .map(SupervisorPolicy::is_oneshot)
-                        {
-                            if let Some(parallelism) = maybe_max_parallelism {
-                                if parallelism == 0 || parallelism > 1 {
-                                    warn!(
-                                        "{}",
-                                        concat!(
-                                            "if `oneshot` policy is enabled, the maximum ",
-                                            "parallelism is fixed to `1` as forcibly"
-                                        )
-                                    );
+                    Some(
+                        WorkerPoolPolicy::new(
+                            maybe_supervisor_policy,
+                            if let Some(true) = maybe_supervisor_policy
+                                .as_ref()
+                                .map(SupervisorPolicy::is_oneshot)
+                            {
+                                if let Some(parallelism) = maybe_max_parallelism {
+                                    if parallelism == 0 || parallelism > 1 {
+                                        warn!(
+                                            "{}",
+                                            concat!(
+                                                "if `oneshot` policy is enabled, the maximum ",
+                                                "parallelism is fixed to `1` as forcibly"
+                                            )
+                                        );
+                                    }
                                 }
-                            }
 
-                            Some(1)
-                        } else {
-                            maybe_max_parallelism
-                        },
-                        flags,
-                    )),
+                                Some(1)
+                            } else {
+                                maybe_max_parallelism
+                            },
+                            flags.clone(),
+                        )
+                        .with_routing(routing)
+                        .with_circuit_breaker(maybe_circuit_breaker),
+                    ),
                     import_map_path,
                     flags,
                     None,
@@ -26,6 +26,7 @@ pub enum ShutdownReason {
     Memory,
     EarlyDrop,
     TerminationRequested,
+    Recycled,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -46,6 +47,22 @@ pub struct EventLoopCompletedEvent {
     pub cpu_time_used: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CircuitStateChangedEvent {
+    pub state: CircuitState,
+    /// Failures of the service in a row, i.e. boot failures and deaths from
+    /// uncaught exceptions.
+    pub failures: usize,
+    pub retry_after_ms: u64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LogEvent {
     pub msg: String,
@@ -68,6 +85,7 @@ pub enum WorkerEvents {
     Shutdown(ShutdownEvent),
     EventLoopCompleted(EventLoopCompletedEvent),
     Log(LogEvent),
+    CircuitStateChanged(CircuitStateChangedEvent),
 }
 
 impl WorkerEvents {
@@ -89,6 +107,7 @@ impl WorkerEvents {
 pub struct EventMetadata {
     pub service_path: Option<String>,
     pub execution_id: Option<Uuid>,
+    pub request_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -0,0 +1,136 @@
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures_util::{Stream, StreamExt};
+use http_v02::{header, Request, Response, StatusCode};
+use hyper_v014::body::{Body, HttpBody};
+use tokio_util::sync::CancellationToken;
+
+use crate::utils::emit_error_response;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RequestBodyTooLarge {
+    pub limit: u64,
+}
+
+impl fmt::Display for RequestBodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the request body exceeds the limit of {} bytes",
+            self.limit
+        )
+    }
+}
+
+impl std::error::Error for RequestBodyTooLarge {}
+
+impl RequestBodyTooLarge {
+    pub fn to_response(&self, request_id: Option<&str>, connection_close: bool) -> Response<Body> {
+        emit_error_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "RequestBodyTooLarge",
+            &self.to_string(),
+            request_id,
+            connection_close,
+        )
+    }
+}
+
+/// Tells when the body of a request passed to [`limit_request_body`] has gone
+/// over the limit.
+#[derive(Debug)]
+pub struct BodyLimit {
+    limit: u64,
+    exceeded: CancellationToken,
+}
+
+impl BodyLimit {
+    /// Resolves once more bytes than allowed have been read from the body. It
+    /// never resolves if the body stays within the limit.
+    pub async fn exceeded(self) -> RequestBodyTooLarge {
+        self.exceeded.cancelled().await;
+        RequestBodyTooLarge { limit: self.limit }
+    }
+}
+
+/// Caps the size of the request body at `limit` bytes, without buffering it.
+///
+/// A request that declares a larger `Content-Length` is refused right away.
+/// Otherwise, the body is replaced with one that fails as soon as the bytes
+/// going through it cross the limit.
+pub fn limit_request_body(
+    req: &mut Request<Body>,
+    limit: u64,
+) -> Result<BodyLimit, RequestBodyTooLarge> {
+    let maybe_content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|it| it.to_str().ok())
+        .and_then(|it| it.parse::<u64>().ok());
+
+    if maybe_content_length.is_some_and(|it| it > limit) {
+        return Err(RequestBodyTooLarge { limit });
+    }
+
+    let exceeded = CancellationToken::new();
+
+    // NOTE: The body is wrapped even if it declares a length within the limit,
+    // since only a body read off the wire is held to its `Content-Length` by
+    // hyper. One streamed from a worker is not.
+    if !req.body().is_end_stream() {
+        let body = std::mem::take(req.body_mut());
+
+        *req.body_mut() = Body::wrap_stream(LimitedBody {
+            inner: body,
+            remaining: limit,
+            limit,
+            exceeded: exceeded.clone(),
+        });
+    }
+
+    Ok(BodyLimit { limit, exceeded })
+}
+
+struct LimitedBody {
+    inner: Body,
+    remaining: u64,
+    limit: u64,
+    exceeded: CancellationToken,
+}
+
+impl Stream for LimitedBody {
+    type Item = Result<Bytes, BoxError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.exceeded.is_cancelled() {
+            return Poll::Ready(None);
+        }
+
+        match self.inner.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let len = chunk.len() as u64;
+
+                if len > self.remaining {
+                    self.exceeded.cancel();
+
+                    return Poll::Ready(Some(Err(Box::new(RequestBodyTooLarge {
+                        limit: self.limit,
+                    }))));
+                }
+
+                self.remaining -= len;
+
+                Poll::Ready(Some(Ok(chunk)))
+            }
+
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
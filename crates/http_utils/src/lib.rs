@@ -1,2 +1,3 @@
+pub mod body_limit;
 pub mod io;
 pub mod utils;
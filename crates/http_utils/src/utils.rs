@@ -1,5 +1,6 @@
 use http_v02::{header, response, HeaderMap, HeaderValue, Response, StatusCode};
 use hyper_v014::body::Body;
+use serde_json::json;
 
 pub fn get_upgrade_type(headers: &HeaderMap) -> Option<String> {
     let connection_header_exists = headers
@@ -43,3 +44,29 @@ pub fn emit_status_code(
     }
     .unwrap()
 }
+
+/// Builds a response for an error produced by the runtime itself, rather than
+/// by a worker. The body is a JSON object describing the error, so it can be
+/// correlated with the logs through the request ID.
+pub fn emit_error_response(
+    status: StatusCode,
+    class: &str,
+    msg: &str,
+    request_id: Option<&str>,
+    connection_close: bool,
+) -> Response<Body> {
+    let body = json!({
+        "msg": msg,
+        "class": class,
+        "requestId": request_id,
+    });
+
+    let mut res = emit_status_code(status, Some(Body::from(body.to_string())), connection_close);
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    res
+}
@@ -18,6 +18,20 @@ const buildErrorClass = (name) => {
     return classErr;
 }
 
+// errors that come with a hint of when the request may be tried again.
+const buildRetryableErrorClass = (name, base = Error) => {
+    const classErr = class extends base {
+        constructor(msg, retryAfterMs = null) {
+            super(msg);
+            this.name = name;
+            this.retryAfterMs = retryAfterMs;
+        }
+    }
+    classErr.getName = () => name;
+    knownErrors[name] = classErr;
+    return classErr;
+}
+
 const buildDomErrorClass = (name) => class extends DOMException {
     constructor(msg) {
         super(msg, name);
@@ -27,6 +41,8 @@ const buildDomErrorClass = (name) => class extends DOMException {
 const InvalidWorkerResponse = buildErrorClass("InvalidWorkerResponse");
 const InvalidWorkerCreation = buildErrorClass("InvalidWorkerCreation");
 const WorkerRequestCancelled = buildErrorClass("WorkerRequestCancelled");
+const WorkerQueueFull = buildRetryableErrorClass("WorkerQueueFull");
+const WorkerCircuitOpen = buildRetryableErrorClass("WorkerCircuitOpen", InvalidWorkerCreation);
 const NotFound = buildErrorClass("NotFound");
 const PermissionDenied = buildErrorClass("PermissionDenied");
 const ConnectionRefused = buildErrorClass("ConnectionRefused");
@@ -57,6 +73,8 @@ function registerErrors() {
     core.registerErrorClass("InvalidWorkerResponse", InvalidWorkerResponse);
     core.registerErrorClass("InvalidWorkerCreation", InvalidWorkerCreation);
     core.registerErrorClass("WorkerRequestCancelled", WorkerRequestCancelled);
+    core.registerErrorClass("WorkerQueueFull", WorkerQueueFull);
+    core.registerErrorClass("WorkerCircuitOpen", WorkerCircuitOpen);
     core.registerErrorClass("NotFound", NotFound);
     core.registerErrorClass("PermissionDenied", PermissionDenied);
     core.registerErrorClass("ConnectionRefused", ConnectionRefused);
@@ -65,7 +65,9 @@ function serveHttp(conn) {
 
 		nextRequest.request[kSupabaseTag] = {
 			watcherRid,
-			streamRid: nextRequest.streamRid
+			streamRid: nextRequest.streamRid,
+			localAddr: conn.localAddr,
+			remoteAddr: conn.remoteAddr,
 		};
 
 		return nextRequest;
@@ -160,13 +162,9 @@ async function respond(requestEvent, httpConn, options) {
 	/** @type {Response} */
 	let response;
 	try {
-		response = await options["handler"](requestEvent.request, {
-			remoteAddr: {
-				port: options.port,
-				hostname: options.hostname,
-				transport: options.transport
-			}
-		});
+		const { remoteAddr } = getSupabaseTag(requestEvent.request);
+
+		response = await options["handler"](requestEvent.request, { remoteAddr });
 
 	} catch (error) {
 		if (options["onError"] !== void 0) {
@@ -9,6 +9,7 @@ use deno_core::v8;
 use deno_core::OpState;
 use deno_core::{op2, JsRuntime};
 use enum_as_inner::EnumAsInner;
+use futures::future::BoxFuture;
 use futures::task::AtomicWaker;
 use futures::FutureExt;
 use log::error;
@@ -49,10 +50,20 @@ pub struct SharedMetricSource {
     retired_user_workers: Arc<AtomicUsize>,
     received_requests: Arc<AtomicUsize>,
     handled_requests: Arc<AtomicUsize>,
+    rate_limited_requests: Arc<AtomicUsize>,
+    dropped_access_log_records: Arc<AtomicUsize>,
     active_io: Arc<AtomicUsize>,
 }
 
 impl SharedMetricSource {
+    pub fn active_user_workers(&self) -> usize {
+        self.active_user_workers.load(Ordering::Relaxed)
+    }
+
+    pub fn retired_user_workers(&self) -> usize {
+        self.retired_user_workers.load(Ordering::Relaxed)
+    }
+
     pub fn active_io(&self) -> usize {
         self.active_io.load(Ordering::Relaxed)
     }
@@ -65,6 +76,14 @@ impl SharedMetricSource {
         self.handled_requests.load(Ordering::Relaxed)
     }
 
+    pub fn rate_limited_requests(&self) -> usize {
+        self.rate_limited_requests.load(Ordering::Relaxed)
+    }
+
+    pub fn dropped_access_log_records(&self) -> usize {
+        self.dropped_access_log_records.load(Ordering::Relaxed)
+    }
+
     pub fn incl_active_user_workers(&self) {
         self.active_user_workers.fetch_add(1, Ordering::Relaxed);
     }
@@ -85,6 +104,15 @@ impl SharedMetricSource {
         self.handled_requests.fetch_add(1, Ordering::Relaxed);
     }
 
+    pub fn incl_rate_limited_requests(&self) {
+        self.rate_limited_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn incl_dropped_access_log_records(&self) {
+        self.dropped_access_log_records
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
     pub fn incl_active_io(&self) {
         self.active_io.fetch_add(1, Ordering::Relaxed);
     }
@@ -98,6 +126,8 @@ impl SharedMetricSource {
         self.retired_user_workers.store(0, Ordering::Relaxed);
         self.received_requests.store(0, Ordering::Relaxed);
         self.handled_requests.store(0, Ordering::Relaxed);
+        self.rate_limited_requests.store(0, Ordering::Relaxed);
+        self.dropped_access_log_records.store(0, Ordering::Relaxed);
         self.active_io.store(0, Ordering::Relaxed);
     }
 }
@@ -132,29 +162,13 @@ impl WorkerMetricSource {
 
         Self { handle, waker }
     }
-}
-
-#[derive(Debug, Clone)]
-pub struct RuntimeMetricSource {
-    pub main: WorkerMetricSource,
-    pub event: Option<WorkerMetricSource>,
-    pub shared: SharedMetricSource,
-}
-
-impl RuntimeMetricSource {
-    pub fn new(
-        main: WorkerMetricSource,
-        maybe_event: Option<WorkerMetricSource>,
-        maybe_shared: Option<SharedMetricSource>,
-    ) -> Self {
-        Self {
-            main,
-            event: maybe_event,
-            shared: maybe_shared.unwrap_or_default(),
-        }
-    }
 
-    async fn get_heap_statistics(&mut self) -> RuntimeHeapStatistics {
+    /// Requests the heap statistics of the isolate.
+    ///
+    /// The statistics are collected by interrupting the isolate, so the
+    /// returned future doesn't resolve while the isolate is blocked outside of
+    /// JavaScript. Callers that must not stall should apply a timeout.
+    pub fn heap_statistics(&self) -> BoxFuture<'static, Option<WorkerHeapStatistics>> {
         #[repr(C)]
         struct InterruptData {
             heap_tx: oneshot::Sender<WorkerHeapStatistics>,
@@ -183,37 +197,54 @@ impl RuntimeMetricSource {
             }
         }
 
-        let request_heap_statistics_fn = |arg: Option<&mut WorkerMetricSource>| {
-            let Some(source) = arg else {
-                return async { None::<WorkerHeapStatistics> }.boxed();
-            };
+        let (tx, rx) = oneshot::channel::<WorkerHeapStatistics>();
+        let data_ptr_mut = Box::into_raw(Box::new(InterruptData { heap_tx: tx }));
 
-            let (tx, rx) = oneshot::channel::<WorkerHeapStatistics>();
-            let data_ptr_mut = Box::into_raw(Box::new(InterruptData { heap_tx: tx }));
+        if !self
+            .handle
+            .request_interrupt(interrupt_fn, data_ptr_mut as *mut std::ffi::c_void)
+        {
+            drop(unsafe { Box::from_raw(data_ptr_mut) });
+            return async { None }.boxed();
+        }
 
-            if !source
-                .handle
-                .request_interrupt(interrupt_fn, data_ptr_mut as *mut std::ffi::c_void)
-            {
-                drop(unsafe { Box::from_raw(data_ptr_mut) });
-                return async { None }.boxed();
-            }
+        let waker = self.waker.clone();
 
-            let waker = source.waker.clone();
+        async move {
+            waker.wake();
+            rx.await.ok()
+        }
+        .boxed()
+    }
+}
 
-            async move {
-                waker.wake();
-                rx.await.ok()
-            }
-            .boxed()
-        };
+#[derive(Debug, Clone)]
+pub struct RuntimeMetricSource {
+    pub main: WorkerMetricSource,
+    pub event: Option<WorkerMetricSource>,
+    pub shared: SharedMetricSource,
+}
 
-        RuntimeHeapStatistics {
-            main_worker_heap_stats: request_heap_statistics_fn(Some(&mut self.main))
-                .await
-                .unwrap_or_default(),
+impl RuntimeMetricSource {
+    pub fn new(
+        main: WorkerMetricSource,
+        maybe_event: Option<WorkerMetricSource>,
+        maybe_shared: Option<SharedMetricSource>,
+    ) -> Self {
+        Self {
+            main,
+            event: maybe_event,
+            shared: maybe_shared.unwrap_or_default(),
+        }
+    }
 
-            event_worker_heap_stats: request_heap_statistics_fn(self.event.as_mut()).await,
+    async fn get_heap_statistics(&self) -> RuntimeHeapStatistics {
+        RuntimeHeapStatistics {
+            main_worker_heap_stats: self.main.heap_statistics().await.unwrap_or_default(),
+            event_worker_heap_stats: match self.event.as_ref() {
+                Some(source) => source.heap_statistics().await,
+                None => None,
+            },
         }
     }
 }
@@ -232,6 +263,7 @@ struct RuntimeSharedStatistics {
     retired_user_workers_count: usize,
     received_requests_count: usize,
     handled_requests_count: usize,
+    rate_limited_requests_count: usize,
 }
 
 impl RuntimeSharedStatistics {
@@ -241,6 +273,7 @@ impl RuntimeSharedStatistics {
             retired_user_workers_count: src.retired_user_workers.load(Ordering::Relaxed),
             received_requests_count: src.received_requests.load(Ordering::Relaxed),
             handled_requests_count: src.handled_requests.load(Ordering::Relaxed),
+            rate_limited_requests_count: src.rate_limited_requests.load(Ordering::Relaxed),
         }
     }
 }
@@ -274,7 +307,7 @@ fn op_console_size(_state: &mut OpState, #[buffer] _result: &mut [u32]) -> Resul
 #[serde]
 async fn op_runtime_metrics(state: Rc<RefCell<OpState>>) -> Result<RuntimeMetrics, AnyError> {
     let mut runtime_metrics = RuntimeMetrics::default();
-    let mut runtime_metric_src = {
+    let runtime_metric_src = {
         let state = state.borrow();
         state.borrow::<RuntimeMetricSource>().clone()
     };
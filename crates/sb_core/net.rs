@@ -9,9 +9,11 @@ use deno_core::OpState;
 use deno_core::Resource;
 use deno_core::ResourceId;
 use deno_net::ops::IpAddr;
+use event_worker::events::EventMetadata;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::rc::Rc;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
@@ -21,6 +23,22 @@ use tokio_util::sync::CancellationToken;
 use tracing::span;
 use tracing::Level;
 
+/// Socket addresses of the connection that a request was received on.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnAddrs {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+}
+
+/// A stream that a worker serves a single request over, along with what the
+/// worker can't learn from the stream itself.
+pub struct DuplexStreamEntry {
+    pub stream: io::DuplexStream,
+    pub conn_token: Option<CancellationToken>,
+    pub conn_addrs: Option<ConnAddrs>,
+    pub request_id: Option<String>,
+}
+
 pub struct TokioDuplexResource {
     id: usize,
     rw: AsyncRefCell<io::DuplexStream>,
@@ -102,9 +120,7 @@ pub async fn op_net_accept(
         let mut op_state = state.borrow_mut();
 
         (
-            op_state
-                .try_take::<mpsc::UnboundedReceiver<(io::DuplexStream, Option<CancellationToken>)>>(
-                ),
+            op_state.try_take::<mpsc::UnboundedReceiver<DuplexStreamEntry>>(),
             op_state
                 .try_borrow::<DenoRuntimeDropToken>()
                 .cloned()
@@ -121,13 +137,17 @@ pub async fn op_net_accept(
         let state = state.clone();
         move |value| {
             let mut op_state = state.borrow_mut();
-            op_state.put::<mpsc::UnboundedReceiver<(io::DuplexStream, Option<CancellationToken>)>>(
-                value,
-            );
+            op_state.put::<mpsc::UnboundedReceiver<DuplexStreamEntry>>(value);
         }
     });
 
-    let Some((stream, conn_token)) = rx.recv().await else {
+    let Some(DuplexStreamEntry {
+        stream,
+        conn_token,
+        conn_addrs,
+        request_id,
+    }) = rx.recv().await
+    else {
         return Err(bad_resource("duplex stream channel is closed"));
     };
 
@@ -141,7 +161,7 @@ pub async fn op_net_accept(
     let mut op_state = state.borrow_mut();
     let rid = op_state.resource_table.add(resource);
 
-    if let Some(token) = conn_token {
+    if let Some(token) = conn_token.clone() {
         // connection token should only last as long as the worker is alive.
         drop(base_rt::SUPERVISOR_RT.spawn({
             let token = token.clone();
@@ -164,17 +184,50 @@ pub async fn op_net_accept(
             .insert(id, token);
     }
 
-    Ok((
-        rid,
-        IpAddr {
-            hostname: "0.0.0.0".to_string(),
-            port: 9999, // FIXME
-        },
-        IpAddr {
-            hostname: "0.0.0.0".to_string(),
-            port: 8888, // FIXME
-        },
-    ))
+    // NOTE: The metadata is shared by the whole worker. The request ID is left
+    // out of the entry if the worker is serving other requests at the same
+    // time, so that the events are not put down to the wrong request.
+    if let Some(metadata) = op_state.try_borrow_mut::<EventMetadata>() {
+        metadata.request_id.clone_from(&request_id);
+    }
+
+    // the events emitted once the request is over (e.g. by timers) must not
+    // be put down to it either.
+    if let Some((request_id, token)) = request_id.zip(conn_token) {
+        drop(deno_core::unsync::spawn({
+            let state = state.clone();
+            async move {
+                token.cancelled().await;
+
+                let mut op_state = state.borrow_mut();
+
+                if let Some(metadata) = op_state.try_borrow_mut::<EventMetadata>() {
+                    if metadata.request_id.as_ref() == Some(&request_id) {
+                        metadata.request_id = None;
+                    }
+                }
+            }
+        }));
+    }
+
+    // NOTE: a connection that didn't come in through a TCP listener (e.g. a
+    // Unix domain socket) has no addresses to report, so we fall back to the
+    // address the worker pretends to listen on.
+    let (local_addr, remote_addr) = match conn_addrs {
+        Some(ConnAddrs { local, remote }) => (IpAddr::from(local), IpAddr::from(remote)),
+        None => (
+            IpAddr {
+                hostname: "0.0.0.0".to_string(),
+                port: 9999,
+            },
+            IpAddr {
+                hostname: "0.0.0.0".to_string(),
+                port: 0,
+            },
+        ),
+    };
+
+    Ok((rid, local_addr, remote_addr))
 }
 
 // TODO: This should be a global ext
@@ -22,7 +22,7 @@ use tracing::{instrument, trace};
 
 use super::TryNormalizePath;
 
-#[derive(Deserialize, Serialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct TmpFsConfig {
     base: Option<PathBuf>,
@@ -4,12 +4,13 @@ use deno_core::FastString;
 use enum_as_inner::EnumAsInner;
 use event_worker::events::{UncaughtExceptionEvent, WorkerEventWithMetadata};
 use hyper_v014::{Body, Request, Response};
+use sb_core::net::ConnAddrs;
 use sb_core::util::sync::AtomicFlag;
 use sb_core::{MetricSource, SharedMetricSource};
 use sb_fs::s3_fs::S3FsConfig;
 use sb_fs::tmp_fs::TmpFsConfig;
 use std::path::PathBuf;
-use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{collections::HashMap, sync::Arc};
 use tokio::sync::mpsc::unbounded_channel;
 use tokio::sync::{mpsc, oneshot, Mutex, Notify, OwnedSemaphorePermit};
@@ -17,6 +18,7 @@ use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use sb_graph::{DecoratorType, EszipPayloadKind};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
 pub enum WorkerExitStatus {
@@ -46,6 +48,25 @@ impl WorkerExit {
     pub async fn set(&self, exit_status: WorkerExitStatus) {
         *self.0.lock().await = exit_status;
     }
+
+    /// Whether the worker has exited with an uncaught exception. It can't
+    /// tell while the status is being set.
+    pub fn is_uncaught_exception(&self) -> bool {
+        self.0
+            .try_lock()
+            .is_ok_and(|it| matches!(*it, WorkerExitStatus::WithUncaughtException(_)))
+    }
+}
+
+/// Requests of a higher priority are let through first once a worker of the
+/// service frees up, and are turned away last once its queue fills up.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueuePriority {
+    High,
+    #[default]
+    Normal,
+    Low,
 }
 
 #[derive(Debug, Clone)]
@@ -65,6 +86,25 @@ pub struct UserWorkerRuntimeOpts {
     pub cpu_time_soft_limit_ms: u64,
     pub cpu_time_hard_limit_ms: u64,
 
+    /// Requests with a larger body are refused with 413 before reaching the
+    /// worker. The server-wide limit still applies on top of this.
+    pub max_request_body_bytes: Option<u64>,
+
+    /// Number of workers of the service that the pool keeps booted, even when
+    /// no request is waiting for them. Workers loaded from an eszip or from
+    /// module code can't be re-created by the pool, and aren't pre-warmed.
+    pub min_instances: Option<usize>,
+
+    /// Where the request goes in the queue of the service if all of its
+    /// workers are busy.
+    pub queue_priority: QueuePriority,
+
+    /// The worker is retired once it has served this many requests, or once
+    /// it is this old, and shuts down after the requests in flight. Only
+    /// applies under the `per_worker` policy.
+    pub max_requests_per_worker: Option<u64>,
+    pub max_worker_age_ms: Option<u64>,
+
     pub beforeunload_wall_clock_pct: Option<u8>,
     pub beforeunload_cpu_pct: Option<u8>,
     pub beforeunload_memory_pct: Option<u8>,
@@ -89,6 +129,11 @@ impl Default for UserWorkerRuntimeOpts {
             worker_timeout_ms: env!("SUPABASE_RESOURCE_LIMIT_TIMEOUT_MS").parse().unwrap(),
             cpu_time_soft_limit_ms: env!("SUPABASE_RESOURCE_LIMIT_CPU_SOFT_MS").parse().unwrap(),
             cpu_time_hard_limit_ms: env!("SUPABASE_RESOURCE_LIMIT_CPU_HARD_MS").parse().unwrap(),
+            max_request_body_bytes: None,
+            min_instances: None,
+            queue_priority: QueuePriority::default(),
+            max_requests_per_worker: None,
+            max_worker_age_ms: None,
             beforeunload_wall_clock_pct: None,
             beforeunload_cpu_pct: None,
             beforeunload_memory_pct: None,
@@ -117,6 +162,7 @@ pub struct UserWorkerProfile {
         mpsc::UnboundedSender<()>,
     ),
     pub service_path: String,
+    pub max_request_body_bytes: Option<u64>,
     pub permit: Option<Arc<OwnedSemaphorePermit>>,
     pub cancel: CancellationToken,
     pub status: TimingStatus,
@@ -179,9 +225,20 @@ impl From<&WorkerRuntimeOpts> for WorkerKind {
 #[derive(Debug, Clone, Default)]
 pub struct TimingStatus {
     pub demand: Arc<AtomicUsize>,
+    /// Requests the worker is done with, out of the ones in `demand`.
+    pub served: Arc<AtomicUsize>,
     pub is_retired: Arc<AtomicFlag>,
 }
 
+impl TimingStatus {
+    /// Count of requests the worker has been given but is not done with yet.
+    pub fn outstanding(&self) -> usize {
+        self.demand
+            .load(Ordering::Acquire)
+            .saturating_sub(self.served.load(Ordering::Acquire))
+    }
+}
+
 #[derive(Debug)]
 pub struct Timing {
     pub status: TimingStatus,
@@ -238,6 +295,24 @@ pub enum UserWorkerMsgs {
     ),
     Idle(Uuid),
     Shutdown(Uuid),
+    /// A worker of the service at the given path failed to boot.
+    BootFailed(String),
+    ServiceStats(oneshot::Sender<HashMap<String, UserWorkerServiceStats>>),
+}
+
+/// Number of live user workers of a service, grouped by whether they still
+/// accept new requests.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UserWorkerServiceStats {
+    pub active: usize,
+    pub retired: usize,
+    /// Requests that are waiting for a worker of the service.
+    pub queued: usize,
+    /// Requests that have left the queue, and the time they spent in it.
+    pub dequeued: usize,
+    pub queue_wait_ms: u64,
+    /// Requests that were turned away because the queue was full.
+    pub shed: usize,
 }
 
 pub type SendRequestResult = (Response<Body>, mpsc::UnboundedSender<()>);
@@ -252,4 +327,5 @@ pub struct WorkerRequestMsg {
     pub req: Request<Body>,
     pub res_tx: oneshot::Sender<Result<Response<Body>, hyper_v014::Error>>,
     pub conn_token: Option<CancellationToken>,
+    pub conn_addrs: Option<ConnAddrs>,
 }
@@ -4,4 +4,8 @@ use thiserror::Error;
 pub enum WorkerError {
     #[error("request has been cancelled by supervisor")]
     RequestCancelledBySupervisor,
+    #[error("too many requests are waiting for a worker of the service")]
+    RequestQueueFull { retry_after_ms: u64 },
+    #[error("the service keeps failing, retry in {retry_after_ms}ms")]
+    CircuitOpen { retry_after_ms: u64 },
 }
@@ -2,8 +2,8 @@ pub mod context;
 pub mod errors;
 
 use crate::context::{
-    CreateUserWorkerResult, UserWorkerMsgs, UserWorkerRuntimeOpts, WorkerContextInitOpts,
-    WorkerRuntimeOpts,
+    CreateUserWorkerResult, QueuePriority, UserWorkerMsgs, UserWorkerRuntimeOpts,
+    WorkerContextInitOpts, WorkerRuntimeOpts,
 };
 use anyhow::Error;
 use context::SendRequestResult;
@@ -26,11 +26,13 @@ use hyper_v014::{Body, Method, Request};
 use log::error;
 use once_cell::sync::Lazy;
 use sb_core::conn_sync::ConnWatcher;
+use sb_core::net::ConnAddrs;
 use sb_fs::s3_fs::S3FsConfig;
 use sb_fs::tmp_fs::TmpFsConfig;
 use sb_graph::{DecoratorType, EszipPayloadKind};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::pin::Pin;
 use std::rc::Rc;
@@ -83,6 +85,11 @@ pub struct UserWorkerCreateOptions {
     worker_timeout_ms: Option<u64>,
     cpu_time_soft_limit_ms: Option<u64>,
     cpu_time_hard_limit_ms: Option<u64>,
+    max_request_body_bytes: Option<u64>,
+    min_instances: Option<usize>,
+    queue_priority: Option<QueuePriority>,
+    max_requests_per_worker: Option<u64>,
+    max_worker_age_ms: Option<u64>,
 
     decorator_type: Option<DecoratorType>,
     jsx_import_source_config: Option<JsxImportBaseConfig>,
@@ -93,12 +100,31 @@ pub struct UserWorkerCreateOptions {
     context: Option<JsonMap>,
 }
 
+/// What `op_user_worker_create` resolves with.
+///
+/// A worker that can't be created for the time being is reported as a value
+/// rather than as an error, so that the main worker is also told when to try
+/// again.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum CreateUserWorkerOutcome {
+    Created {
+        key: String,
+    },
+    Refused {
+        class: &'static str,
+        msg: String,
+        #[serde(rename = "retryAfterMs")]
+        retry_after_ms: u64,
+    },
+}
+
 #[op2(async)]
-#[string]
+#[serde]
 pub async fn op_user_worker_create(
     state: Rc<RefCell<OpState>>,
     #[serde] opts: UserWorkerCreateOptions,
-) -> Result<String, AnyError> {
+) -> Result<CreateUserWorkerOutcome, AnyError> {
     let result_rx = {
         let op_state = state.borrow();
         let tx = op_state.borrow::<mpsc::UnboundedSender<UserWorkerMsgs>>();
@@ -123,6 +149,11 @@ pub async fn op_user_worker_create(
             worker_timeout_ms,
             cpu_time_soft_limit_ms,
             cpu_time_hard_limit_ms,
+            max_request_body_bytes,
+            min_instances,
+            queue_priority,
+            max_requests_per_worker,
+            max_worker_age_ms,
 
             decorator_type: maybe_decorator,
             jsx_import_source_config,
@@ -153,6 +184,12 @@ pub async fn op_user_worker_create(
                     cpu_time_hard_limit_ms: cpu_time_hard_limit_ms
                         .unwrap_or(DEFAULT.cpu_time_hard_limit_ms),
 
+                    max_request_body_bytes,
+                    min_instances,
+                    queue_priority: queue_priority.unwrap_or_default(),
+                    max_requests_per_worker,
+                    max_worker_age_ms,
+
                     force_create,
                     net_access_disabled,
                     allow_net,
@@ -208,8 +245,28 @@ pub async fn op_user_worker_create(
             ),
         )),
 
-        Ok(Err(err)) => Err(custom_error("InvalidWorkerCreation", format!("{err:#}"))),
-        Ok(Ok(v)) => Ok(v.key.to_string()),
+        Ok(Err(err)) => match err.downcast_ref() {
+            Some(err @ WorkerError::RequestQueueFull { retry_after_ms }) => {
+                Ok(CreateUserWorkerOutcome::Refused {
+                    class: "WorkerQueueFull",
+                    msg: err.to_string(),
+                    retry_after_ms: *retry_after_ms,
+                })
+            }
+
+            Some(err @ WorkerError::CircuitOpen { retry_after_ms }) => {
+                Ok(CreateUserWorkerOutcome::Refused {
+                    class: "WorkerCircuitOpen",
+                    msg: err.to_string(),
+                    retry_after_ms: *retry_after_ms,
+                })
+            }
+
+            _ => Err(custom_error("InvalidWorkerCreation", format!("{err:#}"))),
+        },
+        Ok(Ok(v)) => Ok(CreateUserWorkerOutcome::Created {
+            key: v.key.to_string(),
+        }),
     }
 }
 
@@ -220,6 +277,20 @@ pub struct UserWorkerRequest {
     url: String,
     headers: Vec<(String, String)>,
     has_body: bool,
+    local_addr: Option<UserWorkerRequestAddr>,
+    remote_addr: Option<UserWorkerRequestAddr>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UserWorkerRequestAddr {
+    hostname: String,
+    port: u16,
+}
+
+impl UserWorkerRequestAddr {
+    fn to_socket_addr(&self) -> Option<SocketAddr> {
+        Some(SocketAddr::new(self.hostname.parse().ok()?, self.port))
+    }
 }
 
 #[derive(Serialize)]
@@ -414,6 +485,21 @@ pub fn op_user_worker_fetch_build(
         }
     }
 
+    // forward the addresses of the connection that the main worker received
+    // the request on, so the user worker sees the actual client.
+    let local_addr = req
+        .local_addr
+        .as_ref()
+        .and_then(UserWorkerRequestAddr::to_socket_addr);
+    let remote_addr = req
+        .remote_addr
+        .as_ref()
+        .and_then(UserWorkerRequestAddr::to_socket_addr);
+
+    if let Some((local, remote)) = local_addr.zip(remote_addr) {
+        builder = builder.extension(ConnAddrs { local, remote });
+    }
+
     let req = builder.body(body)?;
     let request_rid = state.resource_table.add(UserWorkerRequestResource(req));
 
@@ -525,7 +611,7 @@ pub async fn op_user_worker_fetch_send(
                     return Err(custom_error("WorkerRequestCancelled", err.to_string()));
                 }
 
-                None => {
+                _ => {
                     return Err(custom_error("InvalidWorkerResponse", err.to_string()));
                 }
             }
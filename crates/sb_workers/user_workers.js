@@ -1,6 +1,7 @@
 import { primordials, core } from "ext:core/mod.js";
 import { readableStreamForRid, writableStreamForRid } from "ext:deno_web/06_streams.js";
 import { getSupabaseTag } from "ext:sb_core_main_js/js/http.js";
+import { errors } from "ext:sb_core_main_js/js/errors.js";
 
 const ops = core.ops;
 
@@ -48,6 +49,8 @@ class UserWorker {
 			url,
 			hasBody,
 			headers: headersArray,
+			localAddr: tag?.localAddr,
+			remoteAddr: tag?.remoteAddr,
 		};
 
 		const { requestRid, requestBodyRid } = await ops.op_user_worker_fetch_build(
@@ -136,9 +139,13 @@ class UserWorker {
 			throw new TypeError("service path must be defined");
 		}
 
-		const key = await op_user_worker_create(readyOptions);
+		const result = await op_user_worker_create(readyOptions);
 
-		return new UserWorker(key);
+		if (result.kind === "refused") {
+			throw new errors[result.class](result.msg, result.retryAfterMs);
+		}
+
+		return new UserWorker(result.key);
 	}
 }
 
@@ -39,6 +39,11 @@ interface UserWorkerCreateOptions {
     workerTimeoutMs?: number | null;
     cpuTimeSoftLimitMs?: number | null;
     cpuTimeHardLimitMs?: number | null;
+    maxRequestBodyBytes?: number | null;
+    minInstances?: number | null;
+    queuePriority?: "high" | "normal" | "low" | null;
+    maxRequestsPerWorker?: number | null;
+    maxWorkerAgeMs?: number | null;
 
     decoratorType?: DecoratorType | null;
     jsxImportSourceConfig?: JsxImportBaseConfig | null;
@@ -100,6 +105,13 @@ declare namespace EdgeRuntime {
 
 declare namespace Deno {
     export namespace errors {
+        class InvalidWorkerCreation extends Error { }
         class WorkerRequestCancelled extends Error { }
+        class WorkerQueueFull extends Error {
+            readonly retryAfterMs: number | null;
+        }
+        class WorkerCircuitOpen extends InvalidWorkerCreation {
+            readonly retryAfterMs: number | null;
+        }
     }
 }
\ No newline at end of file